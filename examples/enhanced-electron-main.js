@@ -134,8 +134,8 @@ ipcMain.handle('cap:createRecordingSession', async (event, config) => {
                 video: {
                     codec: "H264",
                     bitrate: config.encoding?.video?.bitrate ?? 2000000,
-                    frame_rate: [30, 1],
-                    resolution: config.encoding?.video?.resolution ?? [1920, 1080],
+                    frame_rate: { numerator: 30, denominator: 1 },
+                    resolution: config.encoding?.video?.resolution ?? { width: 1920, height: 1080 },
                     pixel_format: "YUV420P",
                     hardware_acceleration: true
                 },
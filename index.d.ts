@@ -52,7 +52,7 @@ export interface OutputFormat {
   audio: AudioFormat
   /** Video output format (if screen capture is enabled) */
   video: VideoFormat
-  /** Output directory for segments */
+  /** Output directory for segments and playlists, laid out like the S3 keys */
   outputDir?: string
   /** Enable real-time streaming */
   realTime: boolean
@@ -77,8 +77,433 @@ export const enum VideoFormat {
   /** Raw frames */
   Raw = 2
 }
-/** Initialize the library and check platform capabilities */
+/** Frame dimensions in pixels */
+export interface Resolution {
+  /** Width in pixels */
+  width: number
+  /** Height in pixels */
+  height: number
+}
+/** Platform capabilities information */
+export interface PlatformCapabilities {
+  /** Operating system */
+  platform: Platform
+  /** Audio capture capabilities */
+  audio: AudioCapabilities
+  /** Screen capture capabilities */
+  screen: ScreenCapabilities
+  /** System version */
+  systemVersion: string
+  /** Available permissions */
+  permissions: PermissionStatus
+  /** ScreenCaptureKit availability (macOS only) */
+  screencapturekit: boolean
+}
+/** Platform types */
+export const enum Platform {
+  MacOS = 0,
+  Windows = 1,
+  Linux = 2,
+  Unknown = 3
+}
+/** Audio capabilities */
+export interface AudioCapabilities {
+  /** System audio capture supported */
+  systemAudio: boolean
+  /** Microphone capture supported */
+  microphone: boolean
+  /** Available sample rates */
+  sampleRates: Array<number>
+  /** Supported audio formats */
+  formats: Array<string>
+  /** Number of available input devices */
+  inputDevices: number
+  /** Number of available output devices */
+  outputDevices: number
+}
+/** Screen capture capabilities */
+export interface ScreenCapabilities {
+  /** Screen capture supported */
+  supported: boolean
+  /** Number of available displays */
+  displayCount: number
+  /** Window capture supported */
+  windowCapture: boolean
+  /** Maximum resolution supported */
+  maxResolution?: Resolution
+  /** Supported frame rates */
+  frameRates: Array<number>
+}
+/** Permission status */
+export interface PermissionStatus {
+  /** Microphone permission */
+  microphone: Permission
+  /** Screen recording permission */
+  screenRecording: Permission
+  /** System audio permission (macOS only) */
+  systemAudio: Permission
+}
+/** Permission state */
+export const enum Permission {
+  Granted = 0,
+  Denied = 1,
+  NotDetermined = 2,
+  NotRequired = 3
+}
+/** Encoding configuration */
+export interface EncodingConfig {
+  /** Audio encoding settings */
+  audio: AudioEncodingConfig
+  /** Video encoding settings */
+  video: VideoEncodingConfig
+  /** HLS segmentation settings */
+  hls: HLSConfig
+  /** Upload settings */
+  upload?: UploadConfig
+}
+/** Audio encoding configuration */
+export interface AudioEncodingConfig {
+  /** Audio codec (AAC, or Opus for WebM) */
+  codec: AudioCodec
+  /** Bitrate in bits per second */
+  bitrate: number
+  /** Sample rate in Hz */
+  sampleRate: number
+  /** Number of channels */
+  channels: number
+  /** Channel layout */
+  channelLayout: AudioChannelLayout
+}
+/** Video encoding configuration */
+export interface VideoEncodingConfig {
+  /** Video codec (H.264, HEVC, or VP9/AV1 for WebM) */
+  codec: VideoCodec
+  /** Bitrate in bits per second */
+  bitrate: number
+  /** Frame rate (fps) */
+  frameRate: FrameRate
+  /** Video resolution */
+  resolution: Resolution
+  /** Pixel format */
+  pixelFormat: PixelFormat
+  /** Hardware acceleration */
+  hardwareAcceleration: boolean
+  /** Encoder speed/quality preset: x264/x265/SVT-AV1 `preset`, libvpx/libaom `cpu-used` */
+  preset?: string
+  /** Codec profile (e.g. "high") */
+  profile?: string
+  /** Keyframe interval in frames, defaults to one keyframe per segment */
+  gopSize?: number
+}
+/** Frame rate as a rational number of frames per second */
+export interface FrameRate {
+  /** Frames */
+  numerator: number
+  /** Per this many seconds */
+  denominator: number
+}
+/** HLS configuration */
+export interface HLSConfig {
+  /** Segment duration in seconds */
+  segmentDuration: number
+  /** Target duration for playlist */
+  targetDuration: number
+  /** Number of segments to keep in playlist */
+  playlistSize: number
+  /** Segment container, defaults to MPEG-TS */
+  segmentFormat?: SegmentFormat
+  /** What happens to segments that leave the playlist window, defaults to keeping them */
+  retention?: SegmentRetention
+  /** Seconds an evicted segment stays available before it is deleted, defaults to 30 */
+  cleanupGraceSeconds?: number
+  /**
+   * Duration of Low-Latency HLS partial segments in seconds, 0.2-0.333;
+   * unset disables LL-HLS. Requires CMAF segments
+   */
+  partDuration?: number
+  /**
+   * Whether the origin in front of the sinks holds `_HLS_msn`/`_HLS_part`
+   * playlist requests, so LL-HLS playlists may advertise blocking reloads
+   */
+  blockingReload?: boolean
+}
+/** Retention of segments that leave the live playlist window */
+export const enum SegmentRetention {
+  /** Keep every segment, e.g. for VOD playback after the recording */
+  KeepAll = 0,
+  /** Delete segments once they have left the playlist window, for 24/7 live streams */
+  LiveWindow = 1
+}
+/** HLS segment container */
+export const enum SegmentFormat {
+  /** `.ts`/`.aac` segments (HLS version 3) */
+  MpegTs = 0,
+  /** `init.mp4` plus `.m4s` fragments (HLS version 7, also usable for DASH) */
+  Cmaf = 1
+}
+/** Audio codec options */
+export const enum AudioCodec {
+  AAC = 0,
+  Opus = 1
+}
+/** Video codec options */
+export const enum VideoCodec {
+  H264 = 0,
+  H265 = 1,
+  VP9 = 2,
+  AV1 = 3
+}
+/** Audio channel layout */
+export const enum AudioChannelLayout {
+  Mono = 0,
+  Stereo = 1,
+  Surround51 = 2
+}
+/** Pixel format for video */
+export const enum PixelFormat {
+  YUV420P = 0,
+  RGBA = 1,
+  BGRA = 2
+}
+/** S3 upload configuration */
+export interface UploadConfig {
+  /** S3 bucket name */
+  bucket: string
+  /** AWS region */
+  region: string
+  /** Upload timeout in seconds */
+  timeoutSeconds: number
+  /** Whether to use S3 Transfer Acceleration (AWS only, ignored with a custom endpoint) */
+  acceleratedTransfer: boolean
+  /** Custom endpoint (for S3-compatible services) */
+  endpoint?: string
+  /**
+   * Address buckets as `<endpoint>/<bucket>` instead of `<bucket>.<endpoint>`;
+   * defaults to true with a custom endpoint, as MinIO, Ceph and LocalStack expect
+   */
+  forcePathStyle?: boolean
+  /** Static credentials instead of the default AWS credential chain */
+  credentials?: S3Credentials
+  /** Named profile from the shared AWS config files */
+  profile?: string
+  /** Retry policy for failed uploads */
+  retry?: RetryConfig
+  /** Directory where pending uploads are spooled so they survive restarts */
+  spoolDir?: string
+  /** Multipart settings for full-length recordings */
+  multipart?: MultipartConfig
+  /** Cache-Control, server-side encryption and metadata of uploaded objects */
+  objectHeaders?: ObjectHeadersConfig
+  /** Maximum number of uploads in flight across all sinks, defaults to 4 */
+  maxConcurrentUploads?: number
+  /** Upload bandwidth cap in bytes per second across all sinks */
+  maxBytesPerSecond?: number
+  /** Checksum header sent with every object, defaults to SHA-256 */
+  checksumAlgorithm?: ChecksumAlgorithm
+}
+/** Headers and metadata set on uploaded objects */
+export interface ObjectHeadersConfig {
+  /** Cache-Control of playlists and the manifest, defaults to `no-cache` */
+  playlistCacheControl?: string
+  /** Cache-Control of segments and recordings, defaults to `max-age=31536000, immutable` */
+  segmentCacheControl?: string
+  /** Cache-Control by content type name, e.g. `MasterPlaylist` or `AudioSegment` */
+  cacheControl?: Record<string, string>
+  /** Server-side encryption of every object */
+  serverSideEncryption?: ServerSideEncryption
+  /** KMS key for `Kms` encryption, the bucket's default key if unset */
+  kmsKeyId?: string
+  /** Metadata added to every object next to `user-id`, `session-id` and `sequence` */
+  metadata?: Record<string, string>
+}
+/** Server-side encryption mode */
+export const enum ServerSideEncryption {
+  /** SSE-S3 with S3 managed keys (AES256) */
+  S3 = 0,
+  /** SSE-KMS with an AWS KMS key */
+  Kms = 1
+}
+/** Checksum sent with S3 uploads */
+export const enum ChecksumAlgorithm {
+  /** `x-amz-checksum-crc32c` */
+  Crc32c = 0,
+  /** `x-amz-checksum-sha256` */
+  Sha256 = 1
+}
+/** Static S3 credentials */
+export interface S3Credentials {
+  /** Access key ID */
+  accessKeyId: string
+  /** Secret access key */
+  secretAccessKey: string
+  /** Session token for temporary credentials */
+  sessionToken?: string
+}
+/** Multipart upload settings */
+export interface MultipartConfig {
+  /** Part size in MiB, at least 5 */
+  partSizeMb: number
+  /** Number of parts uploaded in parallel */
+  maxConcurrentParts: number
+}
+/** Retry policy for uploads */
+export interface RetryConfig {
+  /**
+   * Attempts per request retried in place, such as deletes and multipart
+   * parts; queued uploads retry transient failures until they succeed
+   */
+  maxAttempts: number
+  /** Delay before the first retry in milliseconds */
+  initialBackoffMs: number
+  /** Upper bound for the delay between retries in milliseconds */
+  maxBackoffMs: number
+}
+/** Kind of sink listed in `RecordingConfig.sinks` */
+export const enum SinkKind {
+  /** S3 or an S3-compatible service */
+  S3 = 0,
+  /** Local directory */
+  FileSystem = 1,
+  /** Generic HTTP endpoint accepting `PUT` and `DELETE` */
+  HttpPut = 2,
+  /** Plain HTTP uploads to URLs presigned by a backend endpoint */
+  PresignedPut = 3
+}
+/** Request for a presigned URL */
+export interface PresignRequest {
+  /** HTTP method the URL is for, `PUT` or `DELETE` */
+  method: string
+  /** Object key */
+  key: string
+  /** MIME type the object is uploaded with */
+  contentType: string
+  /** Payload size in bytes, 0 for `DELETE` */
+  contentLength: number
+  /** Base64 SHA-256 of the payload, for providers that sign `x-amz-checksum-sha256` */
+  checksumSha256?: string
+}
+/** Presigned URL and the headers that must be sent with it */
+export interface PresignedRequest {
+  /** URL to send the request to */
+  url: string
+  /** Headers covered by the signature */
+  headers?: Record<string, string>
+}
+/** Sink configuration */
+export interface SinkConfig {
+  /** Sink kind */
+  kind: SinkKind
+  /** Bucket name (S3) */
+  bucket?: string
+  /** AWS region (S3), defaults to us-east-1 */
+  region?: string
+  /** Custom endpoint (S3-compatible services) */
+  endpoint?: string
+  /** Path-style bucket addressing, defaults to true with a custom endpoint (S3) */
+  forcePathStyle?: boolean
+  /** Static credentials instead of the default AWS credential chain (S3) */
+  credentials?: S3Credentials
+  /** Named AWS profile (S3) */
+  profile?: string
+  /** Use S3 Transfer Acceleration, which must be enabled on the bucket; defaults to false (S3) */
+  acceleratedTransfer?: boolean
+  /** Root directory (FileSystem) */
+  path?: string
+  /** Base URL that object keys are appended to (HttpPut), or the presign endpoint (PresignedPut) */
+  url?: string
+  /** Extra request headers, e.g. authorization (HttpPut, and presign requests of PresignedPut) */
+  headers?: Record<string, string>
+  /** Request timeout in seconds, defaults to 10 */
+  timeoutSeconds?: number
+}
+/** Complete recording configuration */
+export interface RecordingConfig {
+  /** Audio capture settings */
+  audio: AudioCaptureConfig
+  /** Screen capture settings */
+  screen: ScreenCaptureConfig
+  /** Encoding settings */
+  encoding: EncodingConfig
+  /** User ID for S3 organization */
+  userId: string
+  /** S3 bucket for uploads */
+  s3Bucket?: string
+  /** Enable real-time transcription */
+  enableTranscription: boolean
+  /** Enable real-time streaming */
+  enableStreaming: boolean
+  /** Container output; WebM muxes VP9/AV1 video and Opus audio instead of HLS */
+  output?: OutputFormat
+  /** Additional destinations for HLS segments and playlists */
+  sinks?: Array<SinkConfig>
+}
+/** Recording session information */
+export interface RecordingSession {
+  /** Session ID */
+  id: string
+  /** User ID */
+  userId: string
+  /** Start timestamp in milliseconds */
+  startTime: number
+  /** Current status */
+  status: RecordingStatus
+  /** Error message when status is `Error` */
+  error?: string
+  /** Stream URLs (if streaming enabled) */
+  streamUrls: StreamUrls
+  /** Recording statistics */
+  stats: RecordingStats
+}
+/** Recording status */
+export const enum RecordingStatus {
+  Initializing = 0,
+  Recording = 1,
+  Paused = 2,
+  Stopping = 3,
+  Stopped = 4,
+  Error = 5
+}
+/** Stream URLs for different content types */
+export interface StreamUrls {
+  /** Master playlist URL */
+  master?: string
+  /** Video stream URL */
+  video?: string
+  /** Audio stream URL */
+  audio?: string
+  /** Combined stream URL */
+  combined?: string
+  /** Master playlist of the whole recording, unless segments are deleted after the live window */
+  archive?: string
+  /** DASH MPD, with CMAF segments */
+  dash?: string
+}
+/** Recording statistics */
+export interface RecordingStats {
+  /** Duration in seconds */
+  duration: number
+  /** Number of video frames captured */
+  videoFrames: number
+  /** Number of audio segments processed */
+  audioSegments: number
+  /** Total bytes uploaded */
+  bytesUploaded: number
+  /** Average encoding FPS */
+  avgFps: number
+  /** Uploads queued but not yet in flight */
+  uploadQueueDepth: number
+  /** Uploads currently in flight */
+  uploadsInFlight: number
+}
+/**
+ * Initialize the library and check platform capabilities
+ *
+ * Returns the capabilities as a JSON string; prefer `getPlatformCapabilities`
+ * for a typed result.
+ */
 export declare function init(): string
+/** Get typed platform capabilities */
+export declare function getPlatformCapabilities(): PlatformCapabilities
 /** Get information about available audio devices */
 export declare function getAudioDevices(): string
 /** Get information about available displays for screen capture */
@@ -95,15 +520,89 @@ export declare function createCaptureSession(config: string): string
 export declare function startNativeSystemAudio(sessionId: string): Promise<string>
 /** Test native system audio capture capabilities */
 export declare function testNativeSystemAudio(): string
-/** Create a new recording pipeline with Cap's architecture */
+/**
+ * Create a new recording pipeline with Cap's architecture
+ *
+ * Takes the configuration as a JSON string; prefer the `CapRecordingPipeline`
+ * class for compile-time checked configuration.
+ */
 export declare function createRecordingPipeline(config: string): Promise<string>
 /** Start recording with the specified session */
 export declare function startRecording(sessionId: string): Promise<string>
 /** Stop recording and finalize segments */
 export declare function stopRecording(sessionId: string): Promise<string>
+/** Get the current status and statistics of a recording session */
+export declare function getRecordingSession(sessionId: string): Promise<string>
+/** Stop (if recording) and release a recording session */
+export declare function destroyRecording(sessionId: string): Promise<void>
+/** List IDs of all active recording sessions */
+export declare function listRecordingSessions(): Array<string>
+/**
+ * Subscribe to audio, encoded and HLS segments produced by a recording session
+ *
+ * Returns a listener ID that can be passed to `removeListener`.
+ */
+export declare function onSegment(sessionId: string, callback: (...args: any[]) => any): number
+/** Subscribe to upload results of a recording session */
+export declare function onUpload(sessionId: string, callback: (...args: any[]) => any): number
+/** Subscribe to non-fatal errors raised while a recording session runs */
+export declare function onError(sessionId: string, callback: (...args: any[]) => any): number
+/** Subscribe to status changes of a recording session */
+export declare function onStatusChange(sessionId: string, callback: (...args: any[]) => any): number
+/** Remove a listener registered with one of the `on*` functions */
+export declare function removeListener(sessionId: string, listenerId: number): boolean
 /** Get encoding capabilities and configuration options */
 export declare function getEncodingCapabilities(): string
-/** Process audio chunk and return encoded segments */
-export declare function processAudioChunk(sessionId: string, pcmData: Array<number>): Promise<string>
-/** Flush encoder and return any remaining segments */
+/**
+ * Process audio chunk and return encoded segments
+ *
+ * Chunks are accumulated by a per-session streaming encoder, so segments are
+ * only returned once enough audio has been pushed to complete one.
+ */
+export declare function processAudioChunk(sessionId: string, pcmData: Array<number>, sampleRate?: number | undefined | null, channels?: number | undefined | null): Promise<string>
+/** Flush the session's streaming encoder and return any remaining segments */
 export declare function flushEncoder(sessionId: string): Promise<string>
+/**
+ * Recording pipeline exposed to JavaScript as a typed class
+ *
+ * Instances are registered with the session manager, so `sessionId` can also
+ * be used with the session functions and `on*` event subscriptions. The
+ * session is destroyed when the object is garbage collected without `destroy()`.
+ */
+export class CapRecordingPipeline {
+  /** Create a pipeline for the given configuration */
+  constructor(config: RecordingConfig)
+  /** Unique session ID of this pipeline */
+  get sessionId(): string
+  /** Initialize capture, encoders and uploaders */
+  initialize(): Promise<void>
+  /** Start recording */
+  start(): Promise<RecordingSession>
+  /** Stop recording and finalize segments */
+  stop(): Promise<RecordingSession>
+  /** Pause recording; nothing is encoded until it resumes */
+  pause(): Promise<RecordingSession>
+  /** Resume a paused recording, starting a new discontinuity in the playlists */
+  resume(): Promise<RecordingSession>
+  /** Current recording status */
+  status(): Promise<RecordingStatus>
+  /** Current recording statistics */
+  stats(): Promise<RecordingStats>
+  /** Snapshot of the full session state */
+  session(): Promise<RecordingSession>
+  /** Stop (if recording) and release the session */
+  destroy(): Promise<void>
+  /**
+   * Upload a finished file, such as the WebM recording, to the pipeline's S3 bucket
+   *
+   * Uses S3 multipart upload; calling it again after an interruption resumes
+   * the upload of the same file and key.
+   */
+  uploadFile(path: string, key: string, contentType?: string | undefined | null): Promise<string>
+  /**
+   * Upload to URLs presigned by `provider`, so no AWS credentials are needed
+   *
+   * `id` names the sink's spool directory and should stay the same across restarts.
+   */
+  addPresignedSink(provider: (request: PresignRequest) => Promise<PresignedRequest>, id?: string): Promise<void>
+}
@@ -310,11 +310,24 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { AudioFormat, VideoFormat, init, getAudioDevices, getDisplays, requestPermissions, checkPermissions, getSystemAudioSetupInstructions, createCaptureSession, startNativeSystemAudio, testNativeSystemAudio, createRecordingPipeline, startRecording, stopRecording, getEncodingCapabilities, processAudioChunk, flushEncoder } = nativeBinding
+const { AudioFormat, VideoFormat, Platform, Permission, AudioCodec, VideoCodec, SegmentFormat, SegmentRetention, SinkKind, ServerSideEncryption, ChecksumAlgorithm, AudioChannelLayout, PixelFormat, RecordingStatus, init, getPlatformCapabilities, getAudioDevices, getDisplays, requestPermissions, checkPermissions, getSystemAudioSetupInstructions, createCaptureSession, startNativeSystemAudio, testNativeSystemAudio, createRecordingPipeline, startRecording, stopRecording, getRecordingSession, destroyRecording, listRecordingSessions, onSegment, onUpload, onError, onStatusChange, removeListener, getEncodingCapabilities, processAudioChunk, flushEncoder, CapRecordingPipeline } = nativeBinding
 
 module.exports.AudioFormat = AudioFormat
 module.exports.VideoFormat = VideoFormat
+module.exports.Platform = Platform
+module.exports.Permission = Permission
+module.exports.AudioCodec = AudioCodec
+module.exports.VideoCodec = VideoCodec
+module.exports.SegmentFormat = SegmentFormat
+module.exports.SegmentRetention = SegmentRetention
+module.exports.SinkKind = SinkKind
+module.exports.ServerSideEncryption = ServerSideEncryption
+module.exports.ChecksumAlgorithm = ChecksumAlgorithm
+module.exports.AudioChannelLayout = AudioChannelLayout
+module.exports.PixelFormat = PixelFormat
+module.exports.RecordingStatus = RecordingStatus
 module.exports.init = init
+module.exports.getPlatformCapabilities = getPlatformCapabilities
 module.exports.getAudioDevices = getAudioDevices
 module.exports.getDisplays = getDisplays
 module.exports.requestPermissions = requestPermissions
@@ -326,6 +339,15 @@ module.exports.testNativeSystemAudio = testNativeSystemAudio
 module.exports.createRecordingPipeline = createRecordingPipeline
 module.exports.startRecording = startRecording
 module.exports.stopRecording = stopRecording
+module.exports.getRecordingSession = getRecordingSession
+module.exports.destroyRecording = destroyRecording
+module.exports.listRecordingSessions = listRecordingSessions
+module.exports.onSegment = onSegment
+module.exports.onUpload = onUpload
+module.exports.onError = onError
+module.exports.onStatusChange = onStatusChange
+module.exports.removeListener = removeListener
 module.exports.getEncodingCapabilities = getEncodingCapabilities
 module.exports.processAudioChunk = processAudioChunk
 module.exports.flushEncoder = flushEncoder
+module.exports.CapRecordingPipeline = CapRecordingPipeline
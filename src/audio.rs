@@ -642,6 +642,11 @@ impl AudioProcessor {
     }
 }
 
+// Manual Send implementation so the processor can live inside a registered pipeline
+// SAFETY: cpal streams are only started, paused and dropped by the owning pipeline,
+// which is always accessed behind the session manager's lock
+unsafe impl Send for AudioProcessor {}
+
 /// Get available audio devices
 pub fn get_available_devices() -> CaptureResult<Vec<AudioDevice>> {
     let host = cpal::default_host();
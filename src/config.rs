@@ -103,7 +103,7 @@ pub struct OutputFormat {
     pub audio: AudioFormat,
     /// Video output format (if screen capture is enabled)
     pub video: VideoFormat,
-    /// Output directory for segments
+    /// Output directory for segments and playlists, laid out like the S3 keys
     pub output_dir: Option<String>,
     /// Enable real-time streaming
     pub real_time: bool,
@@ -120,6 +120,42 @@ impl Default for OutputFormat {
     }
 }
 
+/// Frame dimensions in pixels
+///
+/// JSON configs may also give it in the older `[width, height]` form.
+#[napi(object)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ResolutionRepr")]
+pub struct Resolution {
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+}
+
+/// Accepted JSON forms of `Resolution`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ResolutionRepr {
+    Fields { width: u32, height: u32 },
+    Tuple(u32, u32),
+}
+
+impl From<ResolutionRepr> for Resolution {
+    fn from(repr: ResolutionRepr) -> Self {
+        match repr {
+            ResolutionRepr::Fields { width, height } | ResolutionRepr::Tuple(width, height) => Self::new(width, height),
+        }
+    }
+}
+
+impl Resolution {
+    /// Create a resolution from width and height
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
 /// Supported audio formats
 #[napi]
 #[derive(Debug, Serialize, Deserialize)]
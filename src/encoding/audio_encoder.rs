@@ -3,7 +3,7 @@
 //! Following Cap's architecture and patterns
 
 use crate::error::{CaptureError, CaptureResult};
-use super::{AudioEncodingConfig, AudioCodec, AudioChannelLayout};
+use super::{AudioEncodingConfig, AudioCodec, AudioChannelLayout, OpusEncoder, DEFAULT_SEGMENT_DURATION};
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use ffmpeg::{
@@ -16,8 +16,10 @@ use ffmpeg::{
 /// Encoded audio segment ready for upload
 #[derive(Debug, Clone)]
 pub struct EncodedAudioSegment {
-    /// Encoded AAC data
+    /// Encoded data (ADTS-framed for AAC, raw packets for Opus)
     pub data: Vec<u8>,
+    /// Codec of `data`
+    pub codec: AudioCodec,
     /// Segment sequence number
     pub sequence: u32,
     /// Duration in seconds
@@ -28,6 +30,104 @@ pub struct EncodedAudioSegment {
     pub sample_rate: u32,
     /// Number of channels
     pub channels: u16,
+    /// Presentation time of the first sample, in samples
+    pub pts: i64,
+    /// Encoded packets in `data`
+    pub frames: Vec<EncodedAudioFrame>,
+}
+
+/// Location and timing of a single encoded packet within a segment
+#[derive(Debug, Clone)]
+pub struct EncodedAudioFrame {
+    /// Byte offset into the segment data
+    pub offset: usize,
+    /// Size in bytes
+    pub size: usize,
+    /// Presentation timestamp in samples
+    pub pts: i64,
+}
+
+/// Low-Latency HLS partial segment of audio
+#[derive(Debug, Clone)]
+pub struct EncodedAudioPart {
+    /// Sequence number of the segment the part belongs to
+    pub segment: u32,
+    /// Position of the part within its segment
+    pub index: u32,
+    /// The part's packets; `sequence` is a running part number
+    pub media: EncodedAudioSegment,
+}
+
+/// Groups part-sized encoder output into segments
+struct PartAssembler {
+    /// Segment length in samples
+    segment_samples: i64,
+    sequence: u32,
+    part_counter: u32,
+    pending: Vec<EncodedAudioSegment>,
+    /// Parts completed since the last `take_parts`
+    parts: Vec<EncodedAudioPart>,
+}
+
+impl PartAssembler {
+    fn new(sample_rate: u32, segment_duration: f64) -> Self {
+        Self {
+            segment_samples: (sample_rate as f64 * segment_duration) as i64,
+            sequence: 0,
+            part_counter: 0,
+            pending: Vec::new(),
+            parts: Vec::new(),
+        }
+    }
+
+    /// Record a part, returning the segment it completes
+    ///
+    /// Segments end on the first part past each segment boundary, so their
+    /// length varies by up to a part but does not drift.
+    fn push(&mut self, part: EncodedAudioSegment) -> Option<EncodedAudioSegment> {
+        let end = part.pts + (part.duration * part.sample_rate as f64).round() as i64;
+
+        self.parts.push(EncodedAudioPart {
+            segment: self.sequence,
+            index: self.pending.len() as u32,
+            media: EncodedAudioSegment { sequence: self.part_counter, ..part.clone() },
+        });
+        self.part_counter += 1;
+        self.pending.push(part);
+
+        if end >= (self.sequence as i64 + 1) * self.segment_samples {
+            self.finish()
+        } else {
+            None
+        }
+    }
+
+    /// Concatenate the pending parts into the next segment
+    fn finish(&mut self) -> Option<EncodedAudioSegment> {
+        let parts = std::mem::take(&mut self.pending);
+        let first = parts.first()?;
+
+        let mut segment = EncodedAudioSegment {
+            data: Vec::new(),
+            codec: first.codec,
+            sequence: self.sequence,
+            duration: 0.0,
+            timestamp: first.timestamp,
+            sample_rate: first.sample_rate,
+            channels: first.channels,
+            pts: first.pts,
+            frames: Vec::new(),
+        };
+        for part in &parts {
+            let offset = segment.data.len();
+            segment.frames.extend(part.frames.iter().map(|frame| EncodedAudioFrame { offset: frame.offset + offset, ..frame.clone() }));
+            segment.data.extend_from_slice(&part.data);
+            segment.duration += part.duration;
+        }
+
+        self.sequence += 1;
+        Some(segment)
+    }
 }
 
 /// Error types following Cap's pattern
@@ -72,6 +172,7 @@ pub struct AACEncoder {
     pts: i64,
     samples_per_segment: usize,
     current_segment_samples: Vec<f32>,
+    flushed: bool,
 }
 
 impl AACEncoder {
@@ -79,7 +180,7 @@ impl AACEncoder {
     const OUTPUT_SAMPLE_FORMAT: Sample = Sample::F32(Type::Planar);
     
     /// Create new AAC encoder following Cap's factory pattern
-    pub fn new(config: AudioEncodingConfig) -> Result<Self, AudioEncodingError> {
+    pub fn new(config: AudioEncodingConfig, segment_duration: f64) -> Result<Self, AudioEncodingError> {
         // Initialize FFmpeg following Cap's pattern
         ffmpeg::init().map_err(|e| AudioEncodingError::Other(format!("FFmpeg init: {}", e)))?;
         
@@ -149,19 +250,30 @@ impl AACEncoder {
             config: config.clone(),
             sequence_counter: 0,
             pts: 0,
-            samples_per_segment: (config.sample_rate as f64 * 2.0) as usize, // Cap's 2-second segments
+            // Interleaved, so scale by channel count
+            samples_per_segment: (config.sample_rate as f64 * segment_duration) as usize * config.channels as usize,
             current_segment_samples: Vec::new(),
+            flushed: false,
         })
     }
 
+    /// Change the segment length, e.g. to emit LL-HLS parts; takes effect from the next segment
+    pub fn set_segment_duration(&mut self, seconds: f64) {
+        self.samples_per_segment = (self.config.sample_rate as f64 * seconds) as usize * self.config.channels as usize;
+    }
+
     /// Process audio samples following Cap's real-time pattern
     pub fn process_audio(&mut self, pcm_data: &[f32]) -> Result<Vec<EncodedAudioSegment>, AudioEncodingError> {
+        if self.flushed {
+            return Err(AudioEncodingError::Other("Encoder has already been flushed".to_string()));
+        }
+
         let mut segments = Vec::new();
         
         // Add samples to current segment buffer
         self.current_segment_samples.extend_from_slice(pcm_data);
 
-        // Process complete segments (Cap's 2-second segments)
+        // Process complete segments
         while self.current_segment_samples.len() >= self.samples_per_segment {
             let segment_data: Vec<f32> = self.current_segment_samples
                 .drain(..self.samples_per_segment)
@@ -179,35 +291,48 @@ impl AACEncoder {
         let frame_size = 1024; // AAC frame size in samples per channel
         let samples_per_frame = frame_size * self.config.channels as usize;
         let mut encoded_data = Vec::new();
+        let mut frames = Vec::new();
+        let start_pts = self.pts;
         
         // Process the PCM data in chunks that fit the encoder's frame size
         for chunk_start in (0..pcm_data.len()).step_by(samples_per_frame) {
             let chunk_end = (chunk_start + samples_per_frame).min(pcm_data.len());
             let chunk = &pcm_data[chunk_start..chunk_end];
             
-            // Skip incomplete frames at the end (will be handled in next segment or flush)
+            // Carry incomplete frames over to the next segment (or flush)
             if chunk.len() < samples_per_frame {
+                self.current_segment_samples.splice(0..0, chunk.iter().copied());
                 break;
             }
             
+            let frame_pts = self.pts;
             let frame = self.create_audio_frame(chunk)?;
             let raw_frame_data = self.queue_frame(frame)?;
             
             // ✅ Add ADTS header to make it playable
             if !raw_frame_data.is_empty() {
                 let adts_frame = self.add_adts_header(&raw_frame_data);
+                frames.push(EncodedAudioFrame {
+                    offset: encoded_data.len(),
+                    size: adts_frame.len(),
+                    // The encoder emits each packet one frame behind its input
+                    pts: (frame_pts - frame_size as i64).max(0),
+                });
                 encoded_data.extend(adts_frame);
             }
         }
         
         let segment = EncodedAudioSegment {
             data: encoded_data,
+            codec: AudioCodec::AAC,
             sequence: self.sequence_counter,
-            duration: 2.0, // Cap's 2-second segments
+            duration: (self.pts - start_pts) as f64 / self.config.sample_rate as f64,
             // ✅ Use PTS-based timestamp calculation (Cap's approach)
-            timestamp: (self.pts as f64 / self.config.sample_rate as f64 * 1000.0) as u64,
+            timestamp: (start_pts as f64 / self.config.sample_rate as f64 * 1000.0) as u64,
             sample_rate: self.config.sample_rate,
             channels: self.config.channels,
+            pts: frames.first().map(|frame| frame.pts).unwrap_or(start_pts),
+            frames,
         };
         
         self.sequence_counter += 1;
@@ -254,16 +379,22 @@ impl AACEncoder {
     pub fn flush(&mut self) -> Result<Vec<EncodedAudioSegment>, AudioEncodingError> {
         let mut segments = Vec::new();
 
+        // The encoder has already received EOF; flushing again is a no-op
+        if self.flushed {
+            return Ok(segments);
+        }
+        self.flushed = true;
+
         // Encode any remaining samples
         if !self.current_segment_samples.is_empty() {
-            // Pad with silence if needed
-            self.current_segment_samples.resize(self.samples_per_segment, 0.0);
+            // Pad the final partial frame with silence
+            let samples_per_frame = 1024 * self.config.channels as usize;
+            let padded_len = self.current_segment_samples.len().div_ceil(samples_per_frame) * samples_per_frame;
+            self.current_segment_samples.resize(padded_len, 0.0);
 
-            let segment_data = self.current_segment_samples.clone();
+            let segment_data = std::mem::take(&mut self.current_segment_samples);
             let encoded_segment = self.encode_audio_segment(&segment_data)?;
             segments.push(encoded_segment);
-
-            self.current_segment_samples.clear();
         }
         
         // Flush resampler if needed
@@ -296,15 +427,7 @@ impl AACEncoder {
                 };
                 
                 if !encoded_data.is_empty() {
-                    let segment = EncodedAudioSegment {
-                        data: encoded_data,
-                        sequence: self.sequence_counter,
-                        duration: 0.0, // Flush data
-                        // ✅ Use PTS-based timestamp calculation
-                        timestamp: (self.pts as f64 / self.config.sample_rate as f64 * 1000.0) as u64,
-                        sample_rate: self.config.sample_rate,
-                        channels: self.config.channels,
-                    };
+                    let segment = self.flush_segment(encoded_data);
                     
                     self.sequence_counter += 1;
                     segments.push(segment);
@@ -319,15 +442,7 @@ impl AACEncoder {
         while self.encoder.receive_packet(&mut self.packet).is_ok() {
             let encoded_data = self.packet.data().unwrap_or(&[]).to_vec();
             if !encoded_data.is_empty() {
-                let segment = EncodedAudioSegment {
-                    data: encoded_data,
-                    sequence: self.sequence_counter,
-                    duration: 0.0, // Final flush
-                    // ✅ Use PTS-based timestamp calculation
-                    timestamp: (self.pts as f64 / self.config.sample_rate as f64 * 1000.0) as u64,
-                    sample_rate: self.config.sample_rate,
-                    channels: self.config.channels,
-                };
+                let segment = self.flush_segment(encoded_data);
                 
                 self.sequence_counter += 1;
                 segments.push(segment);
@@ -337,6 +452,25 @@ impl AACEncoder {
         Ok(segments)
     }
 
+    /// Wrap packets drained while flushing into a zero-duration segment
+    fn flush_segment(&self, raw_data: Vec<u8>) -> EncodedAudioSegment {
+        let data = self.add_adts_header(&raw_data);
+        let pts = (self.pts - 1024).max(0);
+
+        EncodedAudioSegment {
+            frames: vec![EncodedAudioFrame { offset: 0, size: data.len(), pts }],
+            data,
+            codec: AudioCodec::AAC,
+            sequence: self.sequence_counter,
+            duration: 0.0, // Flush data
+            // ✅ Use PTS-based timestamp calculation
+            timestamp: (self.pts as f64 / self.config.sample_rate as f64 * 1000.0) as u64,
+            sample_rate: self.config.sample_rate,
+            channels: self.config.channels,
+            pts,
+        }
+    }
+
     /// Encode a single frame (Cap's pattern)
     fn encode_frame(&mut self, frame: &ffmpeg::frame::Audio) -> Result<Vec<u8>, AudioEncodingError> {
         self.encoder.send_frame(frame)?;
@@ -409,30 +543,102 @@ impl AudioEncoderTrait for AACEncoder {
     }
 }
 
+/// Codec-specific encoder behind `AudioEncoder`
+enum AudioBackend {
+    Aac(AACEncoder),
+    Opus(OpusEncoder),
+}
+
+impl AudioBackend {
+    fn process_audio(&mut self, pcm_data: &[f32]) -> Result<Vec<EncodedAudioSegment>, AudioEncodingError> {
+        match self {
+            AudioBackend::Aac(encoder) => encoder.process_audio(pcm_data),
+            AudioBackend::Opus(encoder) => encoder.process_audio(pcm_data),
+        }
+    }
+
+    fn flush(&mut self) -> Result<Vec<EncodedAudioSegment>, AudioEncodingError> {
+        match self {
+            AudioBackend::Aac(encoder) => encoder.flush(),
+            AudioBackend::Opus(encoder) => encoder.flush(),
+        }
+    }
+
+    fn set_segment_duration(&mut self, seconds: f64) {
+        match self {
+            AudioBackend::Aac(encoder) => encoder.set_segment_duration(seconds),
+            AudioBackend::Opus(encoder) => encoder.set_segment_duration(seconds),
+        }
+    }
+}
+
 /// Thread-safe wrapper for the audio encoder (Cap's pattern)
 #[derive(Clone)]
 pub struct AudioEncoder {
-    inner: Arc<Mutex<AACEncoder>>,
+    inner: Arc<Mutex<AudioBackend>>,
+    /// Set when LL-HLS parts are enabled; the backend then encodes parts
+    parts: Arc<Mutex<Option<PartAssembler>>>,
     config: AudioEncodingConfig,
+    /// Segment length in seconds
+    segment_duration: f64,
 }
 
 impl AudioEncoder {
-    /// Create new audio encoder with real FFmpeg AAC encoding
-    pub fn new(config: AudioEncodingConfig) -> CaptureResult<Self> {
+    /// Create new audio encoder with real FFmpeg AAC or Opus encoding, cutting segments of `segment_duration` seconds
+    pub fn new(config: AudioEncodingConfig, segment_duration: f64) -> CaptureResult<Self> {
         log::info!("Initializing real FFmpeg audio encoder with config: {:?}", config);
+
+        if !segment_duration.is_finite() || segment_duration <= 0.0 {
+            return Err(CaptureError::Config(format!("Invalid segment duration: {}", segment_duration)));
+        }
         
-        let encoder = AACEncoder::new(config.clone())
-            .map_err(|e| CaptureError::from(e))?;
+        let encoder = match config.codec {
+            AudioCodec::AAC => AudioBackend::Aac(AACEncoder::new(config.clone(), segment_duration)?),
+            AudioCodec::Opus => AudioBackend::Opus(OpusEncoder::new(config.clone(), segment_duration)?),
+        };
         
         log::info!("FFmpeg audio encoder initialized successfully");
         
         Ok(Self {
             inner: Arc::new(Mutex::new(encoder)),
+            parts: Arc::new(Mutex::new(None)),
             config,
+            segment_duration,
         })
     }
 
-    /// Process audio samples and encode to AAC segments (Cap's real-time approach)
+    /// Get the encoder configuration
+    pub fn config(&self) -> &AudioEncodingConfig {
+        &self.config
+    }
+
+    /// `OpusHead` identification header, for containers that need it
+    pub fn opus_head(&self) -> Option<Vec<u8>> {
+        match &*self.inner.lock().ok()? {
+            AudioBackend::Opus(encoder) => Some(encoder.opus_head()),
+            AudioBackend::Aac(_) => None,
+        }
+    }
+
+    /// Also produce partial segments of about `seconds`, for LL-HLS; call before encoding
+    pub fn set_part_duration(&mut self, seconds: Option<f64>) -> CaptureResult<()> {
+        let mut inner = self.inner.lock().map_err(|e| {
+            CaptureError::EncodingError(format!("Failed to acquire encoder lock: {}", e))
+        })?;
+
+        inner.set_segment_duration(seconds.unwrap_or(self.segment_duration));
+        *self.parts.lock().unwrap() = seconds.map(|_| PartAssembler::new(self.config.sample_rate, self.segment_duration));
+        Ok(())
+    }
+
+    /// Take the parts completed so far; they come before the segment that contains them
+    pub fn take_parts(&self) -> Vec<EncodedAudioPart> {
+        self.parts.lock().unwrap().as_mut()
+            .map(|assembler| std::mem::take(&mut assembler.parts))
+            .unwrap_or_default()
+    }
+
+    /// Process audio samples and encode to segments (Cap's real-time approach)
     pub fn process_audio(&mut self, pcm_data: &[f32]) -> CaptureResult<Vec<EncodedAudioSegment>> {
         let mut inner = self.inner.lock().map_err(|e| {
             CaptureError::EncodingError(format!("Failed to acquire encoder lock: {}", e))
@@ -441,7 +647,21 @@ impl AudioEncoder {
         let segments = inner.process_audio(pcm_data)
             .map_err(|e| CaptureError::from(e))?;
 
-        Ok(segments)
+        Ok(self.assemble(segments, false))
+    }
+
+    /// With parts enabled the backend's output are parts, grouped here into segments
+    fn assemble(&self, output: Vec<EncodedAudioSegment>, last: bool) -> Vec<EncodedAudioSegment> {
+        let mut parts = self.parts.lock().unwrap();
+        let Some(assembler) = parts.as_mut() else {
+            return output;
+        };
+
+        let mut segments: Vec<_> = output.into_iter().filter_map(|part| assembler.push(part)).collect();
+        if last {
+            segments.extend(assembler.finish());
+        }
+        segments
     }
 
     /// Flush any remaining audio data (final cleanup)
@@ -452,6 +672,7 @@ impl AudioEncoder {
 
         let segments = inner.flush()
             .map_err(|e| CaptureError::from(e))?;
+        let segments = self.assemble(segments, true);
 
         log::debug!("Flushed audio encoder with {} remaining segments", segments.len());
         Ok(segments)
@@ -468,7 +689,7 @@ impl Drop for AudioEncoder {
 
 /// Factory function following Cap's pattern
 pub fn create_aac_encoder(config: AudioEncodingConfig) -> CaptureResult<AudioEncoder> {
-    AudioEncoder::new(config)
+    AudioEncoder::new(config, DEFAULT_SEGMENT_DURATION)
 }
 
 /// Create transcription-optimized encoder following Cap's defaults
@@ -0,0 +1,164 @@
+//! MPEG-DASH Manifest Generation
+//!
+//! Describes the CMAF fragments of a recording in an MPD, so DASH-only
+//! players fetch the same `init.mp4` and `.m4s` objects as HLS players.
+//! Segments are addressed with a `SegmentTemplate` using `$Number$`.
+
+use super::StreamInfo;
+use super::VIDEO_CLOCK_RATE;
+
+/// MPD `type`
+#[derive(Debug, Clone, Copy)]
+pub enum MpdType {
+    /// Live presentation; players reload the MPD and derive segment availability from the wall clock
+    Dynamic,
+    /// Finished recording
+    Static,
+}
+
+/// Contents of the MPD of a recording
+#[derive(Debug, Clone)]
+pub struct DashManifest {
+    pub mpd_type: MpdType,
+    /// Wall-clock start of segment 0 in Unix milliseconds
+    pub availability_start_time: u64,
+    /// When this version of the MPD was generated, in Unix milliseconds
+    pub publish_time: u64,
+    /// Nominal segment duration in seconds
+    pub segment_duration: f64,
+    /// Number of the first listed segment, for static MPDs whose earlier segments were deleted
+    pub start_number: u32,
+    /// Summed duration of the listed segments in seconds
+    pub duration: f64,
+    /// Seconds of content kept behind the live edge, `None` when every segment is kept
+    pub time_shift_buffer_depth: Option<f64>,
+    pub stream_info: StreamInfo,
+    /// Peak bitrates of the tracks in bits per second
+    pub video_bandwidth: u64,
+    pub audio_bandwidth: u64,
+}
+
+impl DashManifest {
+    /// Render the MPD; segment URLs are relative to the MPD at the session root
+    pub fn render(&self) -> String {
+        let info = &self.stream_info;
+        let mut mpd = String::new();
+
+        mpd.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        mpd.push_str("<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\"");
+        match self.mpd_type {
+            MpdType::Dynamic => {
+                mpd.push_str(" type=\"dynamic\"");
+                mpd.push_str(&format!(" availabilityStartTime=\"{}\"", date_time(self.availability_start_time)));
+                mpd.push_str(&format!(" publishTime=\"{}\"", date_time(self.publish_time)));
+                mpd.push_str(&format!(" minimumUpdatePeriod=\"{}\"", duration(self.segment_duration)));
+                mpd.push_str(&format!(" suggestedPresentationDelay=\"{}\"", duration(self.segment_duration * 3.0)));
+                if let Some(depth) = self.time_shift_buffer_depth {
+                    mpd.push_str(&format!(" timeShiftBufferDepth=\"{}\"", duration(depth)));
+                }
+            }
+            MpdType::Static => {
+                mpd.push_str(" type=\"static\"");
+                mpd.push_str(&format!(" mediaPresentationDuration=\"{}\"", duration(self.duration)));
+            }
+        }
+        mpd.push_str(&format!(" minBufferTime=\"{}\">\n", duration(self.segment_duration * 2.0)));
+        mpd.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+
+        if let Some(video_codecs) = &info.video_codecs {
+            mpd.push_str("    <AdaptationSet id=\"0\" contentType=\"video\" mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\n");
+            self.push_segment_template(&mut mpd, "video/init.mp4", "video/video_recording_$Number$.m4s", VIDEO_CLOCK_RATE as u32);
+            mpd.push_str(&format!(
+                "      <Representation id=\"video\" codecs=\"{}\" bandwidth=\"{}\" width=\"{}\" height=\"{}\" frameRate=\"{}\"/>\n",
+                video_codecs,
+                self.video_bandwidth,
+                info.resolution.width,
+                info.resolution.height,
+                frame_rate(info.frame_rate),
+            ));
+            mpd.push_str("    </AdaptationSet>\n");
+        }
+
+        if let Some(audio_codecs) = &info.audio_codecs {
+            mpd.push_str("    <AdaptationSet id=\"1\" contentType=\"audio\" mimeType=\"audio/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\n");
+            self.push_segment_template(&mut mpd, "audio/init.mp4", "audio/audio_recording_$Number$.m4s", info.audio_sample_rate);
+            mpd.push_str(&format!(
+                "      <Representation id=\"audio\" codecs=\"{}\" bandwidth=\"{}\" audioSamplingRate=\"{}\">\n",
+                audio_codecs, self.audio_bandwidth, info.audio_sample_rate
+            ));
+            mpd.push_str(&format!(
+                "        <AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\"/>\n",
+                info.audio_channels
+            ));
+            mpd.push_str("      </Representation>\n");
+            mpd.push_str("    </AdaptationSet>\n");
+        }
+
+        mpd.push_str("  </Period>\n");
+        mpd.push_str("</MPD>\n");
+        mpd
+    }
+
+    /// `SegmentTemplate` of one track; fragments are numbered from 0 at media time 0
+    fn push_segment_template(&self, mpd: &mut String, initialization: &str, media: &str, timescale: u32) {
+        let segment_duration = (self.segment_duration * timescale as f64).round() as u64;
+        mpd.push_str(&format!(
+            "      <SegmentTemplate timescale=\"{}\" duration=\"{}\" startNumber=\"{}\"",
+            timescale, segment_duration, self.start_number
+        ));
+        if self.start_number > 0 {
+            mpd.push_str(&format!(" presentationTimeOffset=\"{}\"", segment_duration * self.start_number as u64));
+        }
+        mpd.push_str(&format!(" initialization=\"{}\" media=\"{}\"/>\n", initialization, media));
+    }
+}
+
+/// `xs:duration` in seconds
+fn duration(seconds: f64) -> String {
+    format!("PT{:.3}S", seconds)
+}
+
+/// `frameRate` as an integer or a ratio
+fn frame_rate(frame_rate: f64) -> String {
+    if frame_rate.fract() == 0.0 {
+        format!("{}", frame_rate as u32)
+    } else {
+        format!("{}/1000", (frame_rate * 1000.0).round() as u32)
+    }
+}
+
+/// `xs:dateTime` in UTC with millisecond precision, e.g. `2024-05-01T12:00:00.000Z`
+pub fn date_time(unix_ms: u64) -> String {
+    let seconds = unix_ms / 1000;
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day,
+        time / 3600, time % 3600 / 60, time % 60,
+        unix_ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_time() {
+        assert_eq!(date_time(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(date_time(951_825_600_123), "2000-02-29T12:00:00.123Z");
+        assert_eq!(date_time(1_792_108_800_000), "2026-10-16T00:00:00.000Z");
+    }
+}
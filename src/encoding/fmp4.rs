@@ -0,0 +1,716 @@
+//! Fragmented MP4 (CMAF) Muxer
+//!
+//! Packages each track on its own as an ISO BMFF init segment followed by
+//! one `styp`/`moof`/`mdat` fragment per 2-second segment. The same files
+//! serve HLS (`EXT-X-MAP`) and DASH (`SegmentTemplate`) playback.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{AudioCodec, EncodedAudioSegment, EncodedVideoSegment, VideoCodec, VIDEO_CLOCK_RATE};
+use super::webm::{av1_codec_configuration, strip_temporal_delimiters};
+
+const TRACK_ID: u32 = 1;
+
+/// `trun` flags
+const TRUN_DATA_OFFSET: u32 = 0x000001;
+const TRUN_SAMPLE_DURATION: u32 = 0x000100;
+const TRUN_SAMPLE_SIZE: u32 = 0x000200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x000400;
+const TRUN_COMPOSITION_OFFSET: u32 = 0x000800;
+
+/// `tfhd` flag making data offsets relative to the `moof`
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x020000;
+
+/// Sample flags for sync and non-sync samples
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// Identity transformation matrix used by `mvhd` and `tkhd`
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Samples per AAC frame
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+/// One fragment of a CMAF track
+#[derive(Debug, Clone)]
+pub struct CmafFragment {
+    /// Segment sequence number
+    pub sequence: u32,
+    /// Init segment, present on the first fragment of the track
+    pub init: Option<Vec<u8>>,
+    /// `styp`, `moof` and `mdat` boxes
+    pub data: Vec<u8>,
+}
+
+/// Sample described by a `trun` entry
+struct Sample {
+    duration: u32,
+    size: u32,
+    flags: u32,
+    composition_offset: i32,
+}
+
+/// CMAF packager for the video track
+#[derive(Debug, Default)]
+pub struct CmafVideoMuxer {
+    init_written: bool,
+}
+
+impl CmafVideoMuxer {
+    /// Create a packager that emits the init segment with its first fragment
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Package one encoded video segment
+    pub fn mux_segment(&mut self, segment: &EncodedVideoSegment) -> CaptureResult<CmafFragment> {
+        let init = if self.init_written {
+            None
+        } else {
+            Some(video_init_segment(segment)?)
+        };
+
+        let end_dts = segment.pts + (segment.duration * VIDEO_CLOCK_RATE as f64).round() as i64;
+        let mut samples = Vec::with_capacity(segment.frames.len());
+        let mut mdat = Vec::with_capacity(segment.data.len());
+
+        for (index, frame) in segment.frames.iter().enumerate() {
+            let data = &segment.data[frame.offset..frame.offset + frame.size];
+            let sample = match segment.codec {
+                VideoCodec::H264 | VideoCodec::H265 => length_prefixed_sample(data, segment.codec),
+                VideoCodec::AV1 => strip_temporal_delimiters(data),
+                VideoCodec::VP9 => data.to_vec(),
+            };
+            let next_dts = segment.frames.get(index + 1).map_or(end_dts, |next| next.dts);
+
+            samples.push(Sample {
+                duration: (next_dts - frame.dts).max(1) as u32,
+                size: sample.len() as u32,
+                flags: if frame.keyframe { SYNC_SAMPLE_FLAGS } else { NON_SYNC_SAMPLE_FLAGS },
+                composition_offset: (frame.pts - frame.dts) as i32,
+            });
+            mdat.extend_from_slice(&sample);
+        }
+
+        let base_media_decode_time = segment.frames.first().map_or(segment.pts, |frame| frame.dts).max(0) as u64;
+        let data = write_fragment(segment.sequence, base_media_decode_time, &samples, &mdat, true);
+
+        self.init_written = true;
+        Ok(CmafFragment { sequence: segment.sequence, init, data })
+    }
+}
+
+/// CMAF packager for the audio track
+#[derive(Debug, Default)]
+pub struct CmafAudioMuxer {
+    /// `OpusHead` of the stream, required for Opus audio
+    opus_head: Option<Vec<u8>>,
+    init_written: bool,
+}
+
+impl CmafAudioMuxer {
+    /// Create a packager; Opus tracks need the encoder's `OpusHead`
+    pub fn new(opus_head: Option<Vec<u8>>) -> Self {
+        Self { opus_head, init_written: false }
+    }
+
+    /// Package one encoded audio segment
+    pub fn mux_segment(&mut self, segment: &EncodedAudioSegment) -> CaptureResult<CmafFragment> {
+        let init = if self.init_written {
+            None
+        } else {
+            Some(audio_init_segment(segment, self.opus_head.as_deref())?)
+        };
+
+        // Every packet covers the same number of samples
+        let frame_duration = match segment.codec {
+            AudioCodec::AAC => AAC_FRAME_SAMPLES,
+            AudioCodec::Opus => segment.sample_rate / 50,
+        };
+
+        let mut samples = Vec::with_capacity(segment.frames.len());
+        let mut mdat = Vec::with_capacity(segment.data.len());
+        for frame in &segment.frames {
+            let data = &segment.data[frame.offset..frame.offset + frame.size];
+            let payload = match segment.codec {
+                // MP4 carries raw AAC frames without the ADTS header
+                AudioCodec::AAC => &data[adts_header_length(data).min(data.len())..],
+                AudioCodec::Opus => data,
+            };
+
+            samples.push(Sample {
+                duration: frame_duration,
+                size: payload.len() as u32,
+                flags: 0,
+                composition_offset: 0,
+            });
+            mdat.extend_from_slice(payload);
+        }
+
+        let base_media_decode_time = segment.frames.first().map_or(segment.pts, |frame| frame.pts).max(0) as u64;
+        let data = write_fragment(segment.sequence, base_media_decode_time, &samples, &mdat, false);
+
+        self.init_written = true;
+        Ok(CmafFragment { sequence: segment.sequence, init, data })
+    }
+}
+
+/// Build the video init segment from the parameter sets of the first segment
+fn video_init_segment(segment: &EncodedVideoSegment) -> CaptureResult<Vec<u8>> {
+    let (width, height) = segment.resolution;
+    let codec = segment.codec;
+
+    let configuration = match codec {
+        VideoCodec::H264 => avc_configuration(&segment.data),
+        VideoCodec::H265 => hevc_configuration(&segment.data),
+        VideoCodec::VP9 => Some(vp9_configuration()),
+        VideoCodec::AV1 => av1_codec_configuration(&segment.data),
+    }
+    .ok_or_else(|| {
+        CaptureError::EncodingError(format!("{:?} segment {} has no codec configuration", codec, segment.sequence))
+    })?;
+
+    let configuration_box: &[u8; 4] = match codec {
+        VideoCodec::H264 => b"avcC",
+        VideoCodec::H265 => b"hvcC",
+        VideoCodec::VP9 => b"vpcC",
+        VideoCodec::AV1 => b"av1C",
+    };
+    let mut sample_entry_type = [0u8; 4];
+    sample_entry_type.copy_from_slice(codec.sample_entry().as_bytes());
+
+    Ok(write_init_segment(
+        b"vide",
+        VIDEO_CLOCK_RATE as u32,
+        Some((width, height)),
+        |out| {
+            write_box(out, &sample_entry_type, |out| {
+                out.extend_from_slice(&[0; 6]); // Reserved
+                out.extend_from_slice(&1u16.to_be_bytes()); // Data reference index
+                out.extend_from_slice(&[0; 16]); // Pre-defined and reserved
+                out.extend_from_slice(&(width as u16).to_be_bytes());
+                out.extend_from_slice(&(height as u16).to_be_bytes());
+                out.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // 72 dpi
+                out.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+                out.extend_from_slice(&0u32.to_be_bytes()); // Reserved
+                out.extend_from_slice(&1u16.to_be_bytes()); // Frame count
+                out.extend_from_slice(&[0; 32]); // Compressor name
+                out.extend_from_slice(&0x0018u16.to_be_bytes()); // Depth
+                out.extend_from_slice(&(-1i16).to_be_bytes());
+                write_box(out, configuration_box, |out| out.extend_from_slice(&configuration));
+            });
+        },
+    ))
+}
+
+/// Build the audio init segment
+fn audio_init_segment(segment: &EncodedAudioSegment, opus_head: Option<&[u8]>) -> CaptureResult<Vec<u8>> {
+    let channels = segment.channels;
+    let sample_rate = segment.sample_rate;
+
+    let (entry_type, configuration_box, configuration): (&[u8; 4], &[u8; 4], Vec<u8>) = match segment.codec {
+        AudioCodec::AAC => {
+            let first = segment.frames.first()
+                .map(|frame| &segment.data[frame.offset..frame.offset + frame.size])
+                .unwrap_or(&segment.data);
+            let audio_specific_config = adts_audio_specific_config(first).ok_or_else(|| {
+                CaptureError::EncodingError(format!("Audio segment {} has no ADTS header", segment.sequence))
+            })?;
+            (b"mp4a", b"esds", es_descriptor(&audio_specific_config))
+        }
+        AudioCodec::Opus => {
+            let opus_head = opus_head.ok_or_else(|| {
+                CaptureError::EncodingError("Opus tracks need an OpusHead to build the init segment".to_string())
+            })?;
+            let specific_box = opus_specific_box(opus_head)
+                .ok_or_else(|| CaptureError::EncodingError("Invalid OpusHead".to_string()))?;
+            (b"Opus", b"dOps", specific_box)
+        }
+    };
+
+    Ok(write_init_segment(b"soun", sample_rate, None, |out| {
+        write_box(out, entry_type, |out| {
+            out.extend_from_slice(&[0; 6]); // Reserved
+            out.extend_from_slice(&1u16.to_be_bytes()); // Data reference index
+            out.extend_from_slice(&[0; 8]); // Reserved
+            out.extend_from_slice(&channels.to_be_bytes());
+            out.extend_from_slice(&16u16.to_be_bytes()); // Sample size
+            out.extend_from_slice(&[0; 4]); // Pre-defined and reserved
+            // Opus always reports 48kHz; the 16.16 field cannot hold larger rates
+            let entry_rate = if matches!(segment.codec, AudioCodec::Opus) { 48000 } else { sample_rate.min(0xFFFF) };
+            out.extend_from_slice(&(entry_rate << 16).to_be_bytes());
+
+            match segment.codec {
+                AudioCodec::AAC => write_full_box(out, configuration_box, 0, 0, |out| out.extend_from_slice(&configuration)),
+                AudioCodec::Opus => write_box(out, configuration_box, |out| out.extend_from_slice(&configuration)),
+            }
+        });
+    }))
+}
+
+/// Write `ftyp` and a single-track `moov`
+fn write_init_segment(
+    handler: &[u8; 4],
+    timescale: u32,
+    dimensions: Option<(u32, u32)>,
+    sample_entry: impl FnOnce(&mut Vec<u8>),
+) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    write_box(&mut out, b"ftyp", |out| {
+        out.extend_from_slice(b"iso6");
+        out.extend_from_slice(&0u32.to_be_bytes());
+        for brand in [b"iso6", b"cmfc", b"dash"] {
+            out.extend_from_slice(brand);
+        }
+    });
+
+    write_box(&mut out, b"moov", |out| {
+        write_full_box(out, b"mvhd", 0, 0, |out| {
+            out.extend_from_slice(&[0; 8]); // Creation and modification time
+            out.extend_from_slice(&1000u32.to_be_bytes());
+            out.extend_from_slice(&0u32.to_be_bytes()); // Duration lives in the fragments
+            out.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // Rate
+            out.extend_from_slice(&0x0100u16.to_be_bytes()); // Volume
+            out.extend_from_slice(&[0; 10]); // Reserved
+            write_matrix(out);
+            out.extend_from_slice(&[0; 24]); // Pre-defined
+            out.extend_from_slice(&(TRACK_ID + 1).to_be_bytes()); // Next track ID
+        });
+
+        write_box(out, b"trak", |out| {
+            // Track enabled and in movie
+            write_full_box(out, b"tkhd", 0, 0x000003, |out| {
+                out.extend_from_slice(&[0; 8]); // Creation and modification time
+                out.extend_from_slice(&TRACK_ID.to_be_bytes());
+                out.extend_from_slice(&[0; 4]); // Reserved
+                out.extend_from_slice(&0u32.to_be_bytes()); // Duration
+                out.extend_from_slice(&[0; 8]); // Reserved
+                out.extend_from_slice(&[0; 4]); // Layer and alternate group
+                let volume: u16 = if dimensions.is_none() { 0x0100 } else { 0 };
+                out.extend_from_slice(&volume.to_be_bytes());
+                out.extend_from_slice(&[0; 2]); // Reserved
+                write_matrix(out);
+                let (width, height) = dimensions.unwrap_or((0, 0));
+                out.extend_from_slice(&(width << 16).to_be_bytes());
+                out.extend_from_slice(&(height << 16).to_be_bytes());
+            });
+
+            write_box(out, b"mdia", |out| {
+                write_full_box(out, b"mdhd", 0, 0, |out| {
+                    out.extend_from_slice(&[0; 8]); // Creation and modification time
+                    out.extend_from_slice(&timescale.to_be_bytes());
+                    out.extend_from_slice(&0u32.to_be_bytes()); // Duration
+                    out.extend_from_slice(&0x55C4u16.to_be_bytes()); // "und"
+                    out.extend_from_slice(&[0; 2]); // Pre-defined
+                });
+
+                write_full_box(out, b"hdlr", 0, 0, |out| {
+                    out.extend_from_slice(&[0; 4]); // Pre-defined
+                    out.extend_from_slice(handler);
+                    out.extend_from_slice(&[0; 12]); // Reserved
+                    let name: &[u8] = if dimensions.is_some() { b"VideoHandler\0" } else { b"SoundHandler\0" };
+                    out.extend_from_slice(name);
+                });
+
+                write_box(out, b"minf", |out| {
+                    if dimensions.is_some() {
+                        write_full_box(out, b"vmhd", 0, 0x000001, |out| out.extend_from_slice(&[0; 8]));
+                    } else {
+                        write_full_box(out, b"smhd", 0, 0, |out| out.extend_from_slice(&[0; 4]));
+                    }
+
+                    write_box(out, b"dinf", |out| {
+                        write_full_box(out, b"dref", 0, 0, |out| {
+                            out.extend_from_slice(&1u32.to_be_bytes());
+                            // Media data is in the same file
+                            write_full_box(out, b"url ", 0, 0x000001, |_| {});
+                        });
+                    });
+
+                    write_box(out, b"stbl", |out| {
+                        write_full_box(out, b"stsd", 0, 0, |out| {
+                            out.extend_from_slice(&1u32.to_be_bytes());
+                            sample_entry(out);
+                        });
+                        // Sample tables are empty; samples are described by `trun`
+                        write_full_box(out, b"stts", 0, 0, |out| out.extend_from_slice(&0u32.to_be_bytes()));
+                        write_full_box(out, b"stsc", 0, 0, |out| out.extend_from_slice(&0u32.to_be_bytes()));
+                        write_full_box(out, b"stsz", 0, 0, |out| out.extend_from_slice(&[0; 8]));
+                        write_full_box(out, b"stco", 0, 0, |out| out.extend_from_slice(&0u32.to_be_bytes()));
+                    });
+                });
+            });
+        });
+
+        write_box(out, b"mvex", |out| {
+            write_full_box(out, b"trex", 0, 0, |out| {
+                out.extend_from_slice(&TRACK_ID.to_be_bytes());
+                out.extend_from_slice(&1u32.to_be_bytes()); // Default sample description index
+                out.extend_from_slice(&[0; 12]); // Default duration, size and flags
+            });
+        });
+    });
+
+    out
+}
+
+/// Write `styp`, `moof` and `mdat` for one fragment
+fn write_fragment(sequence: u32, base_media_decode_time: u64, samples: &[Sample], mdat: &[u8], video: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(mdat.len() + samples.len() * 16 + 256);
+
+    write_box(&mut out, b"styp", |out| {
+        out.extend_from_slice(b"msdh");
+        out.extend_from_slice(&0u32.to_be_bytes());
+        for brand in [b"msdh", b"cmfs"] {
+            out.extend_from_slice(brand);
+        }
+    });
+
+    let mut trun_flags = TRUN_DATA_OFFSET | TRUN_SAMPLE_DURATION | TRUN_SAMPLE_SIZE;
+    if video {
+        trun_flags |= TRUN_SAMPLE_FLAGS | TRUN_COMPOSITION_OFFSET;
+    }
+
+    let moof_start = out.len();
+    let mut data_offset_position = 0;
+    write_box(&mut out, b"moof", |out| {
+        // Sequence numbers start at 1
+        write_full_box(out, b"mfhd", 0, 0, |out| out.extend_from_slice(&(sequence + 1).to_be_bytes()));
+
+        write_box(out, b"traf", |out| {
+            write_full_box(out, b"tfhd", 0, TFHD_DEFAULT_BASE_IS_MOOF, |out| {
+                out.extend_from_slice(&TRACK_ID.to_be_bytes());
+            });
+            write_full_box(out, b"tfdt", 1, 0, |out| out.extend_from_slice(&base_media_decode_time.to_be_bytes()));
+
+            // Version 1 allows negative composition offsets
+            write_full_box(out, b"trun", 1, trun_flags, |out| {
+                out.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+                data_offset_position = out.len();
+                out.extend_from_slice(&0i32.to_be_bytes());
+                for sample in samples {
+                    out.extend_from_slice(&sample.duration.to_be_bytes());
+                    out.extend_from_slice(&sample.size.to_be_bytes());
+                    if video {
+                        out.extend_from_slice(&sample.flags.to_be_bytes());
+                        out.extend_from_slice(&sample.composition_offset.to_be_bytes());
+                    }
+                }
+            });
+        });
+    });
+
+    // Sample data starts right after the mdat header
+    let data_offset = (out.len() - moof_start + 8) as i32;
+    out[data_offset_position..data_offset_position + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+    write_box(&mut out, b"mdat", |out| out.extend_from_slice(mdat));
+    out
+}
+
+/// Write a box, filling in its size once the contents are known
+fn write_box(out: &mut Vec<u8>, box_type: &[u8; 4], contents: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(box_type);
+    contents(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Write a box with a version and flags header
+fn write_full_box(out: &mut Vec<u8>, box_type: &[u8; 4], version: u8, flags: u32, contents: impl FnOnce(&mut Vec<u8>)) {
+    write_box(out, box_type, |out| {
+        out.extend_from_slice(&(((version as u32) << 24) | (flags & 0x00FF_FFFF)).to_be_bytes());
+        contents(out);
+    });
+}
+
+fn write_matrix(out: &mut Vec<u8>) {
+    for value in UNITY_MATRIX {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Split an Annex-B byte stream into NAL units
+fn split_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut position = 0;
+
+    while position + 3 <= data.len() {
+        if data[position] == 0 && data[position + 1] == 0 && data[position + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&data[start..position]));
+            }
+            position += 3;
+            start = Some(position);
+        } else {
+            position += 1;
+        }
+    }
+    if let Some(start) = start {
+        units.push(&data[start..]);
+    }
+
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+/// Drop the leading zero of a following 4-byte start code
+fn trim_trailing_zeros(unit: &[u8]) -> &[u8] {
+    let end = unit.iter().rposition(|&byte| byte != 0).map_or(0, |index| index + 1);
+    &unit[..end]
+}
+
+fn nal_unit_type(unit: &[u8], codec: VideoCodec) -> u8 {
+    match codec {
+        VideoCodec::H265 => (unit[0] >> 1) & 0x3F,
+        _ => unit[0] & 0x1F,
+    }
+}
+
+/// Whether a NAL unit belongs in the sample entry rather than the sample
+fn is_out_of_band(nal_type: u8, codec: VideoCodec) -> bool {
+    match codec {
+        // VPS, SPS, PPS and access unit delimiters
+        VideoCodec::H265 => matches!(nal_type, 32..=35),
+        // SPS, PPS and access unit delimiters
+        _ => matches!(nal_type, 7..=9),
+    }
+}
+
+/// Convert an Annex-B access unit into 4-byte length-prefixed NAL units
+fn length_prefixed_sample(data: &[u8], codec: VideoCodec) -> Vec<u8> {
+    let mut sample = Vec::with_capacity(data.len());
+    for unit in split_nal_units(data) {
+        if is_out_of_band(nal_unit_type(unit, codec), codec) {
+            continue;
+        }
+        sample.extend_from_slice(&(unit.len() as u32).to_be_bytes());
+        sample.extend_from_slice(unit);
+    }
+    sample
+}
+
+/// First NAL unit of the given type
+fn find_nal_unit(units: &[&[u8]], codec: VideoCodec, nal_type: u8) -> Option<Vec<u8>> {
+    units.iter().find(|unit| nal_unit_type(unit, codec) == nal_type).map(|unit| unit.to_vec())
+}
+
+/// Build an `avcC` record from the in-band SPS and PPS
+fn avc_configuration(data: &[u8]) -> Option<Vec<u8>> {
+    let units = split_nal_units(data);
+    let sps = find_nal_unit(&units, VideoCodec::H264, 7)?;
+    let pps = find_nal_unit(&units, VideoCodec::H264, 8)?;
+    if sps.len() < 4 {
+        return None;
+    }
+
+    let mut configuration = vec![
+        1, // Configuration version
+        sps[1], // Profile
+        sps[2], // Profile compatibility
+        sps[3], // Level
+        0xFF, // 4-byte NAL unit lengths
+        0xE1, // One SPS
+    ];
+    configuration.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    configuration.extend_from_slice(&sps);
+    configuration.push(1); // One PPS
+    configuration.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    configuration.extend_from_slice(&pps);
+
+    // High profiles also describe the chroma format and bit depth; the
+    // encoder always produces 8-bit 4:2:0
+    if matches!(sps[1], 100 | 110 | 122 | 144) {
+        configuration.extend_from_slice(&[0xFD, 0xF8, 0xF8, 0x00]);
+    }
+    Some(configuration)
+}
+
+/// Build an `hvcC` record from the in-band VPS, SPS and PPS
+fn hevc_configuration(data: &[u8]) -> Option<Vec<u8>> {
+    let units = split_nal_units(data);
+    let vps = find_nal_unit(&units, VideoCodec::H265, 32)?;
+    let sps = find_nal_unit(&units, VideoCodec::H265, 33)?;
+    let pps = find_nal_unit(&units, VideoCodec::H265, 34)?;
+
+    // profile_tier_level starts right after the first SPS byte
+    let rbsp = remove_emulation_prevention(sps.get(2..)?);
+    let profile_tier_level = rbsp.get(1..13)?;
+    let max_sub_layers = ((rbsp[0] >> 1) & 0x07) + 1;
+    let temporal_id_nested = rbsp[0] & 0x01;
+
+    let mut configuration = vec![1]; // Configuration version
+    configuration.extend_from_slice(profile_tier_level);
+    configuration.extend_from_slice(&[
+        0xF0, 0x00, // No minimum spatial segmentation
+        0xFC, // Unknown parallelism
+        0xFD, // 4:2:0
+        0xF8, // 8-bit luma
+        0xF8, // 8-bit chroma
+        0x00, 0x00, // Unspecified average frame rate
+        (max_sub_layers << 3) | (temporal_id_nested << 2) | 0x03,
+        3, // Parameter set arrays
+    ]);
+    for (nal_type, unit) in [(32u8, &vps), (33, &sps), (34, &pps)] {
+        // Parameter sets never appear in-band in `hvc1` samples
+        configuration.push(0x80 | nal_type);
+        configuration.extend_from_slice(&1u16.to_be_bytes());
+        configuration.extend_from_slice(&(unit.len() as u16).to_be_bytes());
+        configuration.extend_from_slice(unit);
+    }
+    Some(configuration)
+}
+
+/// Strip emulation prevention bytes from a NAL unit payload
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+/// `vpcC` contents for the 8-bit 4:2:0 BT.709 streams libvpx produces
+fn vp9_configuration() -> Vec<u8> {
+    vec![
+        1, 0, 0, 0, // Version 1, no flags
+        0, // Profile 0
+        40, // Level 4.0
+        0x82, // 8-bit, 4:2:0 colocated, limited range
+        1, 1, 1, // BT.709 primaries, transfer and matrix
+        0, 0, // No codec initialization data
+    ]
+}
+
+/// Length of the ADTS header in front of an AAC frame
+fn adts_header_length(frame: &[u8]) -> usize {
+    match frame.get(1) {
+        // CRC present when protection_absent is clear
+        Some(byte) if byte & 0x01 == 0 => 9,
+        _ => 7,
+    }
+}
+
+/// AudioSpecificConfig derived from an ADTS header
+fn adts_audio_specific_config(frame: &[u8]) -> Option<[u8; 2]> {
+    if frame.len() < 7 || frame[0] != 0xFF || frame[1] & 0xF0 != 0xF0 {
+        return None;
+    }
+
+    let object_type = ((frame[2] >> 6) & 0x03) as u16 + 1;
+    let frequency_index = ((frame[2] >> 2) & 0x0F) as u16;
+    let channel_configuration = (((frame[2] & 0x01) << 2) | (frame[3] >> 6)) as u16;
+
+    let config = (object_type << 11) | (frequency_index << 7) | (channel_configuration << 3);
+    Some(config.to_be_bytes())
+}
+
+/// `esds` ES_Descriptor carrying the AudioSpecificConfig
+fn es_descriptor(audio_specific_config: &[u8]) -> Vec<u8> {
+    let mut decoder_specific_info = vec![0x05, audio_specific_config.len() as u8];
+    decoder_specific_info.extend_from_slice(audio_specific_config);
+
+    let mut decoder_config = vec![
+        0x40, // MPEG-4 audio
+        0x15, // Audio stream
+        0x00, 0x00, 0x00, // Buffer size
+    ];
+    decoder_config.extend_from_slice(&0u32.to_be_bytes()); // Max bitrate
+    decoder_config.extend_from_slice(&0u32.to_be_bytes()); // Average bitrate
+    decoder_config.extend_from_slice(&decoder_specific_info);
+
+    let mut es = vec![0x00, TRACK_ID as u8, 0x00]; // ES_ID and flags
+    es.push(0x04);
+    es.push(decoder_config.len() as u8);
+    es.extend_from_slice(&decoder_config);
+    es.extend_from_slice(&[0x06, 0x01, 0x02]); // SLConfigDescriptor
+
+    let mut descriptor = vec![0x03, es.len() as u8];
+    descriptor.extend_from_slice(&es);
+    descriptor
+}
+
+/// `dOps` contents converted from an `OpusHead` header
+fn opus_specific_box(opus_head: &[u8]) -> Option<Vec<u8>> {
+    if opus_head.len() < 19 || &opus_head[..8] != b"OpusHead" {
+        return None;
+    }
+
+    let pre_skip = u16::from_le_bytes([opus_head[10], opus_head[11]]);
+    let input_sample_rate = u32::from_le_bytes([opus_head[12], opus_head[13], opus_head[14], opus_head[15]]);
+    let output_gain = i16::from_le_bytes([opus_head[16], opus_head[17]]);
+
+    // Same fields as OpusHead, but big-endian and with version 0
+    let mut specific = vec![0, opus_head[9]];
+    specific.extend_from_slice(&pre_skip.to_be_bytes());
+    specific.extend_from_slice(&input_sample_rate.to_be_bytes());
+    specific.extend_from_slice(&output_gain.to_be_bytes());
+    specific.push(opus_head[18]);
+    Some(specific)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::EncodedAudioFrame;
+
+    #[test]
+    fn test_length_prefixed_sample() {
+        let access_unit = [
+            0x00, 0x00, 0x00, 0x01, 0x09, 0xF0, // AUD
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x28, // SPS
+            0x00, 0x00, 0x01, 0x65, 0x88, 0x84, // IDR slice
+        ];
+
+        let sample = length_prefixed_sample(&access_unit, VideoCodec::H264);
+        assert_eq!(sample, vec![0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x84]);
+    }
+
+    #[test]
+    fn test_audio_fragment_layout() {
+        // Two ADTS frames: AAC LC, 48kHz, stereo, 3-byte payloads
+        let header = [0xFF, 0xF1, 0x4C, 0x80, 0x01, 0x5F, 0xFC];
+        let mut data = Vec::new();
+        for payload in [[1, 2, 3], [4, 5, 6]] {
+            data.extend_from_slice(&header);
+            data.extend_from_slice(&payload);
+        }
+        let segment = EncodedAudioSegment {
+            data,
+            codec: AudioCodec::AAC,
+            sequence: 0,
+            duration: 2048.0 / 48000.0,
+            timestamp: 0,
+            sample_rate: 48000,
+            channels: 2,
+            pts: 0,
+            frames: vec![
+                EncodedAudioFrame { offset: 0, size: 10, pts: 0 },
+                EncodedAudioFrame { offset: 10, size: 10, pts: 1024 },
+            ],
+        };
+
+        let fragment = CmafAudioMuxer::new(None).mux_segment(&segment).unwrap();
+        let init = fragment.init.expect("first fragment carries the init segment");
+        assert_eq!(&init[4..8], b"ftyp");
+
+        let data = fragment.data;
+        let styp_size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        assert_eq!(&data[styp_size + 4..styp_size + 8], b"moof");
+        let moof_size = u32::from_be_bytes(data[styp_size..styp_size + 4].try_into().unwrap()) as usize;
+
+        // The mdat holds both frames without their ADTS headers
+        let mdat_start = styp_size + moof_size;
+        assert_eq!(&data[mdat_start + 4..mdat_start + 8], b"mdat");
+        assert_eq!(&data[mdat_start + 8..], &[1, 2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Local Filesystem Sink
+//!
+//! Writes segments and playlists under an output directory using the same
+//! layout as the S3 keys, for recordings that never leave the machine.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{S3ContentType, object_key};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Filesystem sink rooted at `OutputFormat.output_dir`
+#[derive(Debug, Clone)]
+pub struct FileSystemSink {
+    root: PathBuf,
+    user_id: String,
+    video_id: String,
+}
+
+impl FileSystemSink {
+    /// Create a sink writing below `root`
+    pub fn new(root: impl Into<PathBuf>, user_id: String, video_id: String) -> Self {
+        Self {
+            root: root.into(),
+            user_id,
+            video_id,
+        }
+    }
+
+    /// Directory all files are written below
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Relative key of a segment or playlist, identical to its S3 key
+    pub fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        object_key(&self.user_id, &self.video_id, content_type, sequence)
+    }
+
+    /// Absolute path of a segment or playlist
+    pub fn path(&self, content_type: &S3ContentType, sequence: u32) -> PathBuf {
+        self.root.join(self.object_key(content_type, sequence))
+    }
+
+    /// Write packaged segment data, returning its key
+    pub async fn write_segment_data(&self, content_type: &S3ContentType, sequence: u32, data: &[u8]) -> CaptureResult<String> {
+        let key = self.write_object(self.object_key(content_type, sequence), data).await?;
+
+        log::debug!("Wrote {:?} {} to {}", content_type, sequence, key);
+        Ok(key)
+    }
+
+    /// Write data under an explicit key below the root
+    pub async fn write_object(&self, key: String, data: &[u8]) -> CaptureResult<String> {
+        write_atomic(&self.root.join(&key), data).await?;
+        Ok(key)
+    }
+
+    /// Replace a playlist, returning its key
+    pub async fn update_playlist(&self, playlist_content: String, content_type: S3ContentType) -> CaptureResult<String> {
+        if !content_type.is_playlist() {
+            return Err(CaptureError::Config("Invalid playlist content type".to_string()));
+        }
+        let key = self.object_key(&content_type, 0);
+
+        write_atomic(&self.root.join(&key), playlist_content.as_bytes()).await?;
+
+        log::debug!("Updated playlist: {}", key);
+        Ok(key)
+    }
+
+    /// Remove a stored file; missing files are not an error
+    pub async fn remove(&self, content_type: &S3ContentType, sequence: u32) -> CaptureResult<String> {
+        let key = self.object_key(content_type, sequence);
+        match tokio::fs::remove_file(self.root.join(&key)).await {
+            Ok(()) => Ok(key),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(key),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Write through a temporary file and rename it into place, so readers
+/// never observe a partially written file
+///
+/// Every write gets its own temporary file, so concurrent writes of the same
+/// path cannot clobber each other's data; the last rename wins.
+pub(super) async fn write_atomic(path: &Path, data: &[u8]) -> CaptureResult<()> {
+    let parent = path.parent()
+        .ok_or_else(|| CaptureError::Config(format!("Invalid output path: {}", path.display())))?;
+    tokio::fs::create_dir_all(parent).await?;
+
+    let file_name = path.file_name()
+        .ok_or_else(|| CaptureError::Config(format!("Invalid output path: {}", path.display())))?;
+    let temp_path = parent.join(format!(".{}.{}.tmp", file_name.to_string_lossy(), Uuid::new_v4()));
+
+    tokio::fs::write(&temp_path, data).await?;
+    if let Err(e) = tokio::fs::rename(&temp_path, path).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_writes_use_s3_layout() {
+        let root = std::env::temp_dir().join(format!("cap-fs-sink-{}", uuid::Uuid::new_v4()));
+        let sink = FileSystemSink::new(&root, "user".to_string(), "video".to_string());
+
+        let key = sink.write_segment_data(&S3ContentType::AudioSegment, 3, &[1, 2, 3]).await.unwrap();
+        assert_eq!(key, "user/video/audio/audio_recording_3.aac");
+        assert_eq!(std::fs::read(root.join(&key)).unwrap(), vec![1, 2, 3]);
+
+        sink.update_playlist("#EXTM3U\n".to_string(), S3ContentType::MasterPlaylist).await.unwrap();
+        sink.update_playlist("#EXTM3U\n#EXT-X-VERSION:3\n".to_string(), S3ContentType::MasterPlaylist).await.unwrap();
+        let playlist = std::fs::read_to_string(root.join("user/video/stream.m3u8")).unwrap();
+        assert_eq!(playlist, "#EXTM3U\n#EXT-X-VERSION:3\n");
+
+        // Concurrent writes of one path each use their own temporary file
+        let path = root.join("user/video/stream.m3u8");
+        let writes = (0..8u8).map(|index| {
+            let path = path.clone();
+            tokio::spawn(async move { write_atomic(&path, &[index; 1024]).await })
+        }).collect::<Vec<_>>();
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.len() == 1024 && data.iter().all(|byte| *byte == data[0]));
+
+        // No temporary files are left behind
+        let entries = std::fs::read_dir(root.join("user/video")).unwrap();
+        assert!(entries.map(|entry| entry.unwrap().file_name()).all(|name| !name.to_string_lossy().ends_with(".tmp")));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -2,8 +2,10 @@
 //! 
 //! Implements Cap's HLS streaming approach with real-time segment management
 
-use crate::error::CaptureResult;
-use super::{HLSConfig, EncodedAudioSegment, EncodedVideoSegment};
+use crate::config::Resolution;
+use crate::error::{CaptureError, CaptureResult};
+use super::{HLSConfig, EncodedAudioSegment, EncodedVideoSegment, EncodingConfig, DashManifest, MpdType};
+use super::dash::date_time;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -27,6 +29,12 @@ pub struct HLSSegment {
     pub video_size: usize,
     /// Byte size of audio data
     pub audio_size: usize,
+    /// Wall-clock time of the segment's first sample in Unix milliseconds (`EXT-X-PROGRAM-DATE-TIME`)
+    #[serde(default)]
+    pub program_date_time: u64,
+    /// Whether the segment is the first after a pause (`EXT-X-DISCONTINUITY`)
+    #[serde(default)]
+    pub discontinuity: bool,
 }
 
 /// HLS playlist following Cap's structure
@@ -38,10 +46,114 @@ pub struct HLSPlaylist {
     pub target_duration: u32,
     /// Media sequence number
     pub media_sequence: u32,
+    /// Discontinuities before the first listed segment (`EXT-X-DISCONTINUITY-SEQUENCE`)
+    #[serde(default)]
+    pub discontinuity_sequence: u32,
     /// List of segments
     pub segments: Vec<HLSSegment>,
     /// Whether the playlist is complete
     pub end_list: bool,
+    /// `EXT-X-PLAYLIST-TYPE`, unset for the live sliding window
+    #[serde(default)]
+    pub playlist_type: Option<MediaPlaylistType>,
+    /// Partial segments at the live edge, with LL-HLS enabled
+    #[serde(default)]
+    pub parts: Option<PartialSegments>,
+}
+
+/// Partial segment listed with `EXT-X-PART`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HLSPart {
+    /// Media sequence number of the segment the part belongs to
+    pub segment: u32,
+    /// Position of the part within its segment
+    pub index: u32,
+    /// Running part number of the track, used in the part's object key
+    pub number: u32,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Whether the part starts with a keyframe
+    pub independent: bool,
+}
+
+/// LL-HLS parts of the segments near the live edge, per track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialSegments {
+    /// `PART-TARGET` in seconds
+    pub part_target: f64,
+    /// Whether the origin supports blocking playlist reloads
+    pub blocking_reload: bool,
+    pub video: Vec<HLSPart>,
+    pub audio: Vec<HLSPart>,
+}
+
+/// Value of `EXT-X-PLAYLIST-TYPE`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum MediaPlaylistType {
+    /// Segments are only appended while the recording runs
+    Event,
+    /// The recording has finished and the playlist will not change
+    Vod,
+}
+
+/// Properties of the encoded streams advertised in the master playlist
+#[derive(Debug, Clone)]
+pub struct StreamInfo {
+    /// RFC 6381 codec string of the video, `None` for audio-only recordings
+    pub video_codecs: Option<String>,
+    pub resolution: Resolution,
+    pub frame_rate: f64,
+    /// Configured video bitrate, used until segments have been measured
+    pub video_bitrate: u32,
+    /// RFC 6381 codec string of the audio, `None` for video-only recordings
+    pub audio_codecs: Option<String>,
+    /// Configured audio bitrate, used until segments have been measured
+    pub audio_bitrate: u32,
+    pub audio_channels: u16,
+    pub audio_sample_rate: u32,
+}
+
+impl StreamInfo {
+    /// Stream properties of an encoding configuration
+    pub fn from_config(config: &EncodingConfig, has_video: bool, has_audio: bool) -> Self {
+        Self {
+            video_codecs: has_video.then(|| config.video.codecs_attribute()),
+            resolution: config.video.resolution,
+            frame_rate: config.video.frame_rate.as_f64(),
+            video_bitrate: config.video.bitrate,
+            audio_codecs: has_audio.then(|| config.audio.codec.codecs_attribute().to_string()),
+            audio_bitrate: config.audio.bitrate,
+            audio_channels: config.audio.channels,
+            audio_sample_rate: config.audio.sample_rate,
+        }
+    }
+}
+
+/// Peak and average bitrate of the segments of one track
+#[derive(Debug, Clone, Copy, Default)]
+struct BitrateMeter {
+    bytes: u64,
+    duration: f64,
+    peak: f64,
+}
+
+impl BitrateMeter {
+    fn add(&mut self, bytes: usize, duration: f64) {
+        if bytes == 0 || duration <= 0.0 {
+            return;
+        }
+        self.bytes += bytes as u64;
+        self.duration += duration;
+        self.peak = self.peak.max(bytes as f64 * 8.0 / duration);
+    }
+
+    /// Peak and average bits per second, or `fallback` for both before the first segment
+    fn bitrates(&self, fallback: u32) -> (u64, u64) {
+        if self.duration <= 0.0 {
+            return (fallback as u64, fallback as u64);
+        }
+        (self.peak.ceil() as u64, (self.bytes as f64 * 8.0 / self.duration).ceil() as u64)
+    }
 }
 
 /// HLS segmenter following Cap's approach
@@ -49,6 +161,36 @@ pub struct HLSPlaylist {
 pub struct HLSSegmenter {
     config: HLSConfig,
     segments: VecDeque<HLSSegment>,
+    /// Segments that left the window and await deletion (live-only retention)
+    evicted: Vec<HLSSegment>,
+    /// Every segment of the recording for the archive playlists; empty with live-only retention
+    history: Vec<HLSSegment>,
+    /// Set once the recording has stopped
+    ended: bool,
+    /// Wall-clock start of the recording in Unix milliseconds
+    start_time: u64,
+    /// Media time at which the next segment starts, in seconds
+    media_time: f64,
+    /// Pauses not yet reached by a segment, as media time and length in milliseconds
+    pauses: VecDeque<(f64, u64)>,
+    /// Length of the pauses before the next segment, in milliseconds
+    paused_time: u64,
+    /// Length of every pause so far, in milliseconds
+    total_paused_time: u64,
+    /// Discontinuities that left the live window
+    discontinuity_sequence: u32,
+    /// Segments taken out of the playlists because a sink rejected them
+    removed: Vec<u32>,
+    /// Whether the next segment follows a removed one and starts a discontinuity
+    gap: bool,
+    /// LL-HLS parts of the last few segments and of the segment being encoded
+    video_parts: VecDeque<HLSPart>,
+    audio_parts: VecDeque<HLSPart>,
+    /// Parts dropped from the playlists, whose objects can be deleted
+    expired_parts: Vec<(S3ContentType, u32)>,
+    stream_info: StreamInfo,
+    video_bitrate: BitrateMeter,
+    audio_bitrate: BitrateMeter,
     sequence_counter: u32,
     user_id: String,
     video_id: String,
@@ -60,53 +202,129 @@ impl HLSSegmenter {
         Self {
             config,
             segments: VecDeque::new(),
+            evicted: Vec::new(),
+            history: Vec::new(),
+            ended: false,
+            start_time: now_millis(),
+            media_time: 0.0,
+            pauses: VecDeque::new(),
+            paused_time: 0,
+            total_paused_time: 0,
+            discontinuity_sequence: 0,
+            removed: Vec::new(),
+            gap: false,
+            video_parts: VecDeque::new(),
+            audio_parts: VecDeque::new(),
+            expired_parts: Vec::new(),
+            stream_info: StreamInfo::from_config(&EncodingConfig::default(), true, true),
+            video_bitrate: BitrateMeter::default(),
+            audio_bitrate: BitrateMeter::default(),
             sequence_counter: 0,
             user_id,
             video_id,
         }
     }
 
+    /// Describe the encoded streams for the master playlist
+    pub fn set_stream_info(&mut self, stream_info: StreamInfo) {
+        self.stream_info = stream_info;
+    }
+
+    /// Set when the first segment started, in Unix milliseconds; defaults to when the segmenter was created
+    pub fn set_start_time(&mut self, start_time: u64) {
+        self.start_time = start_time;
+    }
+
+    /// Record a pause of `paused_ms` at `media_time` seconds into the recording
+    ///
+    /// Media timestamps continue across a pause, so the segment being encoded
+    /// runs on with what is captured after resuming; the next segment starts
+    /// with `EXT-X-DISCONTINUITY` and a program date-time that skips the pause.
+    pub fn add_discontinuity(&mut self, media_time: f64, paused_ms: u64) {
+        self.pauses.push_back((media_time, paused_ms));
+        self.total_paused_time += paused_ms;
+    }
+
     /// Create HLS segment from encoded audio and video data
     pub fn create_hls_segment(
         &mut self,
-        audio_segment: EncodedAudioSegment,
+        audio_segment: Option<EncodedAudioSegment>,
         video_segment: Option<EncodedVideoSegment>,
     ) -> CaptureResult<HLSSegment> {
-        let duration = audio_segment.duration.max(
+        if audio_segment.is_none() && video_segment.is_none() {
+            return Err(CaptureError::HLS("Segment has neither audio nor video".to_string()));
+        }
+        let duration = audio_segment.as_ref().map(|a| a.duration).unwrap_or(0.0).max(
             video_segment.as_ref().map(|v| v.duration).unwrap_or(0.0)
         );
+        let (video_extension, audio_extension) = if self.config.is_cmaf() { ("m4s", "m4s") } else { ("ts", "aac") };
+
+        let mut discontinuity = std::mem::take(&mut self.gap);
+        while let Some((_, paused_ms)) = self.pauses.front().filter(|(media_time, _)| *media_time <= self.media_time) {
+            self.paused_time += paused_ms;
+            self.pauses.pop_front();
+            discontinuity = self.sequence_counter > 0;
+        }
+        let program_date_time = self.start_time + self.paused_time + (self.media_time * 1000.0).round() as u64;
+        self.media_time += duration;
 
         let segment = HLSSegment {
             sequence_number: self.sequence_counter,
             duration,
             video_url: if video_segment.is_some() {
-                format!("video/video_recording_{}.ts", self.sequence_counter)
+                format!("video/video_recording_{}.{}", self.sequence_counter, video_extension)
             } else {
                 String::new()
             },
-            audio_url: format!("audio/audio_recording_{}.aac", self.sequence_counter),
-            combined_url: if video_segment.is_some() {
+            audio_url: if audio_segment.is_some() {
+                format!("audio/audio_recording_{}.{}", self.sequence_counter, audio_extension)
+            } else {
+                String::new()
+            },
+            // Combined segments are only muxed as MPEG-TS, from both tracks
+            combined_url: if video_segment.is_some() && audio_segment.is_some() && !self.config.is_cmaf() {
                 Some(format!("combined-source/segment_{}.ts", self.sequence_counter))
             } else {
                 None
             },
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
+            timestamp: now_millis(),
             video_size: video_segment.as_ref().map(|v| v.data.len()).unwrap_or(0),
-            audio_size: audio_segment.data.len(),
+            audio_size: audio_segment.as_ref().map(|a| a.data.len()).unwrap_or(0),
+            program_date_time,
+            discontinuity,
         };
 
-        // Add to segments queue
-        self.segments.push_back(segment.clone());
+        self.video_bitrate.add(segment.video_size, segment.duration);
+        self.audio_bitrate.add(segment.audio_size, segment.duration);
+
+        // Add to segments queue, unless a sink already rejected it
+        if self.removed.contains(&segment.sequence_number) {
+            self.gap = true;
+            if self.config.is_live_only() {
+                self.evicted.push(segment.clone());
+            }
+        } else {
+            self.segments.push_back(segment.clone());
+            if !self.config.is_live_only() {
+                self.history.push(segment.clone());
+            }
+        }
 
         // Maintain playlist size limit
-        while self.segments.len() > self.config.playlist_size {
-            self.segments.pop_front();
+        while self.segments.len() > self.config.playlist_size as usize {
+            let Some(evicted) = self.segments.pop_front() else {
+                break;
+            };
+            if evicted.discontinuity {
+                self.discontinuity_sequence += 1;
+            }
+            if self.config.is_live_only() {
+                self.evicted.push(evicted);
+            }
         }
 
         self.sequence_counter += 1;
+        self.expire_parts();
 
         log::debug!("Created HLS segment {} (duration: {:.2}s, video: {} bytes, audio: {} bytes)",
                    segment.sequence_number, segment.duration, segment.video_size, segment.audio_size);
@@ -114,62 +332,337 @@ impl HLSSegmenter {
         Ok(segment)
     }
 
+    /// Add a completed LL-HLS part of the video or audio track
+    pub fn add_part(&mut self, playlist_type: PlaylistType, part: HLSPart) {
+        match playlist_type {
+            PlaylistType::Video => self.video_parts.push_back(part),
+            PlaylistType::Audio => self.audio_parts.push_back(part),
+            PlaylistType::Combined => {}
+        }
+    }
+
+    /// Drop the parts of segments that are more than three segments from the live edge
+    ///
+    /// Players only need parts within three target durations of the end of the playlist.
+    fn expire_parts(&mut self) {
+        let oldest = self.sequence_counter.saturating_sub(3);
+        while let Some(part) = self.video_parts.front().filter(|part| part.segment < oldest) {
+            self.expired_parts.push((S3ContentType::VideoPart, part.number));
+            self.video_parts.pop_front();
+        }
+        while let Some(part) = self.audio_parts.front().filter(|part| part.segment < oldest) {
+            self.expired_parts.push((S3ContentType::AudioPart, part.number));
+            self.audio_parts.pop_front();
+        }
+    }
+
+    /// Take a segment out of the playlists after a sink rejected one of its objects
+    ///
+    /// The segments after it move up a media sequence number and the next one
+    /// starts with a discontinuity. With live-only retention its remaining
+    /// objects are handed out for deletion like evicted segments.
+    pub fn remove_segment(&mut self, sequence: u32) {
+        if self.removed.contains(&sequence) {
+            return;
+        }
+        self.removed.push(sequence);
+
+        let removed = self.segments.iter().position(|segment| segment.sequence_number == sequence)
+            .and_then(|index| self.segments.remove(index));
+        self.history.retain(|segment| segment.sequence_number != sequence);
+        if let (Some(segment), true) = (removed, self.config.is_live_only()) {
+            self.evicted.push(segment);
+        }
+
+        // A segment not created yet is left out when it is
+        if sequence >= self.sequence_counter {
+            return;
+        }
+        let next = self.segments.iter().chain(&self.history)
+            .map(|segment| segment.sequence_number)
+            .filter(|&number| number > sequence)
+            .min();
+        match next {
+            Some(next) => {
+                for segment in self.segments.iter_mut().chain(self.history.iter_mut()) {
+                    segment.discontinuity |= segment.sequence_number == next;
+                }
+            }
+            None => self.gap = true,
+        }
+    }
+
+    /// Media sequence number of a segment, skipping the removed segments before it
+    fn media_sequence_number(&self, sequence: u32) -> u32 {
+        sequence - self.removed.iter().filter(|&&removed| removed < sequence).count() as u32
+    }
+
+    /// Take the objects of parts dropped from the playlists since the last call
+    ///
+    /// Parts duplicate their segment, so they can be deleted whatever the retention.
+    pub fn take_expired_parts(&mut self) -> Vec<(S3ContentType, u32)> {
+        std::mem::take(&mut self.expired_parts)
+    }
+
+    /// Live sliding window over the last `playlist_size` segments
+    pub fn live_playlist(&self) -> HLSPlaylist {
+        HLSPlaylist {
+            version: self.playlist_version(),
+            target_duration: self.config.target_duration,
+            media_sequence: self.media_sequence_number(
+                self.segments.front().map_or(self.sequence_counter, |segment| segment.sequence_number)
+            ),
+            discontinuity_sequence: self.discontinuity_sequence,
+            segments: self.segments.iter().cloned().collect(),
+            end_list: self.ended,
+            playlist_type: None,
+            parts: self.config.part_duration.map(|part_target| PartialSegments {
+                part_target,
+                blocking_reload: self.config.blocking_reload.unwrap_or(false),
+                video: self.video_parts.iter().cloned().collect(),
+                audio: self.audio_parts.iter().cloned().collect(),
+            }),
+        }
+    }
+
+    /// Every segment of the recording: an EVENT playlist while recording, VOD once finished
+    pub fn archive_playlist(&self) -> HLSPlaylist {
+        HLSPlaylist {
+            version: self.playlist_version(),
+            target_duration: self.config.target_duration,
+            media_sequence: self.history.first().map_or(0, |segment| self.media_sequence_number(segment.sequence_number)),
+            discontinuity_sequence: 0,
+            segments: self.history.clone(),
+            end_list: self.ended,
+            playlist_type: Some(if self.ended { MediaPlaylistType::Vod } else { MediaPlaylistType::Event }),
+            parts: None,
+        }
+    }
+
+    /// Whether archive playlists are produced; live-only retention deletes the segments they would list
+    pub fn has_archive(&self) -> bool {
+        !self.config.is_live_only()
+    }
+
+    /// Mark the recording as finished, closing every playlist with `EXT-X-ENDLIST`
+    pub fn finish(&mut self) {
+        self.ended = true;
+    }
+
     /// Generate HLS playlist in M3U8 format following Cap's structure
     pub fn generate_m3u8_playlist(&self, playlist_type: PlaylistType) -> String {
+        self.render_playlist(&self.live_playlist(), playlist_type)
+    }
+
+    /// Generate the archive playlist listing the whole recording
+    pub fn generate_archive_playlist(&self, playlist_type: PlaylistType) -> String {
+        self.render_playlist(&self.archive_playlist(), playlist_type)
+    }
+
+    /// Render one media playlist of a snapshot
+    pub fn render_playlist(&self, snapshot: &HLSPlaylist, playlist_type: PlaylistType) -> String {
         let mut playlist = String::new();
 
         // M3U8 header
         playlist.push_str("#EXTM3U\n");
-        playlist.push_str("#EXT-X-VERSION:3\n");
-        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", self.config.target_duration));
+        playlist.push_str(&format!("#EXT-X-VERSION:{}\n", snapshot.version));
+        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", snapshot.target_duration));
+
+        // LL-HLS parts and the other track's parts, for its rendition report. A track
+        // may be a segment ahead of the playlist; only parts up to the segment after
+        // the last complete one are listed
+        let (parts, other_parts) = match (&snapshot.parts, &playlist_type) {
+            (Some(parts), PlaylistType::Video) => (Some(parts.video.as_slice()), parts.audio.as_slice()),
+            (Some(parts), PlaylistType::Audio) => (Some(parts.audio.as_slice()), parts.video.as_slice()),
+            _ => (None, &[][..]),
+        };
+        let next_segment = snapshot.segments.last().map_or(snapshot.media_sequence, |segment| segment.sequence_number + 1);
+        let listed = |part: &&HLSPart| part.segment <= next_segment;
+        if let (Some(partial), Some(_)) = (&snapshot.parts, parts) {
+            // PART-HOLD-BACK is required with parts; blocking reloads (`_HLS_msn`/`_HLS_part`)
+            // are only advertised when the origin implements them
+            playlist.push_str(&format!(
+                "#EXT-X-SERVER-CONTROL:{}PART-HOLD-BACK={:.3}\n",
+                if partial.blocking_reload { "CAN-BLOCK-RELOAD=YES," } else { "" },
+                partial.part_target * 3.0
+            ));
+            playlist.push_str(&format!("#EXT-X-PART-INF:PART-TARGET={:.3}\n", partial.part_target));
+        }
+
+        playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", snapshot.media_sequence));
+        if snapshot.discontinuity_sequence > 0 {
+            playlist.push_str(&format!("#EXT-X-DISCONTINUITY-SEQUENCE:{}\n", snapshot.discontinuity_sequence));
+        }
+        match snapshot.playlist_type {
+            Some(MediaPlaylistType::Event) => playlist.push_str("#EXT-X-PLAYLIST-TYPE:EVENT\n"),
+            Some(MediaPlaylistType::Vod) => playlist.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n"),
+            None => {}
+        }
 
-        // Media sequence (oldest segment sequence)
-        if let Some(first_segment) = self.segments.front() {
-            playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", first_segment.sequence_number));
+        // fMP4 fragments share the track's init segment, next to the playlist
+        if self.config.is_cmaf() && !matches!(playlist_type, PlaylistType::Combined) {
+            playlist.push_str("#EXT-X-MAP:URI=\"init.mp4\"\n");
         }
 
         // Add segments
-        for segment in &self.segments {
-            playlist.push_str(&format!("#EXTINF:{:.3},\n", segment.duration));
-            
-            match playlist_type {
-                PlaylistType::Video => {
-                    if !segment.video_url.is_empty() {
-                        playlist.push_str(&format!("{}\n", segment.video_url));
-                    }
-                },
-                PlaylistType::Audio => {
-                    playlist.push_str(&format!("{}\n", segment.audio_url));
-                },
-                PlaylistType::Combined => {
-                    if let Some(combined_url) = &segment.combined_url {
-                        playlist.push_str(&format!("{}\n", combined_url));
-                    }
-                },
+        for segment in &snapshot.segments {
+            let url = match playlist_type {
+                PlaylistType::Video => Some(&segment.video_url).filter(|url| !url.is_empty()),
+                PlaylistType::Audio => Some(&segment.audio_url).filter(|url| !url.is_empty()),
+                PlaylistType::Combined => segment.combined_url.as_ref(),
+            };
+
+            if let Some(url) = url {
+                if segment.discontinuity {
+                    playlist.push_str("#EXT-X-DISCONTINUITY\n");
+                }
+                playlist.push_str(&format!("#EXT-X-PROGRAM-DATE-TIME:{}\n", date_time(segment.program_date_time)));
+                for part in parts.unwrap_or_default().iter().filter(|part| part.segment == segment.sequence_number) {
+                    push_part(&mut playlist, &playlist_type, part);
+                }
+                playlist.push_str(&format!("#EXTINF:{:.3},\n", segment.duration));
+                playlist.push_str(&format!("{}\n", file_name(url)));
+            }
+        }
+
+        if let Some(parts) = parts.filter(|_| !snapshot.end_list) {
+            // Parts of the segment still being encoded, then the part that comes next
+            for part in parts.iter().filter(|part| part.segment == next_segment) {
+                push_part(&mut playlist, &playlist_type, part);
+            }
+            if let Some(last) = parts.iter().rev().find(listed) {
+                playlist.push_str(&format!(
+                    "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}\"\n",
+                    part_url(&playlist_type, last.number + 1)
+                ));
+            }
+
+            let other = match playlist_type {
+                PlaylistType::Video => "audio",
+                _ => "video",
+            };
+            if let Some(last) = other_parts.iter().rev().find(listed) {
+                playlist.push_str(&format!(
+                    "#EXT-X-RENDITION-REPORT:URI=\"../{}/stream.m3u8\",LAST-MSN={},LAST-PART={}\n",
+                    other, self.media_sequence_number(last.segment), last.index
+                ));
             }
         }
 
+        if snapshot.end_list {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+
         playlist
     }
 
     /// Generate master playlist for multi-stream playback
     pub fn generate_master_playlist(&self) -> String {
+        self.render_master_playlist("stream.m3u8")
+    }
+
+    /// Generate the master playlist of the archive playlists
+    pub fn generate_archive_master_playlist(&self) -> String {
+        self.render_master_playlist("archive.m3u8")
+    }
+
+    /// Master playlist referencing the media playlists named `media_playlist`
+    ///
+    /// Video variants carry the audio as an `EXT-X-MEDIA` rendition group;
+    /// bandwidths are measured from the segments so far, including the audio.
+    fn render_master_playlist(&self, media_playlist: &str) -> String {
+        let info = &self.stream_info;
+        let (audio_peak, audio_average) = match info.audio_codecs {
+            Some(_) => self.audio_bitrate.bitrates(info.audio_bitrate),
+            None => (0, 0),
+        };
         let mut playlist = String::new();
 
         playlist.push_str("#EXTM3U\n");
-        playlist.push_str("#EXT-X-VERSION:3\n");
+        playlist.push_str(&format!("#EXT-X-VERSION:{}\n", self.playlist_version()));
 
-        // Video stream
-        playlist.push_str("#EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1920x1080\n");
-        playlist.push_str("video/stream.m3u8\n");
+        let Some(video_codecs) = &info.video_codecs else {
+            // Audio-only recordings have a single audio variant
+            playlist.push_str(&format!(
+                "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},CODECS=\"{}\"\n",
+                audio_peak, audio_average, info.audio_codecs.as_deref().unwrap_or_default()
+            ));
+            playlist.push_str(&format!("audio/{}\n", media_playlist));
+            return playlist;
+        };
 
-        // Audio stream
-        playlist.push_str("#EXT-X-STREAM-INF:BANDWIDTH=128000\n");
-        playlist.push_str("audio/stream.m3u8\n");
+        // Video-only recordings have no audio group
+        let codecs = match &info.audio_codecs {
+            Some(audio_codecs) => {
+                playlist.push_str(&format!(
+                    "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"Audio\",DEFAULT=YES,AUTOSELECT=YES,CHANNELS=\"{}\",URI=\"audio/{}\"\n",
+                    info.audio_channels, media_playlist
+                ));
+                format!("{},{}", video_codecs, audio_codecs)
+            }
+            None => video_codecs.clone(),
+        };
+
+        let (video_peak, video_average) = self.video_bitrate.bitrates(info.video_bitrate);
+        playlist.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},CODECS=\"{}\",RESOLUTION={}x{},FRAME-RATE={:.3}",
+            video_peak + audio_peak,
+            video_average + audio_average,
+            codecs,
+            info.resolution.width,
+            info.resolution.height,
+            info.frame_rate,
+        ));
+        if info.audio_codecs.is_some() {
+            playlist.push_str(",AUDIO=\"audio\"");
+        }
+        playlist.push_str(&format!("\nvideo/{}\n", media_playlist));
 
         playlist
     }
 
+    /// Generate the DASH MPD of the CMAF fragments: dynamic while recording, static once finished
+    ///
+    /// Only meaningful with CMAF segments, which DASH players can fetch.
+    pub fn generate_dash_manifest(&self) -> String {
+        let info = &self.stream_info;
+        let (first, listed_duration): (_, f64) = if self.has_archive() {
+            (self.history.first(), self.history.iter().map(|segment| segment.duration).sum())
+        } else {
+            (self.segments.front(), self.segments.iter().map(|segment| segment.duration).sum())
+        };
+
+        let manifest = DashManifest {
+            mpd_type: if self.ended { MpdType::Static } else { MpdType::Dynamic },
+            // Segments become available later by the length of the pauses
+            availability_start_time: self.start_time + self.total_paused_time,
+            publish_time: now_millis(),
+            segment_duration: self.config.segment_duration,
+            // Live segments are found from the wall clock; a finished recording starts at its first remaining segment
+            start_number: match first {
+                Some(segment) if self.ended => segment.sequence_number,
+                _ => 0,
+            },
+            duration: listed_duration,
+            time_shift_buffer_depth: self.config.is_live_only()
+                .then(|| self.config.playlist_size as f64 * self.config.segment_duration),
+            stream_info: info.clone(),
+            video_bandwidth: self.video_bitrate.bitrates(info.video_bitrate).0,
+            audio_bandwidth: self.audio_bitrate.bitrates(info.audio_bitrate).0,
+        };
+        manifest.render()
+    }
+
+    /// Segmenter configuration
+    pub fn config(&self) -> &HLSConfig {
+        &self.config
+    }
+
+    /// Protocol version; `EXT-X-MAP` with fMP4 segments needs version 7
+    fn playlist_version(&self) -> u32 {
+        if self.config.is_cmaf() { 7 } else { 3 }
+    }
+
     /// Get current segments
     pub fn get_segments(&self) -> Vec<HLSSegment> {
         self.segments.iter().cloned().collect()
@@ -180,43 +673,72 @@ impl HLSSegmenter {
         self.segments.back()
     }
 
+    /// Take the segments evicted from the window since the last call
+    ///
+    /// Only filled with live-only retention; their objects can be deleted.
+    pub fn take_evicted(&mut self) -> Vec<HLSSegment> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Stored objects of a segment: audio, video and combined, as segments or fMP4 fragments
+    pub fn segment_objects(&self, segment: &HLSSegment) -> Vec<(S3ContentType, u32)> {
+        let sequence = segment.sequence_number;
+        let mut objects = Vec::new();
+        let (audio, video) = if self.config.is_cmaf() {
+            (S3ContentType::AudioFragment, S3ContentType::VideoFragment)
+        } else {
+            (S3ContentType::AudioSegment, S3ContentType::VideoSegment)
+        };
+        if !segment.audio_url.is_empty() {
+            objects.push((audio, sequence));
+        }
+        if !segment.video_url.is_empty() {
+            objects.push((video, sequence));
+        }
+        if segment.combined_url.is_some() {
+            objects.push((S3ContentType::CombinedSegment, sequence));
+        }
+        objects
+    }
+
     /// Generate S3 key for segment
     pub fn generate_s3_key(&self, segment: &HLSSegment, content_type: S3ContentType) -> String {
-        match content_type {
-            S3ContentType::VideoSegment => {
-                format!("{}/{}/video/video_recording_{}.ts", 
-                       self.user_id, self.video_id, segment.sequence_number)
-            },
-            S3ContentType::AudioSegment => {
-                format!("{}/{}/audio/audio_recording_{}.aac", 
-                       self.user_id, self.video_id, segment.sequence_number)
-            },
-            S3ContentType::CombinedSegment => {
-                format!("{}/{}/combined-source/segment_{}.ts", 
-                       self.user_id, self.video_id, segment.sequence_number)
-            },
-            S3ContentType::VideoPlaylist => {
-                format!("{}/{}/video/stream.m3u8", self.user_id, self.video_id)
-            },
-            S3ContentType::AudioPlaylist => {
-                format!("{}/{}/audio/stream.m3u8", self.user_id, self.video_id)
-            },
-            S3ContentType::CombinedPlaylist => {
-                format!("{}/{}/combined-source/stream.m3u8", self.user_id, self.video_id)
-            },
-            S3ContentType::MasterPlaylist => {
-                format!("{}/{}/stream.m3u8", self.user_id, self.video_id)
-            },
-        }
+        object_key(&self.user_id, &self.video_id, &content_type, segment.sequence_number)
     }
 
     /// Clear all segments (for cleanup)
     pub fn clear_segments(&mut self) {
         self.segments.clear();
+        self.evicted.clear();
+        self.video_parts.clear();
+        self.audio_parts.clear();
+        self.expired_parts.clear();
         self.sequence_counter = 0;
     }
 }
 
+/// URI of a segment relative to its media playlist, which sits in the segment's directory
+fn file_name(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+/// URI of a part, relative to the media playlist listing it
+fn part_url(playlist_type: &PlaylistType, number: u32) -> String {
+    match playlist_type {
+        PlaylistType::Video => format!("video_part_{}.m4s", number),
+        _ => format!("audio_part_{}.m4s", number),
+    }
+}
+
+/// Append an `EXT-X-PART` line
+fn push_part(playlist: &mut String, playlist_type: &PlaylistType, part: &HLSPart) {
+    playlist.push_str(&format!("#EXT-X-PART:DURATION={:.3},URI=\"{}\"", part.duration, part_url(playlist_type, part.number)));
+    if part.independent {
+        playlist.push_str(",INDEPENDENT=YES");
+    }
+    playlist.push('\n');
+}
+
 /// Type of HLS playlist to generate
 #[derive(Debug, Clone)]
 pub enum PlaylistType {
@@ -226,29 +748,196 @@ pub enum PlaylistType {
 }
 
 /// S3 content type for different segment types
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum S3ContentType {
     VideoSegment,
     AudioSegment,
     CombinedSegment,
+    /// fMP4 init segment of the video track
+    VideoInit,
+    /// fMP4 init segment of the audio track
+    AudioInit,
+    /// fMP4 video fragment
+    VideoFragment,
+    /// fMP4 audio fragment
+    AudioFragment,
+    /// LL-HLS partial segment of the video track, keyed by part number
+    VideoPart,
+    /// LL-HLS partial segment of the audio track, keyed by part number
+    AudioPart,
     VideoPlaylist,
     AudioPlaylist,
     CombinedPlaylist,
     MasterPlaylist,
+    /// Archive playlists listing every segment of the recording
+    VideoArchivePlaylist,
+    AudioArchivePlaylist,
+    CombinedArchivePlaylist,
+    MasterArchivePlaylist,
+    /// Per-session manifest of object digests
+    Manifest,
+    /// MPEG-DASH MPD of the CMAF fragments
+    DashManifest,
 }
 
 impl S3ContentType {
+    /// Name of the content type, as it is serialized and used in config keys
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            S3ContentType::VideoSegment => "VideoSegment",
+            S3ContentType::AudioSegment => "AudioSegment",
+            S3ContentType::CombinedSegment => "CombinedSegment",
+            S3ContentType::VideoInit => "VideoInit",
+            S3ContentType::AudioInit => "AudioInit",
+            S3ContentType::VideoFragment => "VideoFragment",
+            S3ContentType::AudioFragment => "AudioFragment",
+            S3ContentType::VideoPart => "VideoPart",
+            S3ContentType::AudioPart => "AudioPart",
+            S3ContentType::VideoPlaylist => "VideoPlaylist",
+            S3ContentType::AudioPlaylist => "AudioPlaylist",
+            S3ContentType::CombinedPlaylist => "CombinedPlaylist",
+            S3ContentType::MasterPlaylist => "MasterPlaylist",
+            S3ContentType::VideoArchivePlaylist => "VideoArchivePlaylist",
+            S3ContentType::AudioArchivePlaylist => "AudioArchivePlaylist",
+            S3ContentType::CombinedArchivePlaylist => "CombinedArchivePlaylist",
+            S3ContentType::MasterArchivePlaylist => "MasterArchivePlaylist",
+            S3ContentType::Manifest => "Manifest",
+            S3ContentType::DashManifest => "DashManifest",
+        }
+    }
+
     /// Get MIME type for S3 upload
     pub fn mime_type(&self) -> &'static str {
         match self {
             S3ContentType::VideoSegment | S3ContentType::CombinedSegment => "video/mp2t",
             S3ContentType::AudioSegment => "audio/aac",
+            S3ContentType::VideoInit | S3ContentType::VideoFragment | S3ContentType::VideoPart => "video/mp4",
+            S3ContentType::AudioInit | S3ContentType::AudioFragment | S3ContentType::AudioPart => "audio/mp4",
             S3ContentType::VideoPlaylist | 
             S3ContentType::AudioPlaylist | 
             S3ContentType::CombinedPlaylist | 
-            S3ContentType::MasterPlaylist => "application/vnd.apple.mpegurl",
+            S3ContentType::MasterPlaylist |
+            S3ContentType::VideoArchivePlaylist |
+            S3ContentType::AudioArchivePlaylist |
+            S3ContentType::CombinedArchivePlaylist |
+            S3ContentType::MasterArchivePlaylist => "application/vnd.apple.mpegurl",
+            S3ContentType::Manifest => "application/json",
+            S3ContentType::DashManifest => "application/dash+xml",
         }
     }
+
+    /// Whether this is a playlist (or the DASH MPD) that is replaced in place
+    pub fn is_playlist(&self) -> bool {
+        matches!(
+            self,
+            S3ContentType::DashManifest |
+            S3ContentType::VideoPlaylist |
+            S3ContentType::AudioPlaylist |
+            S3ContentType::CombinedPlaylist |
+            S3ContentType::MasterPlaylist |
+            S3ContentType::VideoArchivePlaylist |
+            S3ContentType::AudioArchivePlaylist |
+            S3ContentType::CombinedArchivePlaylist |
+            S3ContentType::MasterArchivePlaylist
+        )
+    }
+
+    /// Whether this is a media segment or fragment listed in the media playlists
+    pub fn is_segment(&self) -> bool {
+        matches!(
+            self,
+            S3ContentType::VideoSegment |
+            S3ContentType::AudioSegment |
+            S3ContentType::CombinedSegment |
+            S3ContentType::VideoFragment |
+            S3ContentType::AudioFragment
+        )
+    }
+
+    /// Whether the object is rewritten under the same key as the recording grows
+    pub fn is_replaced_in_place(&self) -> bool {
+        self.is_playlist() || matches!(self, S3ContentType::Manifest)
+    }
+}
+
+/// Sequence number of a segment key such as `.../video_recording_12.ts`
+pub fn sequence_from_key(key: &str) -> Option<u32> {
+    let file_name = key.rsplit('/').next()?;
+    let stem = file_name.split_once('.').map_or(file_name, |(stem, _)| stem);
+    stem.rsplit_once('_')?.1.parse().ok()
+}
+
+/// Object key for a segment or playlist, following Cap's S3 layout
+///
+/// The sequence number is ignored for playlist, manifest and init segment content types.
+pub fn object_key(user_id: &str, video_id: &str, content_type: &S3ContentType, sequence: u32) -> String {
+    match content_type {
+        S3ContentType::VideoSegment => {
+            format!("{}/{}/video/video_recording_{}.ts", user_id, video_id, sequence)
+        },
+        S3ContentType::AudioSegment => {
+            format!("{}/{}/audio/audio_recording_{}.aac", user_id, video_id, sequence)
+        },
+        S3ContentType::CombinedSegment => {
+            format!("{}/{}/combined-source/segment_{}.ts", user_id, video_id, sequence)
+        },
+        S3ContentType::VideoInit => {
+            format!("{}/{}/video/init.mp4", user_id, video_id)
+        },
+        S3ContentType::AudioInit => {
+            format!("{}/{}/audio/init.mp4", user_id, video_id)
+        },
+        S3ContentType::VideoFragment => {
+            format!("{}/{}/video/video_recording_{}.m4s", user_id, video_id, sequence)
+        },
+        S3ContentType::AudioFragment => {
+            format!("{}/{}/audio/audio_recording_{}.m4s", user_id, video_id, sequence)
+        },
+        S3ContentType::VideoPart => {
+            format!("{}/{}/video/video_part_{}.m4s", user_id, video_id, sequence)
+        },
+        S3ContentType::AudioPart => {
+            format!("{}/{}/audio/audio_part_{}.m4s", user_id, video_id, sequence)
+        },
+        S3ContentType::VideoPlaylist => {
+            format!("{}/{}/video/stream.m3u8", user_id, video_id)
+        },
+        S3ContentType::AudioPlaylist => {
+            format!("{}/{}/audio/stream.m3u8", user_id, video_id)
+        },
+        S3ContentType::CombinedPlaylist => {
+            format!("{}/{}/combined-source/stream.m3u8", user_id, video_id)
+        },
+        S3ContentType::MasterPlaylist => {
+            format!("{}/{}/stream.m3u8", user_id, video_id)
+        },
+        S3ContentType::VideoArchivePlaylist => {
+            format!("{}/{}/video/archive.m3u8", user_id, video_id)
+        },
+        S3ContentType::AudioArchivePlaylist => {
+            format!("{}/{}/audio/archive.m3u8", user_id, video_id)
+        },
+        S3ContentType::CombinedArchivePlaylist => {
+            format!("{}/{}/combined-source/archive.m3u8", user_id, video_id)
+        },
+        S3ContentType::MasterArchivePlaylist => {
+            format!("{}/{}/archive.m3u8", user_id, video_id)
+        },
+        S3ContentType::Manifest => {
+            format!("{}/{}/manifest.json", user_id, video_id)
+        },
+        S3ContentType::DashManifest => {
+            format!("{}/{}/stream.mpd", user_id, video_id)
+        },
+    }
+}
+
+/// Current wall-clock time in Unix milliseconds
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
 }
 
 /// Create HLS segmenter with Cap's default settings
@@ -257,7 +946,339 @@ pub fn create_cap_hls_segmenter(user_id: String, video_id: String) -> HLSSegment
         segment_duration: 2.0, // 2-second segments like Cap
         target_duration: 2,
         playlist_size: 5, // Keep last 5 segments
+        segment_format: None,
+        retention: None,
+        cleanup_grace_seconds: None,
+        part_duration: None,
+        blocking_reload: None,
     };
 
     HLSSegmenter::new(config, user_id, video_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{AudioCodec, MemorySink, SegmentFormat, SegmentRetention, SegmentSink, VideoCodec};
+
+    fn audio_segment(sequence: u32) -> EncodedAudioSegment {
+        EncodedAudioSegment {
+            data: vec![0; 16],
+            codec: AudioCodec::AAC,
+            sequence,
+            duration: 2.0,
+            timestamp: 0,
+            sample_rate: 48000,
+            channels: 2,
+            pts: sequence as i64 * 96000,
+            frames: Vec::new(),
+        }
+    }
+
+    fn video_segment(sequence: u32) -> EncodedVideoSegment {
+        EncodedVideoSegment {
+            data: vec![0; 1000],
+            codec: VideoCodec::H264,
+            sequence,
+            duration: 2.0,
+            timestamp: 0,
+            frame_count: 60,
+            resolution: (1920, 1080),
+            pts: sequence as i64 * 180000,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Resolve a playlist URI against the directory of the playlist's key
+    fn resolve(playlist_key: &str, uri: &str) -> String {
+        let mut path: Vec<&str> = playlist_key.split('/').collect();
+        path.pop();
+        for component in uri.split('/') {
+            if component == ".." {
+                path.pop();
+            } else {
+                path.push(component);
+            }
+        }
+        path.join("/")
+    }
+
+    #[test]
+    fn test_live_window_evicts_old_segments() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        for sequence in 0..6 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+        // Segments are kept by default
+        assert!(segmenter.take_evicted().is_empty());
+
+        let mut config = segmenter.config().clone();
+        config.retention = Some(SegmentRetention::LiveWindow);
+        let mut segmenter = HLSSegmenter::new(config, "user".to_string(), "video".to_string());
+        for sequence in 0..7 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+
+        let evicted = segmenter.take_evicted();
+        assert_eq!(evicted.iter().map(|segment| segment.sequence_number).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(matches!(segmenter.segment_objects(&evicted[0]).as_slice(), [(S3ContentType::AudioSegment, 0)]));
+        assert!(segmenter.take_evicted().is_empty());
+        assert!(!segmenter.has_archive());
+    }
+
+    #[test]
+    fn test_master_playlist_from_stream_info() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        let master = segmenter.generate_master_playlist();
+        assert!(master.contains("#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"Audio\",DEFAULT=YES,AUTOSELECT=YES,CHANNELS=\"2\",URI=\"audio/stream.m3u8\"\n"));
+        assert!(master.contains("CODECS=\"avc1.640028,mp4a.40.2\",RESOLUTION=1920x1080,FRAME-RATE=30.000,AUDIO=\"audio\"\nvideo/stream.m3u8\n"));
+
+        // Measured audio bitrate replaces the configured one: 16 bytes per 2 seconds
+        segmenter.set_stream_info(StreamInfo::from_config(&EncodingConfig::default(), false, true));
+        segmenter.create_hls_segment(Some(audio_segment(0)), None).unwrap();
+        let master = segmenter.generate_master_playlist();
+        assert!(master.contains("#EXT-X-STREAM-INF:BANDWIDTH=64,AVERAGE-BANDWIDTH=64,CODECS=\"mp4a.40.2\"\naudio/stream.m3u8\n"));
+        assert!(!master.contains("#EXT-X-MEDIA"));
+    }
+
+    #[test]
+    fn test_video_only_segments() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        segmenter.set_stream_info(StreamInfo::from_config(&EncodingConfig::default(), true, false));
+        let segment = segmenter.create_hls_segment(None, Some(video_segment(0))).unwrap();
+        assert!(segment.audio_url.is_empty() && segment.combined_url.is_none());
+        assert!(matches!(segmenter.segment_objects(&segment).as_slice(), [(S3ContentType::VideoSegment, 0)]));
+        assert!(segmenter.create_hls_segment(None, None).is_err());
+
+        let live = segmenter.live_playlist();
+        assert!(segmenter.render_playlist(&live, PlaylistType::Video).contains("#EXTINF:2.000,\nvideo_recording_0.ts\n"));
+        assert!(!segmenter.render_playlist(&live, PlaylistType::Audio).contains("#EXTINF"));
+        let master = segmenter.generate_master_playlist();
+        assert!(master.ends_with("#EXT-X-STREAM-INF:BANDWIDTH=4000,AVERAGE-BANDWIDTH=4000,CODECS=\"avc1.640028\",RESOLUTION=1920x1080,FRAME-RATE=30.000\nvideo/stream.m3u8\n"));
+        assert!(!master.contains("#EXT-X-MEDIA"));
+    }
+
+    #[test]
+    fn test_archive_playlist_covers_whole_recording() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        for sequence in 0..8 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+
+        let live = segmenter.generate_m3u8_playlist(PlaylistType::Audio);
+        assert!(live.contains("#EXT-X-MEDIA-SEQUENCE:3\n"));
+        assert!(!live.contains("#EXT-X-PLAYLIST-TYPE"));
+
+        let archive = segmenter.generate_archive_playlist(PlaylistType::Audio);
+        assert!(archive.contains("#EXT-X-PLAYLIST-TYPE:EVENT\n"));
+        assert_eq!(archive.matches("#EXTINF").count(), 8);
+        assert!(!archive.contains("#EXT-X-ENDLIST"));
+
+        segmenter.finish();
+        let archive = segmenter.generate_archive_playlist(PlaylistType::Audio);
+        assert!(archive.contains("#EXT-X-PLAYLIST-TYPE:VOD\n"));
+        assert!(archive.ends_with("\naudio_recording_7.aac\n#EXT-X-ENDLIST\n"));
+        assert!(segmenter.generate_m3u8_playlist(PlaylistType::Audio).ends_with("#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn test_dash_manifest_references_fragments() {
+        let config = HLSConfig { segment_format: Some(SegmentFormat::Cmaf), ..HLSConfig::default() };
+        let mut segmenter = HLSSegmenter::new(config, "user".to_string(), "video".to_string());
+        segmenter.set_start_time(1_792_108_800_000);
+        for sequence in 0..3 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+
+        let mpd = segmenter.generate_dash_manifest();
+        assert!(mpd.contains(" type=\"dynamic\" availabilityStartTime=\"2026-10-16T00:00:00.000Z\""));
+        assert!(mpd.contains("<SegmentTemplate timescale=\"90000\" duration=\"180000\" startNumber=\"0\" initialization=\"video/init.mp4\" media=\"video/video_recording_$Number$.m4s\"/>"));
+        assert!(mpd.contains("<SegmentTemplate timescale=\"48000\" duration=\"96000\" startNumber=\"0\" initialization=\"audio/init.mp4\" media=\"audio/audio_recording_$Number$.m4s\"/>"));
+        assert!(mpd.contains("<Representation id=\"video\" codecs=\"avc1.640028\" bandwidth=\"2000000\" width=\"1920\" height=\"1080\" frameRate=\"30\"/>"));
+
+        segmenter.finish();
+        let mpd = segmenter.generate_dash_manifest();
+        assert!(mpd.contains(" type=\"static\" mediaPresentationDuration=\"PT6.000S\""));
+        assert!(!mpd.contains("availabilityStartTime"));
+    }
+
+    #[test]
+    fn test_pause_starts_discontinuity() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        segmenter.set_start_time(1_792_108_800_000);
+        for sequence in 0..2 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+        // Paused for a minute, 3 seconds in; segment 1 runs on after resuming
+        segmenter.add_discontinuity(3.0, 60_000);
+        segmenter.create_hls_segment(Some(audio_segment(2)), None).unwrap();
+
+        let playlist = segmenter.generate_m3u8_playlist(PlaylistType::Audio);
+        assert!(playlist.contains("#EXT-X-PROGRAM-DATE-TIME:2026-10-16T00:00:00.000Z\n#EXTINF:2.000,\naudio_recording_0.aac\n"));
+        assert!(playlist.contains("#EXT-X-PROGRAM-DATE-TIME:2026-10-16T00:00:02.000Z\n#EXTINF:2.000,\naudio_recording_1.aac\n"));
+        assert!(playlist.contains("#EXT-X-DISCONTINUITY\n#EXT-X-PROGRAM-DATE-TIME:2026-10-16T00:01:04.000Z\n#EXTINF:2.000,\naudio_recording_2.aac\n"));
+        assert!(!playlist.contains("#EXT-X-DISCONTINUITY-SEQUENCE"));
+
+        // The discontinuity sequence counts discontinuities that left the window
+        for sequence in 3..8 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+        let playlist = segmenter.generate_m3u8_playlist(PlaylistType::Audio);
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:3\n#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+        assert!(!playlist.contains("#EXT-X-DISCONTINUITY\n"));
+        assert!(segmenter.generate_archive_playlist(PlaylistType::Audio).contains("#EXT-X-DISCONTINUITY\n"));
+    }
+
+    #[tokio::test]
+    async fn test_media_playlist_uris_resolve_to_stored_objects() {
+        for segment_format in [SegmentFormat::MpegTs, SegmentFormat::Cmaf] {
+            let config = HLSConfig { segment_format: Some(segment_format), ..HLSConfig::default() };
+            let mut segmenter = HLSSegmenter::new(config, "user".to_string(), "video".to_string());
+            let sink = MemorySink::new("user".to_string(), "video".to_string());
+            sink.put_segment(S3ContentType::VideoInit, 0, vec![0]).await.unwrap();
+            sink.put_segment(S3ContentType::AudioInit, 0, vec![0]).await.unwrap();
+            for sequence in 0..3 {
+                let segment = segmenter.create_hls_segment(Some(audio_segment(sequence)), Some(video_segment(sequence))).unwrap();
+                for (content_type, sequence) in segmenter.segment_objects(&segment) {
+                    sink.put_segment(content_type, sequence, vec![0]).await.unwrap();
+                }
+            }
+
+            let (live, archive) = (segmenter.live_playlist(), segmenter.archive_playlist());
+            for (snapshot, playlist_type, content_type) in [
+                (&live, PlaylistType::Video, S3ContentType::VideoPlaylist),
+                (&live, PlaylistType::Audio, S3ContentType::AudioPlaylist),
+                (&live, PlaylistType::Combined, S3ContentType::CombinedPlaylist),
+                (&archive, PlaylistType::Video, S3ContentType::VideoArchivePlaylist),
+                (&archive, PlaylistType::Audio, S3ContentType::AudioArchivePlaylist),
+                (&archive, PlaylistType::Combined, S3ContentType::CombinedArchivePlaylist),
+            ] {
+                // CMAF playlists add the init segment; combined segments are MPEG-TS only
+                let expected = match (segmenter.config().is_cmaf(), &playlist_type) {
+                    (true, PlaylistType::Combined) => 0,
+                    (true, _) => 4,
+                    (false, _) => 3,
+                };
+                let playlist = segmenter.render_playlist(snapshot, playlist_type);
+                let key = sink.object_key(&content_type, 0);
+                // Segment lines, plus the URI attribute of tags such as EXT-X-MAP
+                let uris: Vec<&str> = playlist.lines()
+                    .filter_map(|line| match line.strip_prefix('#') {
+                        Some(tag) => tag.split_once("URI=\"").and_then(|(_, rest)| rest.split_once('"')).map(|(uri, _)| uri),
+                        None => Some(line),
+                    })
+                    .collect();
+                assert_eq!(uris.len(), expected, "{}", key);
+                for uri in uris {
+                    assert!(sink.get(&resolve(&key, uri)).is_some(), "{} listed in {} is not stored", uri, key);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_removed_segments_are_unlisted() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        for sequence in 0..3 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+        // One already listed and one a sink rejected before it was created
+        segmenter.remove_segment(1);
+        segmenter.remove_segment(4);
+        for sequence in 3..6 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+
+        for playlist in [
+            segmenter.generate_m3u8_playlist(PlaylistType::Audio),
+            segmenter.generate_archive_playlist(PlaylistType::Audio),
+        ] {
+            assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:0\n"));
+            assert!(!playlist.contains("audio_recording_1.aac"));
+            assert!(!playlist.contains("audio_recording_4.aac"));
+            assert!(playlist.contains("#EXT-X-DISCONTINUITY\n#EXT-X-PROGRAM-DATE-TIME:"));
+            assert_eq!(playlist.matches("#EXT-X-DISCONTINUITY\n").count(), 2);
+            assert_eq!(playlist.matches("#EXTINF").count(), 4);
+        }
+
+        // Media sequence numbers skip the removed segments
+        for sequence in 6..9 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+        let live = segmenter.live_playlist();
+        assert_eq!(live.segments.first().map(|segment| segment.sequence_number), Some(3));
+        assert_eq!(live.media_sequence, 2);
+    }
+
+    #[test]
+    fn test_low_latency_playlist_lists_parts() {
+        let config = HLSConfig {
+            segment_format: Some(SegmentFormat::Cmaf),
+            part_duration: Some(0.5),
+            ..HLSConfig::default()
+        };
+        let mut segmenter = HLSSegmenter::new(config, "user".to_string(), "video".to_string());
+        let part = |segment, index, number| HLSPart { segment, index, number, duration: 0.5, independent: true };
+
+        for number in 0..4 {
+            segmenter.add_part(PlaylistType::Audio, part(0, number, number));
+        }
+        segmenter.add_part(PlaylistType::Video, part(0, 0, 0));
+        segmenter.create_hls_segment(Some(audio_segment(0)), None).unwrap();
+        segmenter.add_part(PlaylistType::Audio, part(1, 0, 4));
+        // Already a segment ahead of the playlist, so not listed yet
+        segmenter.add_part(PlaylistType::Audio, part(2, 0, 8));
+
+        let playlist = segmenter.generate_m3u8_playlist(PlaylistType::Audio);
+        assert!(playlist.contains("#EXT-X-SERVER-CONTROL:PART-HOLD-BACK=1.500\n#EXT-X-PART-INF:PART-TARGET=0.500\n"));
+        assert!(playlist.contains("#EXT-X-PART:DURATION=0.500,URI=\"audio_part_3.m4s\",INDEPENDENT=YES\n#EXTINF:2.000,\naudio_recording_0.m4s\n"));
+        assert!(playlist.ends_with(concat!(
+            "audio_recording_0.m4s\n",
+            "#EXT-X-PART:DURATION=0.500,URI=\"audio_part_4.m4s\",INDEPENDENT=YES\n",
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"audio_part_5.m4s\"\n",
+            "#EXT-X-RENDITION-REPORT:URI=\"../video/stream.m3u8\",LAST-MSN=0,LAST-PART=0\n",
+        )));
+
+        // Parts are dropped three segments behind the live edge
+        for sequence in 1..4 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence)), None).unwrap();
+        }
+        let expired = segmenter.take_expired_parts();
+        assert_eq!(expired.len(), 5);
+        assert!(matches!(expired[0], (S3ContentType::VideoPart, 0)));
+        assert!(matches!(expired[4], (S3ContentType::AudioPart, 3)));
+        assert!(!segmenter.generate_m3u8_playlist(PlaylistType::Audio).contains("audio_part_3.m4s"));
+
+        // Blocking reloads are only advertised for an origin that supports them
+        segmenter.config.blocking_reload = Some(true);
+        assert!(segmenter.generate_m3u8_playlist(PlaylistType::Audio)
+            .contains("#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.500\n"));
+    }
+
+    #[test]
+    fn test_content_type_names_match_serde() {
+        for content_type in [
+            S3ContentType::VideoSegment,
+            S3ContentType::AudioSegment,
+            S3ContentType::CombinedSegment,
+            S3ContentType::VideoInit,
+            S3ContentType::AudioInit,
+            S3ContentType::VideoFragment,
+            S3ContentType::AudioFragment,
+            S3ContentType::VideoPart,
+            S3ContentType::AudioPart,
+            S3ContentType::VideoPlaylist,
+            S3ContentType::AudioPlaylist,
+            S3ContentType::CombinedPlaylist,
+            S3ContentType::MasterPlaylist,
+            S3ContentType::VideoArchivePlaylist,
+            S3ContentType::AudioArchivePlaylist,
+            S3ContentType::CombinedArchivePlaylist,
+            S3ContentType::MasterArchivePlaylist,
+            S3ContentType::Manifest,
+            S3ContentType::DashManifest,
+        ] {
+            assert_eq!(serde_json::to_value(&content_type).unwrap(), content_type.as_str());
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Upload Integrity
+//!
+//! Digests of every packaged segment and playlist, computed as soon as the
+//! object is produced, and the per-session manifest that lists them so
+//! downstream consumers can detect corrupted objects.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::S3ContentType;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::Md5;
+use napi_derive::napi;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Checksum sent with S3 uploads
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ChecksumAlgorithm {
+    /// `x-amz-checksum-crc32c`
+    Crc32c,
+    /// `x-amz-checksum-sha256`
+    Sha256,
+}
+
+/// CRC32C and SHA-256 of one object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectDigest {
+    pub crc32c: u32,
+    pub sha256: [u8; 32],
+}
+
+impl ObjectDigest {
+    /// Digest some data
+    pub fn of(data: &[u8]) -> Self {
+        Self {
+            crc32c: crc32c::crc32c(data),
+            sha256: Sha256::digest(data).into(),
+        }
+    }
+
+    /// CRC32C as sent in `x-amz-checksum-crc32c`: base64 of the big-endian value
+    pub fn crc32c_base64(&self) -> String {
+        BASE64.encode(self.crc32c.to_be_bytes())
+    }
+
+    /// SHA-256 as sent in `x-amz-checksum-sha256`
+    pub fn sha256_base64(&self) -> String {
+        BASE64.encode(self.sha256)
+    }
+
+    pub fn crc32c_hex(&self) -> String {
+        format!("{:08x}", self.crc32c)
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        hex(&self.sha256)
+    }
+}
+
+/// MD5 in hex, which S3 returns as the ETag of unencrypted and SSE-S3 single-part uploads
+pub fn md5_hex(data: &[u8]) -> String {
+    hex(&Md5::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// One object listed in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// Object key
+    pub key: String,
+    /// MIME type
+    pub content_type: String,
+    /// Size in bytes
+    pub bytes: u64,
+    /// CRC32C in hex
+    pub crc32c: String,
+    /// SHA-256 in hex
+    pub sha256: String,
+}
+
+/// Digests of every object of a recording session, stored as `manifest.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionManifest {
+    pub user_id: String,
+    pub session_id: String,
+    /// Objects by key; playlists list the digest of their latest version
+    pub objects: BTreeMap<String, ManifestEntry>,
+}
+
+impl SessionManifest {
+    /// Create an empty manifest
+    pub fn new(user_id: String, session_id: String) -> Self {
+        Self {
+            user_id,
+            session_id,
+            objects: BTreeMap::new(),
+        }
+    }
+
+    /// Digest an object and list it, replacing an earlier version under the same key
+    pub fn record(&mut self, key: &str, content_type: &S3ContentType, data: &[u8]) -> ObjectDigest {
+        let digest = ObjectDigest::of(data);
+        self.objects.insert(key.to_string(), ManifestEntry {
+            key: key.to_string(),
+            content_type: content_type.mime_type().to_string(),
+            bytes: data.len() as u64,
+            crc32c: digest.crc32c_hex(),
+            sha256: digest.sha256_hex(),
+        });
+        digest
+    }
+
+    /// Drop a deleted object from the listing
+    pub fn remove(&mut self, key: &str) {
+        self.objects.remove(key);
+    }
+
+    /// Serialize to pretty-printed JSON
+    pub fn to_json(&self) -> CaptureResult<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| CaptureError::EncodingError(format!("Failed to serialize manifest: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let digest = ObjectDigest::of(b"123456789");
+        assert_eq!(digest.crc32c_hex(), "e3069283");
+        assert_eq!(digest.crc32c_base64(), "4waSgw==");
+        assert_eq!(digest.sha256_hex(), "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225");
+        assert_eq!(md5_hex(b"123456789"), "25f9e794323b453885f5181f1b624d0b");
+
+        let mut manifest = SessionManifest::new("user".to_string(), "video".to_string());
+        manifest.record("user/video/video/stream.m3u8", &S3ContentType::VideoPlaylist, b"old");
+        manifest.record("user/video/video/stream.m3u8", &S3ContentType::VideoPlaylist, b"new!");
+        assert_eq!(manifest.objects.len(), 1);
+        assert_eq!(manifest.objects["user/video/video/stream.m3u8"].bytes, 4);
+    }
+}
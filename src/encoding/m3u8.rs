@@ -0,0 +1,469 @@
+//! M3U8 Parsing and Validation
+//!
+//! Reads media and master playlists back into the structs the segmenter
+//! writes, and checks playlists against the HLS rules that are easiest to
+//! break: segment durations, contiguous media sequence numbers,
+//! `EXT-X-ENDLIST` on finished playlists and master playlist references.
+
+use crate::config::Resolution;
+use crate::error::{CaptureError, CaptureResult};
+use super::{HLSPart, HLSPlaylist, HLSSegment, MediaPlaylistType, PartialSegments, PlaylistType};
+
+/// Variant stream of a master playlist (`EXT-X-STREAM-INF`)
+#[derive(Debug, Clone)]
+pub struct VariantStream {
+    /// Media playlist URI
+    pub uri: String,
+    pub bandwidth: u64,
+    pub average_bandwidth: Option<u64>,
+    pub codecs: Option<String>,
+    pub resolution: Option<Resolution>,
+    /// `GROUP-ID` of the audio renditions
+    pub audio_group: Option<String>,
+}
+
+/// Alternative rendition of a master playlist (`EXT-X-MEDIA`)
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    /// `TYPE`, e.g. `AUDIO`
+    pub media_type: String,
+    pub group_id: String,
+    pub name: String,
+    /// Media playlist URI, `None` when the rendition is muxed into the variants
+    pub uri: Option<String>,
+}
+
+/// Parsed master playlist
+#[derive(Debug, Clone)]
+pub struct MasterPlaylist {
+    pub version: u32,
+    pub variants: Vec<VariantStream>,
+    pub renditions: Vec<Rendition>,
+}
+
+impl MasterPlaylist {
+    /// URIs of every media playlist the master references
+    pub fn media_uris(&self) -> Vec<&str> {
+        self.variants.iter().map(|variant| variant.uri.as_str())
+            .chain(self.renditions.iter().filter_map(|rendition| rendition.uri.as_deref()))
+            .collect()
+    }
+}
+
+/// HLS rule broken by a playlist
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaylistIssue {
+    /// `EXTINF` rounded to the nearest second is above `EXT-X-TARGETDURATION`
+    SegmentExceedsTargetDuration { sequence_number: u32, duration: f64, target_duration: u32 },
+    /// Segment numbers skip ahead of the media sequence
+    MediaSequenceGap { expected: u32, found: u32 },
+    /// A reloaded playlist starts at an earlier media sequence
+    MediaSequenceDecreased { previous: u32, current: u32 },
+    /// A finished or VOD playlist has no `EXT-X-ENDLIST`
+    MissingEndList,
+    /// The master references a media playlist that does not exist
+    MissingMediaPlaylist { uri: String },
+    /// A media playlist is not referenced by the master
+    UnreferencedMediaPlaylist { uri: String },
+    /// A variant names an `AUDIO` group that no `EXT-X-MEDIA` defines
+    UnknownRenditionGroup { group_id: String },
+}
+
+/// Parse a media playlist; segment URIs are stored as the URLs of the `playlist_type` track
+///
+/// Segments are numbered from `EXT-X-MEDIA-SEQUENCE`. Sizes and creation times
+/// are not part of a playlist and are left at zero, as are the program
+/// date-times of segments without `EXT-X-PROGRAM-DATE-TIME`.
+pub fn parse_media_playlist(text: &str, playlist_type: PlaylistType) -> CaptureResult<HLSPlaylist> {
+    let mut version = 1;
+    let mut target_duration = None;
+    let mut media_sequence = 0;
+    let mut discontinuity_sequence = 0;
+    let mut kind = None;
+    let mut end_list = false;
+    let mut part_target = None;
+    let mut blocking_reload = false;
+    let mut parts: Vec<HLSPart> = Vec::new();
+    let mut segments = Vec::new();
+    let mut duration = None;
+    let mut program_date_time = None;
+    let mut discontinuity = false;
+
+    for (line_number, line) in playlist_lines(text)? {
+        if let Some(value) = line.strip_prefix("#EXT-X-VERSION:") {
+            version = parse_value(value, line_number)?;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            target_duration = Some(parse_value(value, line_number)?);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            media_sequence = parse_value(value, line_number)?;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-DISCONTINUITY-SEQUENCE:") {
+            discontinuity_sequence = parse_value(value, line_number)?;
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PROGRAM-DATE-TIME:") {
+            program_date_time = Some(parse_date_time(value)
+                .ok_or_else(|| invalid(line_number, "invalid date-time", value))?);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PLAYLIST-TYPE:") {
+            kind = Some(match value {
+                "EVENT" => MediaPlaylistType::Event,
+                "VOD" => MediaPlaylistType::Vod,
+                _ => return Err(invalid(line_number, "unknown playlist type", value)),
+            });
+        } else if line == "#EXT-X-ENDLIST" {
+            end_list = true;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-SERVER-CONTROL:") {
+            blocking_reload = attribute(&attributes(value), "CAN-BLOCK-RELOAD") == Some("YES");
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PART-INF:") {
+            let attributes = attributes(value);
+            part_target = Some(parse_value(required(&attributes, "PART-TARGET", line_number)?, line_number)?);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PART:") {
+            let attributes = attributes(value);
+            let segment = media_sequence + segments.len() as u32;
+            let uri = required(&attributes, "URI", line_number)?;
+            parts.push(HLSPart {
+                segment,
+                index: parts.iter().filter(|part| part.segment == segment).count() as u32,
+                number: trailing_number(uri).unwrap_or(parts.len() as u32),
+                duration: parse_value(required(&attributes, "DURATION", line_number)?, line_number)?,
+                independent: attribute(&attributes, "INDEPENDENT") == Some("YES"),
+            });
+        } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+            let value = value.split(',').next().unwrap_or_default();
+            duration = Some(parse_value(value, line_number)?);
+        } else if line.starts_with('#') {
+            // Other tags and comments
+        } else {
+            let duration = duration.take()
+                .ok_or_else(|| invalid(line_number, "segment without #EXTINF", line))?;
+            let uri = line.to_string();
+            let (video_url, audio_url, combined_url) = match playlist_type {
+                PlaylistType::Video => (uri, String::new(), None),
+                PlaylistType::Audio => (String::new(), uri, None),
+                PlaylistType::Combined => (String::new(), String::new(), Some(uri)),
+            };
+            segments.push(HLSSegment {
+                sequence_number: media_sequence + segments.len() as u32,
+                duration,
+                video_url,
+                audio_url,
+                combined_url,
+                timestamp: 0,
+                video_size: 0,
+                audio_size: 0,
+                program_date_time: program_date_time.take().unwrap_or_default(),
+                discontinuity: std::mem::take(&mut discontinuity),
+            });
+        }
+    }
+
+    let target_duration = target_duration
+        .ok_or_else(|| CaptureError::HLS("Media playlist has no #EXT-X-TARGETDURATION".to_string()))?;
+
+    Ok(HLSPlaylist {
+        version,
+        target_duration,
+        media_sequence,
+        discontinuity_sequence,
+        segments,
+        end_list,
+        playlist_type: kind,
+        parts: part_target.map(|part_target| {
+            let (video, audio) = match playlist_type {
+                PlaylistType::Video => (parts, Vec::new()),
+                PlaylistType::Audio => (Vec::new(), parts),
+                PlaylistType::Combined => (Vec::new(), Vec::new()),
+            };
+            PartialSegments { part_target, blocking_reload, video, audio }
+        }),
+    })
+}
+
+/// Parse a master playlist
+pub fn parse_master_playlist(text: &str) -> CaptureResult<MasterPlaylist> {
+    let mut master = MasterPlaylist {
+        version: 1,
+        variants: Vec::new(),
+        renditions: Vec::new(),
+    };
+    // `EXT-X-STREAM-INF` applies to the URI on the next line
+    let mut pending: Option<VariantStream> = None;
+
+    for (line_number, line) in playlist_lines(text)? {
+        if let Some(value) = line.strip_prefix("#EXT-X-VERSION:") {
+            master.version = parse_value(value, line_number)?;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attributes = attributes(value);
+            master.renditions.push(Rendition {
+                media_type: required(&attributes, "TYPE", line_number)?.to_string(),
+                group_id: required(&attributes, "GROUP-ID", line_number)?.to_string(),
+                name: required(&attributes, "NAME", line_number)?.to_string(),
+                uri: attribute(&attributes, "URI").map(str::to_string),
+            });
+        } else if let Some(value) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let attributes = attributes(value);
+            let resolution = attribute(&attributes, "RESOLUTION")
+                .map(|value| {
+                    let (width, height) = value.split_once('x')
+                        .ok_or_else(|| invalid(line_number, "invalid resolution", value))?;
+                    Ok::<_, CaptureError>(Resolution {
+                        width: parse_value(width, line_number)?,
+                        height: parse_value(height, line_number)?,
+                    })
+                })
+                .transpose()?;
+            pending = Some(VariantStream {
+                uri: String::new(),
+                bandwidth: parse_value(required(&attributes, "BANDWIDTH", line_number)?, line_number)?,
+                average_bandwidth: attribute(&attributes, "AVERAGE-BANDWIDTH")
+                    .map(|value| parse_value(value, line_number))
+                    .transpose()?,
+                codecs: attribute(&attributes, "CODECS").map(str::to_string),
+                resolution,
+                audio_group: attribute(&attributes, "AUDIO").map(str::to_string),
+            });
+        } else if line.starts_with("#EXTINF:") || line.starts_with("#EXT-X-TARGETDURATION:") {
+            return Err(invalid(line_number, "media playlist tag in a master playlist", line));
+        } else if line.starts_with('#') {
+            // Other tags and comments
+        } else {
+            let mut variant = pending.take()
+                .ok_or_else(|| invalid(line_number, "URI without #EXT-X-STREAM-INF", line))?;
+            variant.uri = line.to_string();
+            master.variants.push(variant);
+        }
+    }
+
+    if pending.is_some() {
+        return Err(CaptureError::HLS("#EXT-X-STREAM-INF without a URI".to_string()));
+    }
+    Ok(master)
+}
+
+/// Check a media playlist; `finished` requires `EXT-X-ENDLIST` even without `EXT-X-PLAYLIST-TYPE:VOD`
+pub fn validate_media_playlist(playlist: &HLSPlaylist, finished: bool) -> Vec<PlaylistIssue> {
+    let mut issues = Vec::new();
+    let mut expected = playlist.media_sequence;
+
+    for segment in &playlist.segments {
+        if segment.sequence_number != expected {
+            issues.push(PlaylistIssue::MediaSequenceGap { expected, found: segment.sequence_number });
+        }
+        expected = segment.sequence_number + 1;
+
+        if segment.duration.round() > playlist.target_duration as f64 {
+            issues.push(PlaylistIssue::SegmentExceedsTargetDuration {
+                sequence_number: segment.sequence_number,
+                duration: segment.duration,
+                target_duration: playlist.target_duration,
+            });
+        }
+    }
+
+    let vod = matches!(playlist.playlist_type, Some(MediaPlaylistType::Vod));
+    if (finished || vod) && !playlist.end_list {
+        issues.push(PlaylistIssue::MissingEndList);
+    }
+    issues
+}
+
+/// Check that a reloaded live playlist follows on from its previous version
+///
+/// Segments that left the window between the two reloads were never seen by the player.
+pub fn validate_playlist_reload(previous: &HLSPlaylist, current: &HLSPlaylist) -> Vec<PlaylistIssue> {
+    let next = previous.media_sequence + previous.segments.len() as u32;
+    if current.media_sequence < previous.media_sequence {
+        vec![PlaylistIssue::MediaSequenceDecreased { previous: previous.media_sequence, current: current.media_sequence }]
+    } else if current.media_sequence > next {
+        vec![PlaylistIssue::MediaSequenceGap { expected: next, found: current.media_sequence }]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Check a master playlist's references against the URIs of the media playlists that exist
+pub fn validate_master_playlist(master: &MasterPlaylist, media_uris: &[&str]) -> Vec<PlaylistIssue> {
+    let mut issues = Vec::new();
+    let referenced = master.media_uris();
+
+    for variant in &master.variants {
+        if let Some(group_id) = &variant.audio_group {
+            let defined = master.renditions.iter()
+                .any(|rendition| rendition.media_type == "AUDIO" && &rendition.group_id == group_id);
+            if !defined {
+                issues.push(PlaylistIssue::UnknownRenditionGroup { group_id: group_id.clone() });
+            }
+        }
+    }
+    for uri in &referenced {
+        if !media_uris.contains(uri) {
+            issues.push(PlaylistIssue::MissingMediaPlaylist { uri: uri.to_string() });
+        }
+    }
+    for uri in media_uris {
+        if !referenced.contains(uri) {
+            issues.push(PlaylistIssue::UnreferencedMediaPlaylist { uri: uri.to_string() });
+        }
+    }
+    issues
+}
+
+/// Trimmed, non-empty lines with their line numbers, after the `#EXTM3U` header
+fn playlist_lines(text: &str) -> CaptureResult<impl Iterator<Item = (usize, &str)> + '_> {
+    let mut lines = text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+    match lines.next() {
+        Some((_, "#EXTM3U")) => Ok(lines),
+        _ => Err(CaptureError::HLS("Playlist does not start with #EXTM3U".to_string())),
+    }
+}
+
+/// Split an attribute list into names and values, unquoting quoted strings
+fn attributes(list: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = list;
+    while let Some((name, value)) = rest.split_once('=') {
+        // Quoted strings may contain commas
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        attributes.push((name.trim(), value));
+        rest = remainder.trim_start_matches(',');
+    }
+    attributes
+}
+
+fn attribute<'a>(attributes: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+fn required<'a>(attributes: &[(&str, &'a str)], name: &str, line_number: usize) -> CaptureResult<&'a str> {
+    attribute(attributes, name).ok_or_else(|| invalid(line_number, "missing attribute", name))
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str, line_number: usize) -> CaptureResult<T> {
+    value.trim().parse().map_err(|_| invalid(line_number, "invalid value", value))
+}
+
+fn invalid(line_number: usize, reason: &str, value: &str) -> CaptureError {
+    CaptureError::HLS(format!("Line {}: {} {:?}", line_number, reason, value))
+}
+
+/// Unix milliseconds of an ISO 8601 date-time, e.g. `2024-05-01T12:00:00.000Z` or `2024-05-01T14:00:00+02:00`
+fn parse_date_time(value: &str) -> Option<u64> {
+    let (date, time) = value.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|field| field.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let (time, offset_minutes) = match time.strip_suffix('Z') {
+        Some(time) => (time, 0),
+        None => match time.rfind(['+', '-']) {
+            Some(index) => {
+                let (hours, minutes) = time[index + 1..].split_once(':')?;
+                let offset = hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?;
+                (&time[..index], if time[index..].starts_with('-') { -offset } else { offset })
+            }
+            None => (time, 0),
+        },
+    };
+    let mut clock = time.splitn(3, ':');
+    let hours = clock.next()?.parse::<i64>().ok()?;
+    let minutes = clock.next()?.parse::<i64>().ok()?;
+    let seconds = clock.next()?.parse::<f64>().ok()?;
+
+    // Days since 1970-01-01 from the civil date (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds_of_day = hours * 3600 + (minutes - offset_minutes) * 60;
+    u64::try_from((days * 86_400 + seconds_of_day) * 1000 + (seconds * 1000.0).round() as i64).ok()
+}
+
+/// Number at the end of a file name, e.g. the part number of `video_part_12.m4s`
+fn trailing_number(uri: &str) -> Option<u32> {
+    let stem = uri.rsplit_once('.').map_or(uri, |(stem, _)| stem);
+    let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    stem[stem.len() - digits..].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{create_cap_hls_segmenter, AudioCodec, EncodedAudioSegment};
+
+    fn audio_segment(sequence: u32, duration: f64) -> EncodedAudioSegment {
+        EncodedAudioSegment {
+            data: vec![0; 16],
+            codec: AudioCodec::AAC,
+            sequence,
+            duration,
+            timestamp: 0,
+            sample_rate: 48000,
+            channels: 2,
+            pts: sequence as i64 * 96000,
+            frames: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_segmenter_playlists_are_valid() {
+        let mut segmenter = create_cap_hls_segmenter("user".to_string(), "video".to_string());
+        segmenter.set_start_time(1_792_108_800_000);
+        for sequence in 0..7 {
+            segmenter.create_hls_segment(Some(audio_segment(sequence, 2.0)), None).unwrap();
+        }
+
+        let live = parse_media_playlist(&segmenter.generate_m3u8_playlist(PlaylistType::Audio), PlaylistType::Audio).unwrap();
+        assert_eq!(live.media_sequence, 2);
+        assert_eq!(live.segments.len(), 5);
+        assert_eq!(live.segments[0].audio_url, "audio_recording_2.aac");
+        assert_eq!(live.segments[0].program_date_time, 1_792_108_804_000);
+        assert!(validate_media_playlist(&live, false).is_empty());
+
+        segmenter.create_hls_segment(Some(audio_segment(7, 2.0)), None).unwrap();
+        let reloaded = parse_media_playlist(&segmenter.generate_m3u8_playlist(PlaylistType::Audio), PlaylistType::Audio).unwrap();
+        assert!(validate_playlist_reload(&live, &reloaded).is_empty());
+
+        segmenter.finish();
+        let archive = parse_media_playlist(&segmenter.generate_archive_playlist(PlaylistType::Audio), PlaylistType::Audio).unwrap();
+        assert_eq!(archive.segments.len(), 8);
+        assert!(validate_media_playlist(&archive, true).is_empty());
+
+        let master = parse_master_playlist(&segmenter.generate_master_playlist()).unwrap();
+        assert_eq!(master.variants[0].audio_group.as_deref(), Some("audio"));
+        assert!(validate_master_playlist(&master, &["video/stream.m3u8", "audio/stream.m3u8"]).is_empty());
+    }
+
+    #[test]
+    fn test_validator_reports_broken_playlists() {
+        let playlist = parse_media_playlist(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:4\n#EXT-X-PLAYLIST-TYPE:VOD\n\
+             #EXTINF:2.000,\naudio/audio_recording_4.aac\n#EXTINF:2.600,\naudio/audio_recording_5.aac\n",
+            PlaylistType::Audio,
+        ).unwrap();
+        assert_eq!(validate_media_playlist(&playlist, false), vec![
+            PlaylistIssue::SegmentExceedsTargetDuration { sequence_number: 5, duration: 2.6, target_duration: 2 },
+            PlaylistIssue::MissingEndList,
+        ]);
+
+        let mut later = playlist.clone();
+        later.media_sequence = 9;
+        assert_eq!(validate_playlist_reload(&playlist, &later), vec![PlaylistIssue::MediaSequenceGap { expected: 6, found: 9 }]);
+
+        let master = parse_master_playlist(
+            "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.640028,mp4a.40.2\",AUDIO=\"aac\"\nvideo/stream.m3u8\n",
+        ).unwrap();
+        assert_eq!(validate_master_playlist(&master, &["audio/stream.m3u8"]), vec![
+            PlaylistIssue::UnknownRenditionGroup { group_id: "aac".to_string() },
+            PlaylistIssue::MissingMediaPlaylist { uri: "video/stream.m3u8".to_string() },
+            PlaylistIssue::UnreferencedMediaPlaylist { uri: "audio/stream.m3u8".to_string() },
+        ]);
+        assert!(parse_media_playlist("#EXTINF:2.0,\nsegment.ts\n", PlaylistType::Audio).is_err());
+    }
+}
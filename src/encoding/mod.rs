@@ -4,18 +4,46 @@
 //! audio and video processing, following their real-time HLS streaming approach.
 
 pub mod audio_encoder;
+pub mod dash;
+pub mod fmp4;
+pub mod fs_sink;
+pub mod opus_encoder;
+pub mod presigned_sink;
 pub mod video_encoder;
 pub mod hls;
+pub mod integrity;
+pub mod m3u8;
 pub mod s3_uploader;
+pub mod sink;
+pub mod ts_muxer;
+pub mod upload_queue;
+pub mod upload_scheduler;
+pub mod webm;
 
-pub use audio_encoder::{AudioEncoder, EncodedAudioSegment, create_transcription_encoder};
-pub use video_encoder::{VideoEncoder, EncodedVideoSegment, create_screen_recording_encoder};
-pub use hls::{HLSSegmenter, HLSSegment, HLSPlaylist, PlaylistType, S3ContentType, create_cap_hls_segmenter};
-pub use s3_uploader::{S3Uploader, UploadConfig, create_cap_s3_uploader};
+pub use audio_encoder::{AudioEncoder, EncodedAudioSegment, EncodedAudioFrame, EncodedAudioPart, create_transcription_encoder};
+pub use dash::{DashManifest, MpdType};
+pub use fmp4::{CmafAudioMuxer, CmafFragment, CmafVideoMuxer};
+pub use fs_sink::FileSystemSink;
+pub use opus_encoder::OpusEncoder;
+pub use presigned_sink::{PresignProvider, PresignRequest, PresignedRequest, HttpPresignProvider, PresignedPutSink};
+pub use video_encoder::{VideoEncoder, EncodedVideoSegment, EncodedVideoFrame, EncodedVideoPart, VIDEO_CLOCK_RATE, create_screen_recording_encoder};
+pub use hls::{HLSSegmenter, HLSSegment, HLSPart, HLSPlaylist, PartialSegments, MediaPlaylistType, PlaylistType, StreamInfo, S3ContentType, create_cap_hls_segmenter, object_key, sequence_from_key};
+pub use integrity::{ChecksumAlgorithm, ManifestEntry, ObjectDigest, SessionManifest, md5_hex};
+pub use m3u8::{MasterPlaylist, PlaylistIssue, Rendition, VariantStream, parse_master_playlist, parse_media_playlist, validate_master_playlist, validate_media_playlist, validate_playlist_reload};
+pub use s3_uploader::{S3Uploader, UploadConfig, MultipartConfig, ObjectHeadersConfig, S3Credentials, ServerSideEncryption, create_cap_s3_uploader, multipart_state_path};
+pub use sink::{SegmentSink, SinkFuture, SinkConfig, SinkKind, MemorySink, HttpPutSink, create_sink};
+pub use ts_muxer::{TsMuxer, mux_ts_segment};
+pub use upload_queue::{RetryConfig, UploadOutcome, UploadQueue, retry_with_backoff};
+pub use upload_scheduler::{UploadPermit, UploadPriority, UploadScheduler};
+pub use webm::{WebmMuxer, WebmChunk, WebmVideoTrack, WebmAudioTrack};
 
+use crate::config::Resolution;
+use napi_derive::napi;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// Encoding configuration
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncodingConfig {
     /// Audio encoding settings
@@ -29,9 +57,10 @@ pub struct EncodingConfig {
 }
 
 /// Audio encoding configuration
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioEncodingConfig {
-    /// Audio codec (AAC)
+    /// Audio codec (AAC, or Opus for WebM)
     pub codec: AudioCodec,
     /// Bitrate in bits per second
     pub bitrate: u32,
@@ -44,23 +73,109 @@ pub struct AudioEncodingConfig {
 }
 
 /// Video encoding configuration
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoEncodingConfig {
-    /// Video codec (H.264)
+    /// Video codec (H.264, HEVC, or VP9/AV1 for WebM)
     pub codec: VideoCodec,
     /// Bitrate in bits per second
     pub bitrate: u32,
     /// Frame rate (fps)
-    pub frame_rate: (u32, u32),
+    pub frame_rate: FrameRate,
     /// Video resolution
-    pub resolution: (u32, u32),
+    pub resolution: Resolution,
     /// Pixel format
     pub pixel_format: PixelFormat,
     /// Hardware acceleration
     pub hardware_acceleration: bool,
+    /// Encoder speed/quality preset: x264/x265/SVT-AV1 `preset`, libvpx/libaom `cpu-used`
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Codec profile (e.g. "high")
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Keyframe interval in frames, defaults to one keyframe per segment
+    #[serde(default)]
+    pub gop_size: Option<u32>,
 }
 
+impl VideoEncodingConfig {
+    /// RFC 6381 codec string for the configured profile, resolution and frame rate
+    ///
+    /// H.264 encodes the profile and the smallest level that fits the stream;
+    /// other codecs use the codec's default profile.
+    pub fn codecs_attribute(&self) -> String {
+        if !matches!(self.codec, VideoCodec::H264) {
+            return self.codec.codecs_attribute().to_string();
+        }
+
+        let profile = match self.profile.as_deref().or(self.codec.default_profile()) {
+            Some("baseline") => "42e0",
+            Some("main") => "4d40",
+            _ => "6400",
+        };
+
+        // Macroblocks per frame and per second against the H.264 level limits
+        let frame_size = self.resolution.width.div_ceil(16) as u64 * self.resolution.height.div_ceil(16) as u64;
+        let rate = (frame_size as f64 * self.frame_rate.as_f64()).ceil() as u64;
+        let level = [(3600, 108_000, 31), (8192, 245_760, 40), (8704, 522_240, 42), (36_864, 983_040, 51), (36_864, 2_073_600, 52)]
+            .iter()
+            .find(|(max_frame, max_rate, _)| frame_size <= *max_frame && rate <= *max_rate)
+            .map_or(60, |(_, _, level)| *level);
+
+        format!("avc1.{}{:02x}", profile, level)
+    }
+}
+
+/// Frame rate as a rational number of frames per second
+///
+/// JSON configs may also give it in the older `[numerator, denominator]` form.
+#[napi(object)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "FrameRateRepr")]
+pub struct FrameRate {
+    /// Frames
+    pub numerator: u32,
+    /// Per this many seconds
+    pub denominator: u32,
+}
+
+/// Accepted JSON forms of `FrameRate`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FrameRateRepr {
+    Fields { numerator: u32, denominator: u32 },
+    Tuple(u32, u32),
+}
+
+impl From<FrameRateRepr> for FrameRate {
+    fn from(repr: FrameRateRepr) -> Self {
+        match repr {
+            FrameRateRepr::Fields { numerator, denominator } | FrameRateRepr::Tuple(numerator, denominator) => Self::new(numerator, denominator),
+        }
+    }
+}
+
+impl FrameRate {
+    /// Create a frame rate from numerator and denominator
+    pub fn new(numerator: u32, denominator: u32) -> Self {
+        Self { numerator, denominator }
+    }
+
+    /// Frames per second as a float
+    pub fn as_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator.max(1) as f64
+    }
+}
+
+/// Cap's HLS segment length in seconds, for encoders created outside a recording
+pub const DEFAULT_SEGMENT_DURATION: f64 = 2.0;
+
+/// Range of LL-HLS part durations in seconds, up to a third of a second
+pub const PART_DURATION_RANGE: std::ops::RangeInclusive<f64> = 0.2..=0.334;
+
 /// HLS configuration
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HLSConfig {
     /// Segment duration in seconds
@@ -68,24 +183,173 @@ pub struct HLSConfig {
     /// Target duration for playlist
     pub target_duration: u32,
     /// Number of segments to keep in playlist
-    pub playlist_size: usize,
+    pub playlist_size: u32,
+    /// Segment container, defaults to MPEG-TS
+    #[serde(default)]
+    pub segment_format: Option<SegmentFormat>,
+    /// What happens to segments that leave the playlist window, defaults to keeping them
+    #[serde(default)]
+    pub retention: Option<SegmentRetention>,
+    /// Seconds an evicted segment stays available before it is deleted, defaults to 30
+    #[serde(default)]
+    pub cleanup_grace_seconds: Option<u32>,
+    /// Duration of Low-Latency HLS partial segments in seconds, 0.2-0.333;
+    /// unset disables LL-HLS. Requires CMAF segments
+    #[serde(default)]
+    pub part_duration: Option<f64>,
+    /// Whether the origin in front of the sinks holds `_HLS_msn`/`_HLS_part`
+    /// playlist requests, so LL-HLS playlists may advertise blocking reloads
+    #[serde(default)]
+    pub blocking_reload: Option<bool>,
+}
+
+impl HLSConfig {
+    /// Whether segments are packaged as fragmented MP4 (CMAF)
+    pub fn is_cmaf(&self) -> bool {
+        matches!(self.segment_format, Some(SegmentFormat::Cmaf))
+    }
+
+    /// Whether segments that leave the playlist window are deleted
+    pub fn is_live_only(&self) -> bool {
+        matches!(self.retention, Some(SegmentRetention::LiveWindow))
+    }
+
+    /// Delay between a segment leaving the playlist and its deletion
+    pub fn cleanup_grace(&self) -> Duration {
+        Duration::from_secs(self.cleanup_grace_seconds.unwrap_or(30) as u64)
+    }
+
+    /// Whether playlists carry LL-HLS partial segments
+    pub fn is_low_latency(&self) -> bool {
+        self.part_duration.is_some()
+    }
+}
+
+/// Retention of segments that leave the live playlist window
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SegmentRetention {
+    /// Keep every segment, e.g. for VOD playback after the recording
+    KeepAll,
+    /// Delete segments once they have left the playlist window, for 24/7 live streams
+    LiveWindow,
+}
+
+/// HLS segment container
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SegmentFormat {
+    /// `.ts`/`.aac` segments (HLS version 3)
+    MpegTs,
+    /// `init.mp4` plus `.m4s` fragments (HLS version 7, also usable for DASH)
+    Cmaf,
 }
 
 /// Audio codec options
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum AudioCodec {
     AAC,
+    Opus,
+}
+
+impl AudioCodec {
+    /// RFC 6381 codec string, as used in HLS `CODECS`; AAC is encoded as AAC-LC
+    pub fn codecs_attribute(&self) -> &'static str {
+        match self {
+            AudioCodec::AAC => "mp4a.40.2",
+            AudioCodec::Opus => "opus",
+        }
+    }
+
+    /// Matroska `CodecID` of the track
+    pub fn matroska_codec_id(&self) -> &'static str {
+        match self {
+            AudioCodec::AAC => "A_AAC",
+            AudioCodec::Opus => "A_OPUS",
+        }
+    }
 }
 
 /// Video codec options
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum VideoCodec {
     H264,
     H265,
+    VP9,
+    AV1,
+}
+
+impl VideoCodec {
+    /// FFmpeg encoders implementing this codec, in order of preference
+    pub fn encoder_names(&self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &["libx264"],
+            VideoCodec::H265 => &["libx265"],
+            VideoCodec::VP9 => &["libvpx-vp9"],
+            VideoCodec::AV1 => &["libsvtav1", "libaom-av1"],
+        }
+    }
+
+    /// Profile used when the config does not specify one
+    pub fn default_profile(&self) -> Option<&'static str> {
+        match self {
+            VideoCodec::H264 => Some("high"),
+            VideoCodec::H265 => Some("main"),
+            VideoCodec::VP9 | VideoCodec::AV1 => None,
+        }
+    }
+
+    /// Whether the codec can be stored in a WebM container
+    pub fn is_webm_compatible(&self) -> bool {
+        matches!(self, VideoCodec::VP9 | VideoCodec::AV1)
+    }
+
+    /// Matroska `CodecID` of the track
+    pub fn matroska_codec_id(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "V_MPEG4/ISO/AVC",
+            VideoCodec::H265 => "V_MPEGH/ISO/HEVC",
+            VideoCodec::VP9 => "V_VP9",
+            VideoCodec::AV1 => "V_AV1",
+        }
+    }
+
+    /// ISO BMFF sample entry type; HEVC uses `hvc1` (parameter sets
+    /// out-of-band) because Apple's HLS players reject `hev1`
+    pub fn sample_entry(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "avc1",
+            VideoCodec::H265 => "hvc1",
+            VideoCodec::VP9 => "vp09",
+            VideoCodec::AV1 => "av01",
+        }
+    }
+
+    /// MPEG-TS `stream_type` for the elementary stream, if TS can carry it
+    pub fn mpegts_stream_type(&self) -> Option<u8> {
+        match self {
+            VideoCodec::H264 => Some(0x1B),
+            VideoCodec::H265 => Some(0x24),
+            VideoCodec::VP9 | VideoCodec::AV1 => None,
+        }
+    }
+
+    /// RFC 6381 codec string for the default profile, as used in HLS `CODECS`
+    pub fn codecs_attribute(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "avc1.640028",
+            VideoCodec::H265 => "hvc1.1.6.L120.90",
+            VideoCodec::VP9 => "vp09.00.40.08",
+            VideoCodec::AV1 => "av01.0.08M.08",
+        }
+    }
 }
 
 /// Audio channel layout
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum AudioChannelLayout {
     Mono,
     Stereo,
@@ -93,7 +357,8 @@ pub enum AudioChannelLayout {
 }
 
 /// Pixel format for video
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum PixelFormat {
     YUV420P,
     RGBA,
@@ -128,10 +393,13 @@ impl Default for VideoEncodingConfig {
         Self {
             codec: VideoCodec::H264,
             bitrate: 2000000, // 2Mbps
-            frame_rate: (30, 1), // 30fps
-            resolution: (1920, 1080),
+            frame_rate: FrameRate::new(30, 1), // 30fps
+            resolution: Resolution::new(1920, 1080),
             pixel_format: PixelFormat::YUV420P,
             hardware_acceleration: true,
+            preset: None,
+            profile: None,
+            gop_size: None,
         }
     }
 }
@@ -139,9 +407,31 @@ impl Default for VideoEncodingConfig {
 impl Default for HLSConfig {
     fn default() -> Self {
         Self {
-            segment_duration: 2.0, // 2-second segments like Cap
+            segment_duration: DEFAULT_SEGMENT_DURATION,
             target_duration: 2,
             playlist_size: 5,
+            segment_format: None,
+            retention: None,
+            cleanup_grace_seconds: None,
+            part_duration: None,
+            blocking_reload: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_rate_and_resolution_accept_tuples() {
+        let mut json = serde_json::to_value(VideoEncodingConfig::default()).unwrap();
+        assert_eq!(json["frame_rate"], serde_json::json!({ "numerator": 30, "denominator": 1 }));
+
+        json["frame_rate"] = serde_json::json!([60, 1]);
+        json["resolution"] = serde_json::json!([1280, 720]);
+        let config: VideoEncodingConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(config.frame_rate, FrameRate::new(60, 1));
+        assert_eq!(config.resolution, Resolution::new(1280, 720));
+    }
+}
@@ -0,0 +1,215 @@
+//! FFmpeg-based Opus Encoder
+//!
+//! Real-time libopus encoding for WebM recordings. Segments follow the AAC
+//! encoder's layout so audio and video still pair up by sequence.
+
+use super::{AudioCodec, AudioEncodingConfig, EncodedAudioFrame, EncodedAudioSegment};
+use super::audio_encoder::AudioEncodingError;
+use ffmpeg::{
+    codec::{context, encoder},
+    format::{sample::Type, Sample},
+    ChannelLayout, Dictionary, Rational,
+};
+
+/// Sample rates libopus accepts natively
+const OPUS_SAMPLE_RATES: [u32; 5] = [48000, 24000, 16000, 12000, 8000];
+
+/// Opus encoder producing 20ms packets grouped into segments
+pub struct OpusEncoder {
+    encoder: encoder::Audio,
+    packet: ffmpeg::Packet,
+    config: AudioEncodingConfig,
+    /// Samples per channel in each encoded frame
+    frame_size: usize,
+    frames_per_segment: usize,
+    /// Encoder lookahead in samples at the configured rate
+    initial_padding: i64,
+    sequence_counter: u32,
+    pts: i64,
+    pending_samples: Vec<f32>,
+    current_data: Vec<u8>,
+    current_frames: Vec<EncodedAudioFrame>,
+    flushed: bool,
+}
+
+impl OpusEncoder {
+    // libopus takes interleaved float input directly, so no resampler is needed
+    const SAMPLE_FORMAT: Sample = Sample::F32(Type::Packed);
+
+    /// Create a new libopus encoder
+    pub fn new(config: AudioEncodingConfig, segment_duration: f64) -> Result<Self, AudioEncodingError> {
+        ffmpeg::init().map_err(|e| AudioEncodingError::Other(format!("FFmpeg init: {}", e)))?;
+
+        if !OPUS_SAMPLE_RATES.contains(&config.sample_rate) {
+            return Err(AudioEncodingError::Other(format!(
+                "Opus does not support sample rate {}", config.sample_rate
+            )));
+        }
+        // Channel mapping family 0 only covers mono and stereo
+        if !(1..=2).contains(&config.channels) {
+            return Err(AudioEncodingError::Other(format!(
+                "Opus encoding supports mono or stereo, got {} channels", config.channels
+            )));
+        }
+
+        let codec = encoder::find_by_name("libopus")
+            .ok_or_else(|| AudioEncodingError::TaskLaunch("Could not find libopus codec".into()))?;
+
+        let mut encoder = context::Context::new_with_codec(codec).encoder().audio()?;
+        encoder.set_bit_rate(config.bitrate as usize);
+        encoder.set_rate(config.sample_rate as i32);
+        encoder.set_format(Self::SAMPLE_FORMAT);
+        encoder.set_channel_layout(ChannelLayout::default(config.channels as i32));
+        encoder.set_time_base(Rational(1, config.sample_rate as i32));
+
+        let mut options = Dictionary::new();
+        options.set("application", "audio");
+        let encoder = encoder.open_with(options)?;
+
+        let frame_size = match encoder.frame_size() as usize {
+            0 => config.sample_rate as usize / 50, // 20ms
+            size => size,
+        };
+        // SAFETY: the context is open and owned by `encoder`
+        let initial_padding = unsafe { (*encoder.as_ptr()).initial_padding } as i64;
+
+        Ok(Self {
+            encoder,
+            packet: ffmpeg::Packet::empty(),
+            frame_size,
+            frames_per_segment: ((config.sample_rate as f64 * segment_duration) as usize / frame_size).max(1),
+            initial_padding,
+            sequence_counter: 0,
+            pts: 0,
+            pending_samples: Vec::new(),
+            current_data: Vec::new(),
+            current_frames: Vec::new(),
+            flushed: false,
+            config,
+        })
+    }
+
+    /// `OpusHead` identification header (RFC 7845) describing this stream
+    pub fn opus_head(&self) -> Vec<u8> {
+        // Pre-skip is always expressed at 48kHz
+        let pre_skip = (self.initial_padding * 48000 / self.config.sample_rate as i64) as u16;
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // Version
+        head.push(self.config.channels as u8);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&self.config.sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+        head.push(0); // Channel mapping family
+        head
+    }
+
+    /// Change the segment length, e.g. to emit LL-HLS parts; takes effect from the next segment
+    pub fn set_segment_duration(&mut self, seconds: f64) {
+        self.frames_per_segment = ((self.config.sample_rate as f64 * seconds) as usize / self.frame_size).max(1);
+    }
+
+    /// Encode interleaved PCM samples, returning completed segments
+    pub fn process_audio(&mut self, pcm_data: &[f32]) -> Result<Vec<EncodedAudioSegment>, AudioEncodingError> {
+        if self.flushed {
+            return Err(AudioEncodingError::Other("Encoder has already been flushed".to_string()));
+        }
+
+        let mut segments = Vec::new();
+        let samples_per_frame = self.frame_size * self.config.channels as usize;
+        self.pending_samples.extend_from_slice(pcm_data);
+
+        while self.pending_samples.len() >= samples_per_frame {
+            let chunk: Vec<f32> = self.pending_samples.drain(..samples_per_frame).collect();
+            self.encode_frame(&chunk)?;
+
+            if self.current_frames.len() >= self.frames_per_segment {
+                segments.extend(self.finish_segment());
+            }
+        }
+
+        Ok(segments)
+    }
+
+    /// Encode one full frame of interleaved samples
+    fn encode_frame(&mut self, samples: &[f32]) -> Result<(), AudioEncodingError> {
+        let mut frame = ffmpeg::frame::Audio::new(
+            Self::SAMPLE_FORMAT,
+            self.frame_size,
+            ChannelLayout::default(self.config.channels as i32),
+        );
+        frame.set_rate(self.config.sample_rate);
+        frame.set_pts(Some(self.pts));
+
+        for (bytes, sample) in frame.data_mut(0).chunks_exact_mut(4).zip(samples) {
+            bytes.copy_from_slice(&sample.to_ne_bytes());
+        }
+
+        self.pts += self.frame_size as i64;
+        self.encoder.send_frame(&frame)?;
+        self.receive_packets();
+        Ok(())
+    }
+
+    /// Append encoded packets to the current segment
+    fn receive_packets(&mut self) {
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            let Some(data) = self.packet.data() else {
+                continue;
+            };
+
+            // Packet timestamps are shifted back by the lookahead; containers
+            // signal that through the pre-skip instead
+            let pts = self.packet.pts().unwrap_or(0) + self.initial_padding;
+            self.current_frames.push(EncodedAudioFrame {
+                offset: self.current_data.len(),
+                size: data.len(),
+                pts,
+            });
+            self.current_data.extend_from_slice(data);
+        }
+    }
+
+    /// Close the current segment
+    fn finish_segment(&mut self) -> Option<EncodedAudioSegment> {
+        let first_pts = self.current_frames.first()?.pts;
+        let frames = std::mem::take(&mut self.current_frames);
+        let sample_rate = self.config.sample_rate as f64;
+
+        let segment = EncodedAudioSegment {
+            data: std::mem::take(&mut self.current_data),
+            codec: AudioCodec::Opus,
+            sequence: self.sequence_counter,
+            duration: (frames.len() * self.frame_size) as f64 / sample_rate,
+            timestamp: (first_pts as f64 / sample_rate * 1000.0) as u64,
+            sample_rate: self.config.sample_rate,
+            channels: self.config.channels,
+            pts: first_pts,
+            frames,
+        };
+
+        self.sequence_counter += 1;
+        Some(segment)
+    }
+
+    /// Flush remaining audio data
+    pub fn flush(&mut self) -> Result<Vec<EncodedAudioSegment>, AudioEncodingError> {
+        // The encoder has already received EOF; flushing again is a no-op
+        if self.flushed {
+            return Ok(Vec::new());
+        }
+        self.flushed = true;
+
+        if !self.pending_samples.is_empty() {
+            // Pad the final partial frame with silence
+            let mut chunk = std::mem::take(&mut self.pending_samples);
+            chunk.resize(self.frame_size * self.config.channels as usize, 0.0);
+            self.encode_frame(&chunk)?;
+        }
+
+        self.encoder.send_eof()?;
+        self.receive_packets();
+
+        Ok(self.finish_segment().into_iter().collect())
+    }
+}
@@ -0,0 +1,279 @@
+//! Presigned URL Sink
+//!
+//! Uploads with plain HTTP to presigned URLs, so the machine recording never
+//! holds AWS credentials. A `PresignProvider`, such as a backend endpoint or
+//! a JavaScript callback, signs a `PUT` (or `DELETE`) per object key.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{ObjectDigest, S3ContentType, SegmentSink, SinkFuture, object_key};
+use super::sink::http_upload_error;
+use napi_derive::napi;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Request for a presigned URL
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignRequest {
+    /// HTTP method the URL is for, `PUT` or `DELETE`
+    pub method: String,
+    /// Object key
+    pub key: String,
+    /// MIME type the object is uploaded with
+    pub content_type: String,
+    /// Payload size in bytes, 0 for `DELETE`
+    pub content_length: i64,
+    /// Base64 SHA-256 of the payload, for providers that sign `x-amz-checksum-sha256`
+    pub checksum_sha256: Option<String>,
+}
+
+/// Presigned URL and the headers that must be sent with it
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignedRequest {
+    /// URL to send the request to
+    pub url: String,
+    /// Headers covered by the signature
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+}
+
+/// Source of presigned URLs
+pub trait PresignProvider: Send + Sync {
+    /// Sign a request for one object
+    fn presign(&self, request: PresignRequest) -> SinkFuture<'_, PresignedRequest>;
+}
+
+/// Provider that asks an HTTP endpoint for presigned URLs
+///
+/// The endpoint receives a `PresignRequest` as a JSON `POST` body and answers
+/// with a `PresignedRequest` as JSON.
+pub struct HttpPresignProvider {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: HashMap<String, String>,
+}
+
+impl HttpPresignProvider {
+    /// Create a provider for the given endpoint; `headers` typically carry the user's session token
+    pub fn new(endpoint: String, headers: HashMap<String, String>, timeout: Duration) -> CaptureResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| CaptureError::Config(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self { client, endpoint, headers })
+    }
+}
+
+impl PresignProvider for HttpPresignProvider {
+    fn presign(&self, request: PresignRequest) -> SinkFuture<'_, PresignedRequest> {
+        Box::pin(async move {
+            let body = serde_json::to_vec(&request)
+                .map_err(|e| CaptureError::Upload(format!("Failed to serialize presign request: {}", e)))?;
+            let http_request = self.client
+                .post(&self.endpoint)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+            let http_request = self.headers.iter().fold(http_request, |http_request, (name, value)| http_request.header(name, value));
+
+            let response = http_request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| http_upload_error(format!("Presign request for {} failed", request.key), e))?;
+            let bytes = response.bytes()
+                .await
+                .map_err(|e| CaptureError::Upload(format!("Presign request for {} failed: {}", request.key, e)))?;
+
+            serde_json::from_slice(&bytes)
+                .map_err(|e| CaptureError::Upload(format!("Invalid presign response for {}: {}", request.key, e)))
+        })
+    }
+}
+
+/// Sink that uploads every object to a URL presigned by a `PresignProvider`
+pub struct PresignedPutSink {
+    provider: Arc<dyn PresignProvider>,
+    client: reqwest::Client,
+    id: String,
+    user_id: String,
+    video_id: String,
+}
+
+impl PresignedPutSink {
+    /// Create a sink; `id` names the sink's spool directory and should be stable across restarts
+    pub fn new(
+        provider: Arc<dyn PresignProvider>,
+        id: String,
+        timeout: Duration,
+        user_id: String,
+        video_id: String,
+    ) -> CaptureResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| CaptureError::Config(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self { provider, client, id, user_id, video_id })
+    }
+
+    async fn send(&self, method: reqwest::Method, request: PresignRequest, data: Vec<u8>) -> CaptureResult<String> {
+        let key = request.key.clone();
+        let content_type = request.content_type.clone();
+        let presigned = self.provider.presign(request).await?;
+
+        // The provider's headers win, so a signed Content-Type is sent exactly as signed
+        let signs_content_type = presigned.headers.iter().flatten()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+        let http_request = self.client.request(method, &presigned.url).body(data);
+        let http_request = if signs_content_type {
+            http_request
+        } else {
+            http_request.header(reqwest::header::CONTENT_TYPE, content_type)
+        };
+        let http_request = presigned.headers.iter().flatten()
+            .fold(http_request, |http_request, (name, value)| http_request.header(name, value));
+
+        http_request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| http_upload_error(format!("Presigned request for {} failed", key), e))?;
+
+        Ok(key)
+    }
+}
+
+impl SegmentSink for PresignedPutSink {
+    fn name(&self) -> &str {
+        "presigned"
+    }
+
+    fn id(&self) -> String {
+        format!("presigned-{}", self.id)
+    }
+
+    fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        object_key(&self.user_id, &self.video_id, content_type, sequence)
+    }
+
+    fn put_object(&self, key: String, content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String> {
+        Box::pin(async move {
+            let content_length = i64::try_from(data.len())
+                .map_err(|_| CaptureError::UploadRejected(format!("{} is too large to presign", key)))?;
+            let request = PresignRequest {
+                method: "PUT".to_string(),
+                key,
+                content_type: content_type.mime_type().to_string(),
+                content_length,
+                checksum_sha256: Some(ObjectDigest::of(&data).sha256_base64()),
+            };
+            self.send(reqwest::Method::PUT, request, data).await
+        })
+    }
+
+    fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String> {
+        Box::pin(async move {
+            let request = PresignRequest {
+                method: "DELETE".to_string(),
+                key: self.object_key(&content_type, sequence),
+                content_type: content_type.mime_type().to_string(),
+                content_length: 0,
+                checksum_sha256: None,
+            };
+            self.send(reqwest::Method::DELETE, request, Vec::new()).await
+        })
+    }
+
+    fn finalize(&self) -> SinkFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP server: `POST /presign` signs `/upload/<key>`, `PUT /upload/<key>` stores the body
+    async fn stub_server(stored: Arc<Mutex<HashMap<String, Vec<u8>>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let url = base_url.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (stored, url) = (stored.clone(), url.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 4096];
+                    let (head, body_start, content_length) = loop {
+                        let read = socket.read(&mut buffer).await.unwrap();
+                        request.extend_from_slice(&buffer[..read]);
+                        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                            let head = String::from_utf8_lossy(&request[..end]).to_string();
+                            let content_length = head.lines()
+                                .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse::<usize>().unwrap()))
+                                .unwrap_or(0);
+                            break (head, end + 4, content_length);
+                        }
+                    };
+                    while request.len() < body_start + content_length {
+                        let read = socket.read(&mut buffer).await.unwrap();
+                        request.extend_from_slice(&buffer[..read]);
+                    }
+                    let body = request[body_start..body_start + content_length].to_vec();
+
+                    let mut request_line = head.lines().next().unwrap().split(' ');
+                    let (method, path) = (request_line.next().unwrap(), request_line.next().unwrap());
+                    let response_body = match method {
+                        "POST" => {
+                            let request: PresignRequest = serde_json::from_slice(&body).unwrap();
+                            serde_json::to_string(&PresignedRequest {
+                                url: format!("{}/upload/{}", url, request.key),
+                                headers: Some(HashMap::from([("x-signature".to_string(), "stub".to_string())])),
+                            }).unwrap()
+                        }
+                        _ => {
+                            let signed = head.lines().any(|line| line.eq_ignore_ascii_case("x-signature: stub"));
+                            assert!(signed, "presigned headers were not sent");
+                            let typed = head.lines().any(|line| line.eq_ignore_ascii_case("content-type: audio/aac"));
+                            assert!(typed, "Content-Type was not sent");
+                            stored.lock().unwrap().insert(path.trim_start_matches("/upload/").to_string(), body);
+                            String::new()
+                        }
+                    };
+
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response_body.len(), response_body
+                    );
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        base_url
+    }
+
+    #[tokio::test]
+    async fn test_uploads_to_presigned_urls() {
+        let stored = Arc::new(Mutex::new(HashMap::new()));
+        let base_url = stub_server(stored.clone()).await;
+
+        let provider = HttpPresignProvider::new(format!("{}/presign", base_url), HashMap::new(), Duration::from_secs(5)).unwrap();
+        let sink = PresignedPutSink::new(Arc::new(provider), "test".to_string(), Duration::from_secs(5), "user".to_string(), "video".to_string()).unwrap();
+
+        let key = sink.put_segment(S3ContentType::AudioSegment, 1, vec![1, 2, 3]).await.unwrap();
+        assert_eq!(key, "user/video/audio/audio_recording_1.aac");
+        assert_eq!(stored.lock().unwrap().get(&key), Some(&vec![1, 2, 3]));
+    }
+}
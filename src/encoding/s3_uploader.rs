@@ -3,14 +3,32 @@
 //! Implements Cap's S3 upload strategy for real-time HLS streaming
 
 use crate::error::{CaptureError, CaptureResult};
-use super::{EncodedAudioSegment, EncodedVideoSegment, S3ContentType};
-use aws_sdk_s3::{Client, config::Region};
-use aws_config::load_defaults;
+use super::{ChecksumAlgorithm, EncodedAudioSegment, EncodedVideoSegment, ObjectDigest, RetryConfig, S3ContentType, md5_hex, object_key, mux_ts_segment, retry_with_backoff, sequence_from_key};
+use super::fs_sink::write_atomic;
+use super::upload_queue::is_permanent_status;
+use aws_sdk_s3::{Client, config::{Credentials, Region, http::HttpResponse}, error::SdkError};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, ServerSideEncryption as S3ServerSideEncryption};
+use futures::StreamExt;
+use napi_derive::napi;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::time::timeout;
 
+/// Default Cache-Control of live playlists
+const PLAYLIST_CACHE_CONTROL: &str = "no-cache";
+/// Default Cache-Control of segments, which never change once uploaded
+const SEGMENT_CACHE_CONTROL: &str = "max-age=31536000, immutable";
+/// Smallest part size S3 accepts for all but the last part
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// Maximum number of parts in one multipart upload
+const MAX_PARTS: u64 = 10_000;
+
 /// S3 upload configuration
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadConfig {
     /// S3 bucket name
@@ -18,11 +36,152 @@ pub struct UploadConfig {
     /// AWS region
     pub region: String,
     /// Upload timeout in seconds
-    pub timeout_seconds: u64,
-    /// Whether to use accelerated transfer
+    pub timeout_seconds: u32,
+    /// Whether to use S3 Transfer Acceleration (AWS only, ignored with a custom endpoint)
     pub accelerated_transfer: bool,
     /// Custom endpoint (for S3-compatible services)
     pub endpoint: Option<String>,
+    /// Address buckets as `<endpoint>/<bucket>` instead of `<bucket>.<endpoint>`;
+    /// defaults to true with a custom endpoint, as MinIO, Ceph and LocalStack expect
+    #[serde(default)]
+    pub force_path_style: Option<bool>,
+    /// Static credentials instead of the default AWS credential chain
+    #[serde(default)]
+    pub credentials: Option<S3Credentials>,
+    /// Named profile from the shared AWS config files
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Retry policy for failed uploads
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+    /// Directory where pending uploads are spooled so they survive restarts
+    #[serde(default)]
+    pub spool_dir: Option<String>,
+    /// Multipart settings for full-length recordings
+    #[serde(default)]
+    pub multipart: Option<MultipartConfig>,
+    /// Cache-Control, server-side encryption and metadata of uploaded objects
+    #[serde(default)]
+    pub object_headers: Option<ObjectHeadersConfig>,
+    /// Maximum number of uploads in flight across all sinks, defaults to 4
+    #[serde(default)]
+    pub max_concurrent_uploads: Option<u32>,
+    /// Upload bandwidth cap in bytes per second across all sinks
+    #[serde(default)]
+    pub max_bytes_per_second: Option<u32>,
+    /// Checksum header sent with every object, defaults to SHA-256
+    #[serde(default)]
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+}
+
+/// Headers and metadata set on uploaded objects
+#[napi(object)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ObjectHeadersConfig {
+    /// Cache-Control of playlists and the manifest, defaults to `no-cache`
+    pub playlist_cache_control: Option<String>,
+    /// Cache-Control of segments and recordings, defaults to `max-age=31536000, immutable`
+    pub segment_cache_control: Option<String>,
+    /// Cache-Control by content type name (`S3ContentType::as_str`), e.g. `MasterPlaylist` or `AudioSegment`
+    pub cache_control: Option<HashMap<String, String>>,
+    /// Server-side encryption of every object
+    pub server_side_encryption: Option<ServerSideEncryption>,
+    /// KMS key for `Kms` encryption, the bucket's default key if unset
+    pub kms_key_id: Option<String>,
+    /// Metadata added to every object next to `user-id`, `session-id` and `sequence`
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// Server-side encryption mode
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerSideEncryption {
+    /// SSE-S3 with S3 managed keys (AES256)
+    S3,
+    /// SSE-KMS with an AWS KMS key
+    Kms,
+}
+
+/// Headers resolved for one object
+#[derive(Debug, Clone)]
+struct ObjectHeaders {
+    cache_control: String,
+    encryption: Option<S3ServerSideEncryption>,
+    kms_key_id: Option<String>,
+    metadata: HashMap<String, String>,
+}
+
+/// Static S3 credentials
+#[napi(object)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct S3Credentials {
+    /// Access key ID
+    pub access_key_id: String,
+    /// Secret access key
+    pub secret_access_key: String,
+    /// Session token for temporary credentials
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for S3Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep secrets out of logs
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UploadConfig {
+    /// Whether buckets are addressed path-style
+    pub fn path_style(&self) -> bool {
+        self.force_path_style.unwrap_or(self.endpoint.is_some())
+    }
+
+    /// Public URL of the bucket, used to build stream URLs
+    pub fn bucket_url(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) if self.path_style() => {
+                format!("{}/{}", endpoint.trim_end_matches('/'), self.bucket)
+            }
+            Some(endpoint) => match endpoint.trim_end_matches('/').split_once("://") {
+                Some((scheme, host)) => format!("{}://{}.{}", scheme, self.bucket, host),
+                None => format!("https://{}.{}", self.bucket, endpoint.trim_end_matches('/')),
+            },
+            None => format!("https://{}.s3.amazonaws.com", self.bucket),
+        }
+    }
+}
+
+/// Multipart upload settings
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartConfig {
+    /// Part size in MiB, at least 5
+    pub part_size_mb: u32,
+    /// Number of parts uploaded in parallel
+    pub max_concurrent_parts: u32,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        Self {
+            part_size_mb: 8,
+            max_concurrent_parts: 4,
+        }
+    }
+}
+
+/// Progress of a multipart upload, saved so an interrupted upload can resume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MultipartState {
+    bucket: String,
+    key: String,
+    upload_id: String,
+    file_size: u64,
+    part_size: u64,
+    /// ETags of the uploaded parts by part number
+    parts: BTreeMap<i32, String>,
 }
 
 /// S3 uploader for real-time streaming
@@ -39,18 +198,37 @@ impl S3Uploader {
     pub async fn new(config: UploadConfig, user_id: String, video_id: String) -> CaptureResult<Self> {
         log::info!("Initializing S3 uploader for user {} video {}", user_id, video_id);
 
-        // Load AWS configuration
-        let aws_config = load_defaults(aws_config::BehaviorVersion::latest()).await;
+        // Load AWS configuration, optionally from a named profile or static credentials
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(Region::new(config.region.clone()));
+        if let Some(profile) = &config.profile {
+            loader = loader.profile_name(profile);
+        }
+        if let Some(credentials) = &config.credentials {
+            loader = loader.credentials_provider(Credentials::new(
+                credentials.access_key_id.clone(),
+                credentials.secret_access_key.clone(),
+                credentials.session_token.clone(),
+                None,
+                "UploadConfig",
+            ));
+        }
+        let aws_config = loader.load().await;
         let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&aws_config);
 
-        // Set region
-        s3_config_builder = s3_config_builder.region(Region::new(config.region.clone()));
-
         // Set custom endpoint if provided
         if let Some(endpoint) = &config.endpoint {
             s3_config_builder = s3_config_builder.endpoint_url(endpoint);
         }
 
+        // Transfer Acceleration only exists on AWS
+        if config.accelerated_transfer && config.endpoint.is_some() {
+            log::warn!("Ignoring accelerated transfer for custom endpoint");
+        }
+        s3_config_builder = s3_config_builder
+            .accelerate(config.accelerated_transfer && config.endpoint.is_none())
+            .force_path_style(config.path_style());
+
         let s3_config = s3_config_builder.build();
         let client = Client::from_conf(s3_config);
 
@@ -64,13 +242,12 @@ impl S3Uploader {
 
     /// Upload audio segment immediately (real-time streaming)
     pub async fn upload_audio_segment_realtime(&self, segment: EncodedAudioSegment) -> CaptureResult<String> {
-        let key = format!("{}/{}/audio/audio_recording_{}.aac", 
-                         self.user_id, self.video_id, segment.sequence);
+        let key = self.object_key(&S3ContentType::AudioSegment, segment.sequence);
 
         self.upload_data_with_timeout(
             &key,
             segment.data,
-            S3ContentType::AudioSegment.mime_type()
+            &S3ContentType::AudioSegment
         ).await?;
 
         log::debug!("Uploaded audio segment {} to S3: {}", segment.sequence, key);
@@ -79,13 +256,13 @@ impl S3Uploader {
 
     /// Upload video segment immediately (real-time streaming)
     pub async fn upload_video_segment_realtime(&self, segment: EncodedVideoSegment) -> CaptureResult<String> {
-        let key = format!("{}/{}/video/video_recording_{}.ts", 
-                         self.user_id, self.video_id, segment.sequence);
+        let key = self.object_key(&S3ContentType::VideoSegment, segment.sequence);
+        let ts_data = mux_ts_segment(Some(&segment), None)?;
 
         self.upload_data_with_timeout(
             &key,
-            segment.data,
-            S3ContentType::VideoSegment.mime_type()
+            ts_data,
+            &S3ContentType::VideoSegment
         ).await?;
 
         log::debug!("Uploaded video segment {} to S3: {}", segment.sequence, key);
@@ -96,78 +273,358 @@ impl S3Uploader {
     pub async fn upload_combined_segment(&self, 
                                        audio_segment: EncodedAudioSegment,
                                        video_segment: EncodedVideoSegment) -> CaptureResult<String> {
-        // For combined segments, we need to mux audio and video
-        // This is a simplified approach - in production, use FFmpeg muxing
-        let combined_data = self.combine_audio_video(audio_segment.data, video_segment.data)?;
-        
-        let key = format!("{}/{}/combined-source/segment_{}.ts", 
-                         self.user_id, self.video_id, video_segment.sequence);
+        let combined_data = mux_ts_segment(Some(&video_segment), Some(&audio_segment))?;
+        self.upload_combined_ts(video_segment.sequence, combined_data).await
+    }
+
+    /// Upload an already muxed combined MPEG-TS segment
+    pub async fn upload_combined_ts(&self, sequence: u32, ts_data: Vec<u8>) -> CaptureResult<String> {
+        let key = self.object_key(&S3ContentType::CombinedSegment, sequence);
 
         self.upload_data_with_timeout(
             &key,
-            combined_data,
-            S3ContentType::CombinedSegment.mime_type()
+            ts_data,
+            &S3ContentType::CombinedSegment
         ).await?;
 
-        log::debug!("Uploaded combined segment {} to S3: {}", video_segment.sequence, key);
+        log::debug!("Uploaded combined segment {} to S3: {}", sequence, key);
+        Ok(key)
+    }
+
+    /// Upload already packaged segment data, such as fMP4 init segments and fragments
+    pub async fn upload_segment_data(&self, content_type: &S3ContentType, sequence: u32, data: Vec<u8>) -> CaptureResult<String> {
+        let key = self.object_key(content_type, sequence);
+
+        self.upload_data_with_timeout(&key, data, content_type).await?;
+
+        log::debug!("Uploaded {:?} {} to S3: {}", content_type, sequence, key);
+        Ok(key)
+    }
+
+    /// Upload data under an explicit key in a single attempt; the upload queue retries it
+    pub async fn upload_object(&self, key: String, content_type: &S3ContentType, data: Vec<u8>) -> CaptureResult<String> {
+        let headers = self.object_headers(Some(content_type), &key);
+        self.put_with_headers(&key, data, content_type.mime_type(), headers).await?;
+
+        log::debug!("Uploaded {:?} to S3: {}", content_type, key);
         Ok(key)
     }
 
     /// Update HLS playlist after new segment
     pub async fn update_playlist(&self, playlist_content: String, content_type: S3ContentType) -> CaptureResult<String> {
-        let key = match content_type {
-            S3ContentType::VideoPlaylist => {
-                format!("{}/{}/video/stream.m3u8", self.user_id, self.video_id)
-            },
-            S3ContentType::AudioPlaylist => {
-                format!("{}/{}/audio/stream.m3u8", self.user_id, self.video_id)
-            },
-            S3ContentType::CombinedPlaylist => {
-                format!("{}/{}/combined-source/stream.m3u8", self.user_id, self.video_id)
-            },
-            S3ContentType::MasterPlaylist => {
-                format!("{}/{}/stream.m3u8", self.user_id, self.video_id)
-            },
-            _ => return Err(CaptureError::Upload("Invalid playlist content type".to_string())),
-        };
+        if !content_type.is_playlist() {
+            return Err(CaptureError::Upload("Invalid playlist content type".to_string()));
+        }
+        let key = self.object_key(&content_type, 0);
 
         self.upload_data_with_timeout(
             &key,
             playlist_content.into_bytes(),
-            content_type.mime_type()
+            &content_type
         ).await?;
 
         log::debug!("Updated playlist: {}", key);
         Ok(key)
     }
 
-    /// Upload data with timeout (for real-time guarantees)
-    async fn upload_data_with_timeout(&self, key: &str, data: Vec<u8>, content_type: &str) -> CaptureResult<()> {
-        let upload_future = self.client
+    /// Object key for a segment or playlist in this session
+    pub fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        object_key(&self.user_id, &self.video_id, content_type, sequence)
+    }
+
+    /// Bucket objects are uploaded to
+    pub fn bucket(&self) -> &str {
+        &self.config.bucket
+    }
+
+    /// Cache-Control, encryption and metadata of an object; `None` stands for a full recording
+    fn object_headers(&self, content_type: Option<&S3ContentType>, key: &str) -> ObjectHeaders {
+        let config = self.config.object_headers.clone().unwrap_or_default();
+        let is_playlist = content_type.is_some_and(S3ContentType::is_replaced_in_place);
+
+        let cache_control = content_type
+            .and_then(|content_type| config.cache_control.as_ref()?.get(content_type.as_str()).cloned())
+            .or(if is_playlist { config.playlist_cache_control } else { config.segment_cache_control })
+            .unwrap_or_else(|| if is_playlist { PLAYLIST_CACHE_CONTROL } else { SEGMENT_CACHE_CONTROL }.to_string());
+
+        let (encryption, kms_key_id) = match config.server_side_encryption {
+            Some(ServerSideEncryption::S3) => (Some(S3ServerSideEncryption::Aes256), None),
+            Some(ServerSideEncryption::Kms) => (Some(S3ServerSideEncryption::AwsKms), config.kms_key_id),
+            None => (None, None),
+        };
+
+        // Spooled objects of earlier sessions keep the IDs from their key
+        let (user_id, session_id) = key_owner(key).unwrap_or((self.user_id.as_str(), self.video_id.as_str()));
+        let mut metadata = config.metadata.unwrap_or_default();
+        metadata.insert("user-id".to_string(), user_id.to_string());
+        metadata.insert("session-id".to_string(), session_id.to_string());
+        if let Some(sequence) = sequence_from_key(key) {
+            metadata.insert("sequence".to_string(), sequence.to_string());
+        }
+
+        ObjectHeaders { cache_control, encryption, kms_key_id, metadata }
+    }
+
+    /// Upload data with timeout (for real-time guarantees), retrying with backoff
+    async fn upload_data_with_timeout(&self, key: &str, data: Vec<u8>, content_type: &S3ContentType) -> CaptureResult<()> {
+        let retry = self.config.retry.clone().unwrap_or_default();
+        let headers = self.object_headers(Some(content_type), key);
+        retry_with_backoff(&retry, key, || {
+            self.put_with_headers(key, data.clone(), content_type.mime_type(), headers.clone())
+        }).await
+    }
+
+    /// Upload data with timeout in a single attempt
+    async fn put_with_headers(&self, key: &str, data: Vec<u8>, content_type: &str, headers: ObjectHeaders) -> CaptureResult<()> {
+        // S3 rejects the object if it does not match the checksum header
+        let digest = ObjectDigest::of(&data);
+        let checksum = match self.config.checksum_algorithm {
+            Some(ChecksumAlgorithm::Crc32c) => digest.crc32c_base64(),
+            _ => digest.sha256_base64(),
+        };
+        // SSE-KMS objects have an ETag that is not the MD5 of their content
+        let expected_etag = (!matches!(headers.encryption, Some(S3ServerSideEncryption::AwsKms)))
+            .then(|| md5_hex(&data));
+
+        let request = self.client
             .put_object()
             .bucket(&self.config.bucket)
             .key(key)
             .body(data.into())
             .content_type(content_type)
-            .send();
+            .set_cache_control(Some(headers.cache_control))
+            .set_server_side_encryption(headers.encryption)
+            .set_ssekms_key_id(headers.kms_key_id)
+            .set_metadata(Some(headers.metadata));
+        let upload_future = match self.config.checksum_algorithm {
+            Some(ChecksumAlgorithm::Crc32c) => request.checksum_crc32_c(checksum.clone()),
+            _ => request.checksum_sha256(checksum.clone()),
+        }.send();
 
         // Apply timeout for real-time streaming requirements
-        timeout(Duration::from_secs(self.config.timeout_seconds), upload_future)
+        let output = timeout(Duration::from_secs(self.config.timeout_seconds as u64), upload_future)
             .await
             .map_err(|_| CaptureError::Upload(format!("Upload timeout for key: {}", key)))?
-            .map_err(|e| CaptureError::Upload(format!("Failed to upload {}: {}", key, e)))?;
+            .map_err(|e| s3_upload_error(format!("Failed to upload {}", key), e))?;
+
+        let returned_checksum = output.checksum_sha256().or(output.checksum_crc32_c());
+        verify_upload(key, &checksum, returned_checksum, expected_etag.as_deref(), output.e_tag())
+    }
+
+    /// Upload a local file such as a finalized recording using S3 multipart upload
+    ///
+    /// Progress is saved to `multipart_state_path(path)` after every part, so
+    /// calling this again after an interruption resumes the same upload. If a
+    /// part still fails after retries the upload is aborted.
+    pub async fn upload_file_multipart(&self, path: &Path, key: &str, content_type: &str) -> CaptureResult<String> {
+        let multipart = self.config.multipart.clone().unwrap_or_default();
+        let file_size = tokio::fs::metadata(path).await?.len();
+        if file_size == 0 {
+            // S3 rejects multipart uploads without parts
+            let retry = self.config.retry.clone().unwrap_or_default();
+            let headers = self.object_headers(None, key);
+            retry_with_backoff(&retry, key, || self.put_with_headers(key, Vec::new(), content_type, headers.clone())).await?;
+            return Ok(key.to_string());
+        }
+
+        let state_path = multipart_state_path(path);
+        let mut state = match self.resume_multipart(&state_path, key, file_size).await {
+            Some(state) => state,
+            None => {
+                let part_size = (multipart.part_size_mb as u64 * 1024 * 1024)
+                    .max(MIN_PART_SIZE)
+                    .max(file_size.div_ceil(MAX_PARTS));
+                let headers = self.object_headers(None, key);
+                let output = self.client
+                    .create_multipart_upload()
+                    .bucket(&self.config.bucket)
+                    .key(key)
+                    .content_type(content_type)
+                    .set_cache_control(Some(headers.cache_control))
+                    .set_server_side_encryption(headers.encryption)
+                    .set_ssekms_key_id(headers.kms_key_id)
+                    .set_metadata(Some(headers.metadata))
+                    .send()
+                    .await
+                    .map_err(|e| CaptureError::Upload(format!("Failed to start multipart upload of {}: {}", key, e)))?;
+                let upload_id = output.upload_id()
+                    .ok_or_else(|| CaptureError::Upload(format!("No upload ID returned for {}", key)))?;
+
+                MultipartState {
+                    bucket: self.config.bucket.clone(),
+                    key: key.to_string(),
+                    upload_id: upload_id.to_string(),
+                    file_size,
+                    part_size,
+                    parts: BTreeMap::new(),
+                }
+            }
+        };
+        write_multipart_state(&state_path, &state).await?;
+
+        let result = match self.upload_missing_parts(path, &state_path, &mut state, multipart.max_concurrent_parts).await {
+            Ok(()) => self.complete_multipart(&state).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Multipart upload of {} failed, aborting: {}", key, e);
+            self.abort_multipart(&state).await;
+            let _ = tokio::fs::remove_file(&state_path).await;
+            return Err(e);
+        }
+
+        let _ = tokio::fs::remove_file(&state_path).await;
+        log::info!("Uploaded {} ({} bytes, {} parts)", key, file_size, state.parts.len());
+        Ok(key.to_string())
+    }
+
+    /// Load a saved multipart upload and refresh its parts from S3
+    async fn resume_multipart(&self, state_path: &Path, key: &str, file_size: u64) -> Option<MultipartState> {
+        let data = tokio::fs::read(state_path).await.ok()?;
+        let mut state: MultipartState = serde_json::from_slice(&data).ok()?;
+
+        if state.bucket != self.config.bucket || state.key != key || state.file_size != file_size {
+            log::warn!("Discarding saved multipart upload {}: the file or destination changed", state.upload_id);
+            self.abort_multipart(&state).await;
+            return None;
+        }
+
+        // S3 is authoritative; parts may have finished after the last save
+        match self.list_uploaded_parts(&state).await {
+            Ok(parts) => {
+                log::info!("Resuming multipart upload of {} with {} parts done", key, parts.len());
+                state.parts = parts;
+                Some(state)
+            }
+            Err(e) => {
+                log::warn!("Cannot resume multipart upload {}: {}", state.upload_id, e);
+                None
+            }
+        }
+    }
+
+    async fn list_uploaded_parts(&self, state: &MultipartState) -> CaptureResult<BTreeMap<i32, String>> {
+        let mut parts = BTreeMap::new();
+        let mut marker = None;
+
+        loop {
+            let output = self.client
+                .list_parts()
+                .bucket(&state.bucket)
+                .key(&state.key)
+                .upload_id(&state.upload_id)
+                .set_part_number_marker(marker.take())
+                .send()
+                .await
+                .map_err(|e| CaptureError::Upload(format!("Failed to list parts of {}: {}", state.key, e)))?;
+
+            for part in output.parts() {
+                if let (Some(number), Some(etag)) = (part.part_number(), part.e_tag()) {
+                    parts.insert(number, etag.to_string());
+                }
+            }
+
+            match output.next_part_number_marker() {
+                Some(next) if output.is_truncated().unwrap_or(false) => marker = Some(next.to_string()),
+                _ => return Ok(parts),
+            }
+        }
+    }
+
+    /// Upload every part not yet recorded in `state`, saving progress as parts finish
+    async fn upload_missing_parts(
+        &self,
+        path: &Path,
+        state_path: &Path,
+        state: &mut MultipartState,
+        max_concurrent_parts: u32,
+    ) -> CaptureResult<()> {
+        let retry = self.config.retry.clone().unwrap_or_default();
+        let key = state.key.clone();
+        let upload_id = state.upload_id.clone();
+        let missing: Vec<_> = plan_parts(state.file_size, state.part_size)
+            .into_iter()
+            .filter(|(number, _, _)| !state.parts.contains_key(number))
+            .collect();
+
+        let mut uploads = futures::stream::iter(missing)
+            .map(|(number, offset, length)| {
+                let (retry, key, upload_id) = (&retry, &key, &upload_id);
+                async move {
+                    let data = read_part(path, offset, length).await?;
+                    let what = format!("Part {} of {}", number, key);
+                    let etag = retry_with_backoff(retry, &what, || self.upload_part(key, upload_id, number, data.clone())).await?;
+                    Ok::<_, CaptureError>((number, etag))
+                }
+            })
+            .buffer_unordered(max_concurrent_parts.max(1) as usize);
+
+        while let Some(result) = uploads.next().await {
+            let (number, etag) = result?;
+            state.parts.insert(number, etag);
+            write_multipart_state(state_path, state).await?;
+        }
+        Ok(())
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, number: i32, data: Vec<u8>) -> CaptureResult<String> {
+        let upload_future = self.client
+            .upload_part()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(number)
+            .body(data.into())
+            .send();
+
+        let output = timeout(Duration::from_secs(self.config.timeout_seconds as u64), upload_future)
+            .await
+            .map_err(|_| CaptureError::Upload(format!("Timeout uploading part {} of {}", number, key)))?
+            .map_err(|e| CaptureError::Upload(format!("Failed to upload part {} of {}: {}", number, key, e)))?;
+
+        output.e_tag()
+            .map(str::to_string)
+            .ok_or_else(|| CaptureError::Upload(format!("No ETag returned for part {} of {}", number, key)))
+    }
+
+    async fn complete_multipart(&self, state: &MultipartState) -> CaptureResult<()> {
+        let expected = plan_parts(state.file_size, state.part_size).len();
+        if state.parts.len() != expected {
+            return Err(CaptureError::Upload(format!(
+                "Multipart upload of {} has {} of {} parts", state.key, state.parts.len(), expected
+            )));
+        }
+
+        let parts = state.parts.iter()
+            .map(|(number, etag)| CompletedPart::builder().part_number(*number).e_tag(etag).build())
+            .collect();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&state.bucket)
+            .key(&state.key)
+            .upload_id(&state.upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .map_err(|e| CaptureError::Upload(format!("Failed to complete multipart upload of {}: {}", state.key, e)))?;
 
         Ok(())
     }
 
-    /// Simple audio+video combination (in production, use proper muxing)
-    fn combine_audio_video(&self, audio_data: Vec<u8>, video_data: Vec<u8>) -> CaptureResult<Vec<u8>> {
-        // This is a simplified approach for demonstration
-        // In production, use FFmpeg to properly mux audio and video streams
-        let mut combined = Vec::with_capacity(audio_data.len() + video_data.len());
-        combined.extend(video_data); // Video first
-        combined.extend(audio_data); // Audio after
-        Ok(combined)
+    /// Abort a multipart upload so S3 discards its parts; failures are only logged
+    async fn abort_multipart(&self, state: &MultipartState) {
+        let result = self.client
+            .abort_multipart_upload()
+            .bucket(&state.bucket)
+            .key(&state.key)
+            .upload_id(&state.upload_id)
+            .send()
+            .await;
+
+        if let Err(e) = result {
+            log::warn!("Failed to abort multipart upload {} of {}: {}", state.upload_id, state.key, e);
+        }
     }
 
     /// Batch upload multiple segments (for efficiency)
@@ -221,34 +678,54 @@ impl S3Uploader {
         Ok(presigned_url.uri().to_string())
     }
 
-    /// Delete old segments (cleanup)
-    pub async fn cleanup_old_segments(&self, segment_sequences: Vec<u32>) -> CaptureResult<()> {
-        let delete_futures = segment_sequences.into_iter().map(|seq| {
-            let audio_key = format!("{}/{}/audio/audio_recording_{}.aac", 
-                                   self.user_id, self.video_id, seq);
-            let video_key = format!("{}/{}/video/video_recording_{}.ts", 
-                                   self.user_id, self.video_id, seq);
-            
-            async move {
-                // Delete audio segment
-                if let Err(e) = self.client.delete_object()
-                    .bucket(&self.config.bucket)
-                    .key(&audio_key)
-                    .send()
-                    .await {
-                    log::warn!("Failed to delete audio segment {}: {}", audio_key, e);
-                }
+    /// Generate a presigned `PUT` URL, e.g. for a backend serving a `PresignedPut` sink
+    ///
+    /// The client must send the returned `Content-Type` header.
+    pub async fn generate_presigned_put_url(&self, key: &str, content_type: &str, expiry_seconds: u64) -> CaptureResult<String> {
+        let request = self.client
+            .put_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .content_type(content_type);
 
-                // Delete video segment
-                if let Err(e) = self.client.delete_object()
-                    .bucket(&self.config.bucket)
-                    .key(&video_key)
-                    .send()
-                    .await {
-                    log::warn!("Failed to delete video segment {}: {}", video_key, e);
+        let presigned_url = request
+            .presigned(
+                aws_sdk_s3::presigning::PresigningConfig::expires_in(
+                    Duration::from_secs(expiry_seconds)
+                ).map_err(|e| CaptureError::Upload(format!("Failed to create presigning config: {}", e)))?
+            )
+            .await
+            .map_err(|e| CaptureError::Upload(format!("Failed to generate presigned URL: {}", e)))?;
+
+        Ok(presigned_url.uri().to_string())
+    }
+
+    /// Delete a single stored object
+    pub async fn delete_segment(&self, content_type: &S3ContentType, sequence: u32) -> CaptureResult<String> {
+        let key = self.object_key(content_type, sequence);
+
+        self.client.delete_object()
+            .bucket(&self.config.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| CaptureError::Upload(format!("Failed to delete {}: {}", key, e)))?;
+
+        Ok(key)
+    }
+
+    /// Delete the audio, video and combined segments of the given sequences
+    ///
+    /// The recording pipeline does this automatically with `SegmentRetention::LiveWindow`.
+    pub async fn cleanup_old_segments(&self, segment_sequences: Vec<u32>) -> CaptureResult<()> {
+        let content_types = [S3ContentType::AudioSegment, S3ContentType::VideoSegment, S3ContentType::CombinedSegment];
+        let delete_futures = segment_sequences.into_iter()
+            .flat_map(|seq| content_types.iter().map(move |content_type| (content_type, seq)))
+            .map(|(content_type, seq)| async move {
+                if let Err(e) = self.delete_segment(content_type, seq).await {
+                    log::warn!("{}", e);
                 }
-            }
-        });
+            });
 
         futures::future::join_all(delete_futures).await;
         log::info!("Cleaned up old segments");
@@ -256,13 +733,293 @@ impl S3Uploader {
     }
 }
 
+/// Upload error for a failed S3 request; client errors other than timeouts and throttling are rejections
+fn s3_upload_error<E: std::error::Error + 'static>(context: String, e: SdkError<E, HttpResponse>) -> CaptureError {
+    let status = e.raw_response().map(|response| response.status().as_u16());
+    let message = format!("{}: {}", context, e);
+    match status {
+        Some(status) if is_permanent_status(status) => CaptureError::UploadRejected(message),
+        _ => CaptureError::Upload(message),
+    }
+}
+
+/// Check what S3 reports for a single-part upload against the data that was sent
+///
+/// A mismatch fails the upload so it is retried. ETags that are not a plain
+/// MD5, such as those of multipart copies, are not compared.
+fn verify_upload(key: &str, checksum: &str, returned_checksum: Option<&str>, expected_etag: Option<&str>, etag: Option<&str>) -> CaptureResult<()> {
+    if let Some(returned) = returned_checksum.filter(|returned| *returned != checksum) {
+        return Err(CaptureError::Upload(format!("Checksum mismatch for {}: sent {}, stored {}", key, checksum, returned)));
+    }
+
+    let etag = etag.map(|etag| etag.trim_matches('"'));
+    if let (Some(expected), Some(etag)) = (expected_etag, etag) {
+        let is_md5 = etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit());
+        if is_md5 && !etag.eq_ignore_ascii_case(expected) {
+            return Err(CaptureError::Upload(format!("ETag mismatch for {}: expected {}, got {}", key, expected, etag)));
+        }
+    }
+    Ok(())
+}
+
 /// Create S3 uploader with Cap's production settings
 pub fn create_cap_s3_uploader(bucket: String, _user_id: String, _video_id: String) -> UploadConfig {
     UploadConfig {
         bucket,
         region: "us-east-1".to_string(), // Cap's primary region
         timeout_seconds: 10, // 10 second timeout for real-time streaming
-        accelerated_transfer: true,
+        // Transfer Acceleration has to be enabled on the bucket first
+        accelerated_transfer: false,
         endpoint: None,
+        retry: None,
+        spool_dir: None,
+        multipart: None,
+        max_concurrent_uploads: None,
+        max_bytes_per_second: None,
+        force_path_style: None,
+        credentials: None,
+        profile: None,
+        object_headers: None,
+        checksum_algorithm: None,
+    }
+}
+
+/// User and session ID of a key following the `object_key` layout
+fn key_owner(key: &str) -> Option<(&str, &str)> {
+    let mut parts = key.split('/');
+    let owner = (parts.next()?, parts.next()?);
+    parts.next().map(|_| owner)
+}
+
+/// File where the progress of a multipart upload of `path` is saved
+pub fn multipart_state_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".upload.json");
+    path.with_file_name(file_name)
+}
+
+/// Split a file into `(part number, offset, length)` parts
+fn plan_parts(file_size: u64, part_size: u64) -> Vec<(i32, u64, u64)> {
+    (0..file_size.div_ceil(part_size))
+        .map(|index| {
+            let offset = index * part_size;
+            ((index + 1) as i32, offset, part_size.min(file_size - offset))
+        })
+        .collect()
+}
+
+async fn read_part(path: &Path, offset: u64, length: u64) -> CaptureResult<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut data = vec![0; length as usize];
+    file.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+async fn write_multipart_state(state_path: &Path, state: &MultipartState) -> CaptureResult<()> {
+    let data = serde_json::to_vec(state)
+        .map_err(|e| CaptureError::Upload(format!("Failed to serialize multipart state: {}", e)))?;
+    write_atomic(state_path, &data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// Requests seen by `stub_s3` as method and path with query
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// Minimal S3 endpoint for multipart uploads: new uploads get the ID `created`,
+    /// existing ones list `listed` as uploaded, and part uploads fail if `reject_parts`
+    async fn stub_s3(listed: Vec<i32>, reject_parts: bool) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Requests::default();
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (seen, listed) = (seen.clone(), listed.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 64 * 1024];
+                    let (head, body_start) = loop {
+                        let read = socket.read(&mut buffer).await.unwrap();
+                        request.extend_from_slice(&buffer[..read]);
+                        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                            break (String::from_utf8_lossy(&request[..end]).to_ascii_lowercase(), end + 4);
+                        }
+                    };
+                    let content_length = head.lines()
+                        .find_map(|line| line.strip_prefix("content-length:").map(|value| value.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if head.contains("expect: 100-continue") {
+                        socket.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.unwrap();
+                    }
+                    while request.len() < body_start + content_length {
+                        let read = socket.read(&mut buffer).await.unwrap();
+                        request.extend_from_slice(&buffer[..read]);
+                    }
+
+                    let mut request_line = head.lines().next().unwrap().split(' ');
+                    let (method, path) = (request_line.next().unwrap().to_uppercase(), request_line.next().unwrap().to_string());
+                    seen.lock().unwrap().push((method.clone(), path.clone()));
+
+                    let part_number = path.split_once("partnumber=")
+                        .map(|(_, rest)| rest.split('&').next().unwrap().parse::<i32>().unwrap());
+                    let (status, headers, body) = match (method.as_str(), part_number) {
+                        ("PUT", Some(_)) if reject_parts => (
+                            "400 Bad Request",
+                            String::new(),
+                            "<Error><Code>InvalidRequest</Code><Message>rejected</Message></Error>".to_string(),
+                        ),
+                        ("PUT", Some(number)) => ("200 OK", format!("ETag: \"etag-{}\"\r\n", number), String::new()),
+                        ("POST", _) if !path.contains("uploadid=") => (
+                            "200 OK",
+                            String::new(),
+                            "<InitiateMultipartUploadResult><Bucket>media</Bucket><Key>key</Key><UploadId>created</UploadId></InitiateMultipartUploadResult>".to_string(),
+                        ),
+                        ("GET", _) => {
+                            let parts: String = listed.iter()
+                                .map(|number| format!("<Part><PartNumber>{0}</PartNumber><ETag>\"etag-{0}\"</ETag><Size>1</Size></Part>", number))
+                                .collect();
+                            ("200 OK", String::new(), format!("<ListPartsResult><IsTruncated>false</IsTruncated>{}</ListPartsResult>", parts))
+                        }
+                        ("POST", _) => (
+                            "200 OK",
+                            String::new(),
+                            "<CompleteMultipartUploadResult><Bucket>media</Bucket><Key>key</Key><ETag>\"etag-3\"</ETag></CompleteMultipartUploadResult>".to_string(),
+                        ),
+                        _ => ("204 No Content", String::new(), String::new()),
+                    };
+
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+                        status, body.len(), headers, body
+                    );
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        (endpoint, requests)
+    }
+
+    async fn stub_uploader(endpoint: String) -> S3Uploader {
+        let mut config = create_cap_s3_uploader("media".to_string(), String::new(), String::new());
+        config.endpoint = Some(endpoint);
+        config.credentials = Some(S3Credentials {
+            access_key_id: "test".to_string(),
+            secret_access_key: "test".to_string(),
+            session_token: None,
+        });
+        config.retry = Some(RetryConfig { max_attempts: 2, initial_backoff_ms: 1, max_backoff_ms: 1 });
+        S3Uploader::new(config, "user".to_string(), "video".to_string()).await.unwrap()
+    }
+
+    fn recording_file(size: u64) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cap-multipart-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("recording.webm");
+        std::fs::write(&path, vec![7u8; size as usize]).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_multipart_resumes_saved_upload() {
+        let path = recording_file(2 * MIN_PART_SIZE + 1024);
+        // Part 2 finished after the last save, so only S3 knows about it
+        let state = MultipartState {
+            bucket: "media".to_string(),
+            key: "user/video/recording.webm".to_string(),
+            upload_id: "saved".to_string(),
+            file_size: 2 * MIN_PART_SIZE + 1024,
+            part_size: MIN_PART_SIZE,
+            parts: BTreeMap::from([(1, "\"etag-1\"".to_string())]),
+        };
+        write_multipart_state(&multipart_state_path(&path), &state).await.unwrap();
+
+        let (endpoint, requests) = stub_s3(vec![1, 2], false).await;
+        let uploader = stub_uploader(endpoint).await;
+        uploader.upload_file_multipart(&path, "user/video/recording.webm", "video/webm").await.unwrap();
+
+        let requests = requests.lock().unwrap().clone();
+        assert!(!requests.iter().any(|(method, path)| method == "POST" && !path.contains("uploadid=")));
+        assert!(requests.iter().any(|(method, path)| method == "GET" && path.contains("uploadid=saved")));
+        let parts: Vec<_> = requests.iter().filter(|(method, _)| method == "PUT").collect();
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].1.contains("partnumber=3"));
+        assert!(requests.iter().any(|(method, path)| method == "POST" && path.contains("uploadid=saved")));
+        assert!(!multipart_state_path(&path).exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_multipart_aborted_when_part_fails() {
+        let path = recording_file(MIN_PART_SIZE + 1024);
+
+        let (endpoint, requests) = stub_s3(Vec::new(), true).await;
+        let uploader = stub_uploader(endpoint).await;
+        assert!(uploader.upload_file_multipart(&path, "user/video/recording.webm", "video/webm").await.is_err());
+
+        // S3 is told to discard the parts, and nothing is left to resume
+        let requests = requests.lock().unwrap().clone();
+        assert!(requests.iter().any(|(method, path)| method == "DELETE" && path.contains("uploadid=created")));
+        assert!(!requests.iter().any(|(method, path)| method == "POST" && path.contains("uploadid=")));
+        assert!(!multipart_state_path(&path).exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_plan_parts() {
+        let mib = 1024 * 1024;
+        let parts = plan_parts(12 * mib + 3, 5 * mib);
+        assert_eq!(parts, vec![
+            (1, 0, 5 * mib),
+            (2, 5 * mib, 5 * mib),
+            (3, 10 * mib, 2 * mib + 3),
+        ]);
+        assert_eq!(plan_parts(5 * mib, 5 * mib).len(), 1);
+        assert_eq!(multipart_state_path(Path::new("/tmp/rec.mp4")), PathBuf::from("/tmp/rec.mp4.upload.json"));
+    }
+
+    #[test]
+    fn test_key_metadata() {
+        assert_eq!(key_owner("user/video/audio/audio_recording_7.aac"), Some(("user", "video")));
+        assert_eq!(key_owner("recording.mp4"), None);
+        assert_eq!(sequence_from_key("user/video/audio/audio_recording_7.aac"), Some(7));
+        assert_eq!(sequence_from_key("user/video/combined-source/segment_12.ts"), Some(12));
+        assert_eq!(sequence_from_key("user/video/video/stream.m3u8"), None);
+        assert_eq!(sequence_from_key("user/video/video/init.mp4"), None);
+    }
+
+    #[test]
+    fn test_verify_upload() {
+        let etag = "\"25f9e794323b453885f5181f1b624d0b\"";
+        assert!(verify_upload("k", "abc", Some("abc"), Some("25f9e794323b453885f5181f1b624d0b"), Some(etag)).is_ok());
+        assert!(verify_upload("k", "abc", Some("abd"), None, None).is_err());
+        assert!(verify_upload("k", "abc", None, Some("00000000000000000000000000000000"), Some(etag)).is_err());
+        // Multipart-style ETags are not an MD5 of the content
+        assert!(verify_upload("k", "abc", None, Some("25f9e794323b453885f5181f1b624d0b"), Some("\"abc-2\"")).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_url() {
+        let mut config = create_cap_s3_uploader("media".to_string(), String::new(), String::new());
+        assert_eq!(config.bucket_url(), "https://media.s3.amazonaws.com");
+
+        config.endpoint = Some("http://localhost:9000/".to_string());
+        assert!(config.path_style());
+        assert_eq!(config.bucket_url(), "http://localhost:9000/media");
+
+        config.force_path_style = Some(false);
+        assert_eq!(config.bucket_url(), "http://media.localhost:9000");
     }
 }
@@ -0,0 +1,391 @@
+//! Segment Sinks
+//!
+//! Destinations for packaged segments and playlists. The pipeline writes to
+//! every configured sink, so S3, a local directory and custom ingest
+//! backends can be combined without touching the recording code.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{FileSystemSink, HttpPresignProvider, PresignedPutSink, S3ContentType, S3Credentials, S3Uploader, create_cap_s3_uploader, object_key};
+use super::upload_queue::is_permanent_status;
+use futures::future::BoxFuture;
+use napi_derive::napi;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Future returned by `SegmentSink` operations
+pub type SinkFuture<'a, T> = BoxFuture<'a, CaptureResult<T>>;
+
+/// Destination for segments and playlists
+///
+/// Keys follow the layout of `object_key`; every operation returns the key
+/// it acted on.
+pub trait SegmentSink: Send + Sync {
+    /// Short name used in logs
+    fn name(&self) -> &str;
+
+    /// Stable identifier, used to name the sink's spool directory
+    fn id(&self) -> String {
+        self.name().to_string()
+    }
+
+    /// Whether writes leave the machine and count as uploads
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    /// Key of a segment or playlist of this sink's session
+    fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String;
+
+    /// Store data under an explicit key, e.g. one spooled by an earlier session
+    fn put_object(&self, key: String, content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String>;
+
+    /// Store a segment, init segment or other packaged media object
+    fn put_segment(&self, content_type: S3ContentType, sequence: u32, data: Vec<u8>) -> SinkFuture<'_, String> {
+        let key = self.object_key(&content_type, sequence);
+        self.put_object(key, content_type, data)
+    }
+
+    /// Replace a playlist
+    fn put_playlist(&self, content_type: S3ContentType, playlist: String) -> SinkFuture<'_, String> {
+        if !content_type.is_playlist() {
+            return Box::pin(async { Err(CaptureError::Config("Invalid playlist content type".to_string())) });
+        }
+
+        let key = self.object_key(&content_type, 0);
+        self.put_object(key, content_type, playlist.into_bytes())
+    }
+
+    /// Remove a previously stored segment
+    fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String>;
+
+    /// Called once after the recording has stopped and the final playlists were written
+    fn finalize(&self) -> SinkFuture<'_, ()>;
+}
+
+/// Kind of sink listed in `RecordingConfig.sinks`
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SinkKind {
+    /// S3 or an S3-compatible service
+    S3,
+    /// Local directory
+    FileSystem,
+    /// Generic HTTP endpoint accepting `PUT` and `DELETE`
+    HttpPut,
+    /// Plain HTTP uploads to URLs presigned by a backend endpoint
+    PresignedPut,
+}
+
+/// Sink configuration
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkConfig {
+    /// Sink kind
+    pub kind: SinkKind,
+    /// Bucket name (S3)
+    pub bucket: Option<String>,
+    /// AWS region (S3), defaults to us-east-1
+    pub region: Option<String>,
+    /// Custom endpoint (S3-compatible services)
+    pub endpoint: Option<String>,
+    /// Path-style bucket addressing, defaults to true with a custom endpoint (S3)
+    pub force_path_style: Option<bool>,
+    /// Static credentials instead of the default AWS credential chain (S3)
+    pub credentials: Option<S3Credentials>,
+    /// Named AWS profile (S3)
+    pub profile: Option<String>,
+    /// Use S3 Transfer Acceleration, which must be enabled on the bucket; defaults to false (S3)
+    pub accelerated_transfer: Option<bool>,
+    /// Root directory (FileSystem)
+    pub path: Option<String>,
+    /// Base URL that object keys are appended to (HttpPut), or the presign endpoint (PresignedPut)
+    pub url: Option<String>,
+    /// Extra request headers, e.g. authorization (HttpPut, and presign requests of PresignedPut)
+    pub headers: Option<HashMap<String, String>>,
+    /// Request timeout in seconds, defaults to 10
+    pub timeout_seconds: Option<u32>,
+}
+
+/// Build a sink from its configuration
+pub async fn create_sink(config: &SinkConfig, user_id: &str, video_id: &str) -> CaptureResult<Arc<dyn SegmentSink>> {
+    let missing = |field: &str| CaptureError::Config(format!("{:?} sink requires `{}`", config.kind, field));
+
+    let sink: Arc<dyn SegmentSink> = match config.kind {
+        SinkKind::S3 => {
+            let bucket = config.bucket.clone().ok_or_else(|| missing("bucket"))?;
+            let mut upload_config = create_cap_s3_uploader(bucket, user_id.to_string(), video_id.to_string());
+            if let Some(region) = &config.region {
+                upload_config.region = region.clone();
+            }
+            if let Some(timeout_seconds) = config.timeout_seconds {
+                upload_config.timeout_seconds = timeout_seconds;
+            }
+            upload_config.endpoint = config.endpoint.clone();
+            upload_config.force_path_style = config.force_path_style;
+            upload_config.credentials = config.credentials.clone();
+            upload_config.profile = config.profile.clone();
+            upload_config.accelerated_transfer = config.accelerated_transfer.unwrap_or(false);
+            Arc::new(S3Uploader::new(upload_config, user_id.to_string(), video_id.to_string()).await?)
+        }
+        SinkKind::FileSystem => {
+            let path = config.path.clone().ok_or_else(|| missing("path"))?;
+            Arc::new(FileSystemSink::new(path, user_id.to_string(), video_id.to_string()))
+        }
+        SinkKind::HttpPut => {
+            let url = config.url.clone().ok_or_else(|| missing("url"))?;
+            Arc::new(HttpPutSink::new(
+                url,
+                config.headers.clone().unwrap_or_default(),
+                Duration::from_secs(config.timeout_seconds.unwrap_or(10) as u64),
+                user_id.to_string(),
+                video_id.to_string(),
+            )?)
+        }
+        SinkKind::PresignedPut => {
+            let url = config.url.clone().ok_or_else(|| missing("url"))?;
+            let timeout = Duration::from_secs(config.timeout_seconds.unwrap_or(10) as u64);
+            let provider = HttpPresignProvider::new(url.clone(), config.headers.clone().unwrap_or_default(), timeout)?;
+            Arc::new(PresignedPutSink::new(Arc::new(provider), url, timeout, user_id.to_string(), video_id.to_string())?)
+        }
+    };
+
+    Ok(sink)
+}
+
+impl SegmentSink for S3Uploader {
+    fn name(&self) -> &str {
+        "s3"
+    }
+
+    fn id(&self) -> String {
+        format!("s3-{}", self.bucket())
+    }
+
+    fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        S3Uploader::object_key(self, content_type, sequence)
+    }
+
+    fn put_object(&self, key: String, content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String> {
+        Box::pin(async move { self.upload_object(key, &content_type, data).await })
+    }
+
+    fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String> {
+        Box::pin(async move { self.delete_segment(&content_type, sequence).await })
+    }
+
+    fn finalize(&self) -> SinkFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl SegmentSink for FileSystemSink {
+    fn name(&self) -> &str {
+        "filesystem"
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        FileSystemSink::object_key(self, content_type, sequence)
+    }
+
+    fn put_object(&self, key: String, _content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String> {
+        Box::pin(async move { self.write_object(key, &data).await })
+    }
+
+    fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String> {
+        Box::pin(async move { self.remove(&content_type, sequence).await })
+    }
+
+    fn finalize(&self) -> SinkFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// In-memory sink, mainly for tests
+#[derive(Debug, Clone)]
+pub struct MemorySink {
+    user_id: String,
+    video_id: String,
+    objects: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    finalized: Arc<AtomicBool>,
+}
+
+impl MemorySink {
+    /// Create an empty sink; clones share the same storage
+    pub fn new(user_id: String, video_id: String) -> Self {
+        Self {
+            user_id,
+            video_id,
+            objects: Arc::new(Mutex::new(BTreeMap::new())),
+            finalized: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Contents stored under a key
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    /// All stored keys in lexical order
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Whether `finalize` has been called
+    pub fn is_finalized(&self) -> bool {
+        self.finalized.load(Ordering::SeqCst)
+    }
+
+}
+
+impl SegmentSink for MemorySink {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        object_key(&self.user_id, &self.video_id, content_type, sequence)
+    }
+
+    fn put_object(&self, key: String, _content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String> {
+        self.objects.lock().unwrap().insert(key.clone(), data);
+        Box::pin(async move { Ok(key) })
+    }
+
+    fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String> {
+        let key = self.object_key(&content_type, sequence);
+        self.objects.lock().unwrap().remove(&key);
+        Box::pin(async move { Ok(key) })
+    }
+
+    fn finalize(&self) -> SinkFuture<'_, ()> {
+        self.finalized.store(true, Ordering::SeqCst);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Sink that `PUT`s every object to `<url>/<key>`
+pub struct HttpPutSink {
+    client: reqwest::Client,
+    base_url: String,
+    headers: HashMap<String, String>,
+    user_id: String,
+    video_id: String,
+}
+
+impl HttpPutSink {
+    /// Create a sink for the given base URL
+    pub fn new(
+        base_url: String,
+        headers: HashMap<String, String>,
+        timeout: Duration,
+        user_id: String,
+        video_id: String,
+    ) -> CaptureResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| CaptureError::Config(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers,
+            user_id,
+            video_id,
+        })
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder, key: String) -> CaptureResult<String> {
+        let request = self.headers.iter().fold(request, |request, (name, value)| request.header(name, value));
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| http_upload_error(format!("HTTP request for {} failed", key), e))?;
+
+        Ok(key)
+    }
+
+    async fn put(&self, key: String, content_type: &S3ContentType, data: Vec<u8>) -> CaptureResult<String> {
+        let request = self.client
+            .put(self.url(&key))
+            .header(reqwest::header::CONTENT_TYPE, content_type.mime_type())
+            .body(data);
+        self.send(request, key).await
+    }
+}
+
+impl SegmentSink for HttpPutSink {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn id(&self) -> String {
+        format!("http-{}", self.base_url)
+    }
+
+    fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+        object_key(&self.user_id, &self.video_id, content_type, sequence)
+    }
+
+    fn put_object(&self, key: String, content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String> {
+        Box::pin(async move { self.put(key, &content_type, data).await })
+    }
+
+    fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String> {
+        Box::pin(async move {
+            let key = self.object_key(&content_type, sequence);
+            self.send(self.client.delete(self.url(&key)), key).await
+        })
+    }
+
+    fn finalize(&self) -> SinkFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Upload error for a failed HTTP request; client errors are rejections that retrying cannot fix
+pub(super) fn http_upload_error(context: String, e: reqwest::Error) -> CaptureError {
+    let message = format!("{}: {}", context, e);
+    match e.status() {
+        Some(status) if is_permanent_status(status.as_u16()) => CaptureError::UploadRejected(message),
+        _ => CaptureError::Upload(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_sink() {
+        let memory = MemorySink::new("user".to_string(), "video".to_string());
+        let sink: Arc<dyn SegmentSink> = Arc::new(memory.clone());
+
+        sink.put_segment(S3ContentType::VideoSegment, 0, vec![0x47]).await.unwrap();
+        sink.put_playlist(S3ContentType::VideoPlaylist, "#EXTM3U\n".to_string()).await.unwrap();
+        assert_eq!(memory.keys(), vec!["user/video/video/stream.m3u8", "user/video/video/video_recording_0.ts"]);
+
+        sink.delete(S3ContentType::VideoSegment, 0).await.unwrap();
+        assert_eq!(memory.get("user/video/video/video_recording_0.ts"), None);
+
+        sink.finalize().await.unwrap();
+        assert!(memory.is_finalized());
+    }
+}
@@ -0,0 +1,387 @@
+//! MPEG-TS Muxer
+//!
+//! Native MPEG-2 transport stream writer for HLS segments. Each segment
+//! starts with a PAT and PMT, followed by one PES packet per access unit,
+//! interleaved by decode time. Continuity counters carry over between
+//! segments written by the same muxer.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{AudioCodec, EncodedAudioSegment, EncodedVideoSegment, VideoCodec, VIDEO_CLOCK_RATE};
+use std::collections::HashMap;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_PAYLOAD_SIZE: usize = TS_PACKET_SIZE - 4;
+const SYNC_BYTE: u8 = 0x47;
+
+const PAT_PID: u16 = 0x0000;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x0100;
+const AUDIO_PID: u16 = 0x0101;
+
+const PROGRAM_NUMBER: u16 = 1;
+const VIDEO_STREAM_ID: u8 = 0xE0;
+const AUDIO_STREAM_ID: u8 = 0xC0;
+/// ADTS AAC
+const AAC_STREAM_TYPE: u8 = 0x0F;
+
+/// Offset added to PTS/DTS so the PCR (taken from the raw DTS) always leads them
+const TIMESTAMP_OFFSET: i64 = VIDEO_CLOCK_RATE * 7 / 10;
+
+/// Access unit delimiters inserted before frames that lack one
+const H264_AUD: [u8; 6] = [0x00, 0x00, 0x00, 0x01, 0x09, 0xF0];
+const HEVC_AUD: [u8; 7] = [0x00, 0x00, 0x00, 0x01, 0x46, 0x01, 0x50];
+
+/// Access unit queued for a segment
+struct AccessUnit<'a> {
+    pid: u16,
+    stream_id: u8,
+    /// Timestamps in 90kHz units
+    pts: i64,
+    dts: i64,
+    keyframe: bool,
+    data: &'a [u8],
+}
+
+/// Stateful MPEG-TS muxer
+#[derive(Debug, Default)]
+pub struct TsMuxer {
+    continuity_counters: HashMap<u16, u8>,
+}
+
+impl TsMuxer {
+    /// Create a muxer with fresh continuity counters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mux a video and/or audio segment into a self-contained TS segment
+    pub fn mux_segment(
+        &mut self,
+        video: Option<&EncodedVideoSegment>,
+        audio: Option<&EncodedAudioSegment>,
+    ) -> CaptureResult<Vec<u8>> {
+        let video_stream_type = match video {
+            Some(segment) => Some(segment.codec.mpegts_stream_type().ok_or_else(|| {
+                CaptureError::EncodingError(format!("{:?} video cannot be carried in MPEG-TS", segment.codec))
+            })?),
+            None => None,
+        };
+        if let Some(segment) = audio {
+            if !matches!(segment.codec, AudioCodec::AAC) {
+                return Err(CaptureError::EncodingError(format!(
+                    "{:?} audio cannot be carried in MPEG-TS", segment.codec
+                )));
+            }
+        }
+        if video.is_none() && audio.is_none() {
+            return Err(CaptureError::EncodingError("Nothing to mux".to_string()));
+        }
+
+        let mut units = Vec::new();
+        if let Some(segment) = video {
+            for frame in &segment.frames {
+                units.push(AccessUnit {
+                    pid: VIDEO_PID,
+                    stream_id: VIDEO_STREAM_ID,
+                    pts: frame.pts,
+                    dts: frame.dts,
+                    keyframe: frame.keyframe,
+                    data: &segment.data[frame.offset..frame.offset + frame.size],
+                });
+            }
+        }
+        if let Some(segment) = audio {
+            for frame in &segment.frames {
+                let pts = frame.pts * VIDEO_CLOCK_RATE / segment.sample_rate as i64;
+                units.push(AccessUnit {
+                    pid: AUDIO_PID,
+                    stream_id: AUDIO_STREAM_ID,
+                    pts,
+                    dts: pts,
+                    keyframe: true,
+                    data: &segment.data[frame.offset..frame.offset + frame.size],
+                });
+            }
+        }
+        // Stable sort keeps decode order within each stream
+        units.sort_by_key(|unit| unit.dts);
+
+        // The PCR travels on the video PID when there is one
+        let pcr_pid = if video.is_some() { VIDEO_PID } else { AUDIO_PID };
+
+        let mut out = Vec::with_capacity(
+            (video.map_or(0, |segment| segment.data.len()) + audio.map_or(0, |segment| segment.data.len()))
+                * 11 / 10,
+        );
+        self.write_section(&mut out, PAT_PID, &pat_section());
+        self.write_section(&mut out, PMT_PID, &pmt_section(pcr_pid, video_stream_type, audio.is_some()));
+
+        for unit in &units {
+            let pes = pes_packet(unit, video.map(|segment| segment.codec));
+            let pcr = (unit.pid == pcr_pid).then_some(unit.dts.max(0) as u64);
+            self.write_packets(&mut out, unit.pid, &pes, pcr, unit.keyframe && unit.pid == VIDEO_PID);
+        }
+
+        Ok(out)
+    }
+
+    /// Write a PSI section, prefixed with its pointer field
+    fn write_section(&mut self, out: &mut Vec<u8>, pid: u16, section: &[u8]) {
+        let mut payload = Vec::with_capacity(section.len() + 1);
+        payload.push(0x00); // pointer_field
+        payload.extend_from_slice(section);
+
+        // PSI tables are padded with 0xFF rather than adaptation field stuffing
+        payload.resize(payload.len().div_ceil(TS_PAYLOAD_SIZE) * TS_PAYLOAD_SIZE, 0xFF);
+        for (index, chunk) in payload.chunks(TS_PAYLOAD_SIZE).enumerate() {
+            let cc = self.next_continuity_counter(pid);
+            out.push(SYNC_BYTE);
+            out.push((if index == 0 { 0x40 } else { 0x00 }) | ((pid >> 8) as u8 & 0x1F));
+            out.push(pid as u8);
+            out.push(0x10 | cc);
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    /// Split a PES packet into TS packets
+    fn write_packets(&mut self, out: &mut Vec<u8>, pid: u16, pes: &[u8], pcr: Option<u64>, random_access: bool) {
+        let mut offset = 0;
+        let mut first = true;
+
+        while offset < pes.len() {
+            // Adaptation field contents, excluding its length byte
+            let mut adaptation: Option<Vec<u8>> = None;
+            if first && (pcr.is_some() || random_access) {
+                let mut field = vec![(if random_access { 0x40 } else { 0x00 }) | (if pcr.is_some() { 0x10 } else { 0x00 })];
+                if let Some(pcr) = pcr {
+                    field.extend_from_slice(&encode_pcr(pcr));
+                }
+                adaptation = Some(field);
+            }
+
+            let space = TS_PAYLOAD_SIZE - adaptation.as_ref().map_or(0, |field| 1 + field.len());
+            let chunk_len = (pes.len() - offset).min(space);
+
+            // Pad short packets with adaptation field stuffing
+            if chunk_len < space {
+                let stuffing = space - chunk_len;
+                match &mut adaptation {
+                    Some(field) => field.resize(field.len() + stuffing, 0xFF),
+                    None => {
+                        let mut field = Vec::new();
+                        if stuffing > 1 {
+                            field.push(0x00);
+                            field.resize(stuffing - 1, 0xFF);
+                        }
+                        adaptation = Some(field);
+                    }
+                }
+            }
+
+            let cc = self.next_continuity_counter(pid);
+            out.push(SYNC_BYTE);
+            out.push((if first { 0x40 } else { 0x00 }) | ((pid >> 8) as u8 & 0x1F));
+            out.push(pid as u8);
+            match &adaptation {
+                Some(field) => {
+                    out.push(0x30 | cc);
+                    out.push(field.len() as u8);
+                    out.extend_from_slice(field);
+                }
+                None => out.push(0x10 | cc),
+            }
+            out.extend_from_slice(&pes[offset..offset + chunk_len]);
+
+            offset += chunk_len;
+            first = false;
+        }
+    }
+
+    fn next_continuity_counter(&mut self, pid: u16) -> u8 {
+        let counter = self.continuity_counters.entry(pid).or_insert(0x0F);
+        *counter = (*counter + 1) & 0x0F;
+        *counter
+    }
+}
+
+/// Mux a single segment with fresh continuity counters
+pub fn mux_ts_segment(
+    video: Option<&EncodedVideoSegment>,
+    audio: Option<&EncodedAudioSegment>,
+) -> CaptureResult<Vec<u8>> {
+    TsMuxer::new().mux_segment(video, audio)
+}
+
+/// Program association table with a single program
+fn pat_section() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+    body.extend_from_slice(&(0xE000 | PMT_PID).to_be_bytes());
+    psi_section(0x00, 0x0001, &body)
+}
+
+/// Program map table describing the video and audio streams
+fn pmt_section(pcr_pid: u16, video_stream_type: Option<u8>, has_audio: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(0xE000 | pcr_pid).to_be_bytes());
+    body.extend_from_slice(&0xF000u16.to_be_bytes()); // program_info_length = 0
+
+    let streams = video_stream_type.map(|stream_type| (stream_type, VIDEO_PID))
+        .into_iter()
+        .chain(has_audio.then_some((AAC_STREAM_TYPE, AUDIO_PID)));
+    for (stream_type, pid) in streams {
+        body.push(stream_type);
+        body.extend_from_slice(&(0xE000 | pid).to_be_bytes());
+        body.extend_from_slice(&0xF000u16.to_be_bytes()); // ES_info_length = 0
+    }
+
+    psi_section(0x02, PROGRAM_NUMBER, &body)
+}
+
+/// Wrap a table body in the long-form section header and CRC
+fn psi_section(table_id: u8, table_id_extension: u16, body: &[u8]) -> Vec<u8> {
+    // Everything after section_length: 5 header bytes, body, CRC
+    let section_length = 5 + body.len() + 4;
+
+    let mut section = vec![table_id];
+    section.extend_from_slice(&(0xB000 | section_length as u16).to_be_bytes());
+    section.extend_from_slice(&table_id_extension.to_be_bytes());
+    section.push(0xC1); // version 0, current_next_indicator
+    section.push(0x00); // section_number
+    section.push(0x00); // last_section_number
+    section.extend_from_slice(body);
+
+    let crc = crc32_mpeg2(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section
+}
+
+/// Build a PES packet for one access unit
+fn pes_packet(unit: &AccessUnit, video_codec: Option<VideoCodec>) -> Vec<u8> {
+    let with_dts = unit.dts != unit.pts;
+    let header_data_length = if with_dts { 10 } else { 5 };
+
+    let aud: &[u8] = match video_codec {
+        Some(codec) if unit.pid == VIDEO_PID && !starts_with_aud(unit.data, codec) => match codec {
+            VideoCodec::H264 => &H264_AUD,
+            VideoCodec::H265 => &HEVC_AUD,
+            _ => &[],
+        },
+        _ => &[],
+    };
+
+    let mut pes = vec![0x00, 0x00, 0x01, unit.stream_id];
+    let length = 3 + header_data_length + aud.len() + unit.data.len();
+    // Video PES packets may exceed 16 bits, which is signalled as 0
+    let packet_length = if unit.stream_id == VIDEO_STREAM_ID || length > u16::MAX as usize { 0 } else { length as u16 };
+    pes.extend_from_slice(&packet_length.to_be_bytes());
+    pes.push(0x80); // marker bits
+    pes.push(if with_dts { 0xC0 } else { 0x80 });
+    pes.push(header_data_length as u8);
+
+    let pts = (unit.pts + TIMESTAMP_OFFSET) as u64;
+    if with_dts {
+        pes.extend_from_slice(&encode_timestamp(0x3, pts));
+        pes.extend_from_slice(&encode_timestamp(0x1, (unit.dts + TIMESTAMP_OFFSET) as u64));
+    } else {
+        pes.extend_from_slice(&encode_timestamp(0x2, pts));
+    }
+
+    pes.extend_from_slice(aud);
+    pes.extend_from_slice(unit.data);
+    pes
+}
+
+/// Whether an Annex-B access unit already begins with an access unit delimiter
+fn starts_with_aud(data: &[u8], codec: VideoCodec) -> bool {
+    let nal = if data.starts_with(&[0, 0, 0, 1]) {
+        data.get(4)
+    } else if data.starts_with(&[0, 0, 1]) {
+        data.get(3)
+    } else {
+        None
+    };
+
+    match (codec, nal) {
+        (VideoCodec::H264, Some(header)) => header & 0x1F == 9,
+        (VideoCodec::H265, Some(header)) => (header >> 1) & 0x3F == 35,
+        _ => false,
+    }
+}
+
+/// Encode a 33-bit PES timestamp with its 4-bit prefix
+fn encode_timestamp(prefix: u8, timestamp: u64) -> [u8; 5] {
+    let timestamp = timestamp & 0x1_FFFF_FFFF;
+    [
+        (prefix << 4) | (((timestamp >> 30) as u8 & 0x07) << 1) | 0x01,
+        (timestamp >> 22) as u8,
+        (((timestamp >> 15) as u8 & 0x7F) << 1) | 0x01,
+        (timestamp >> 7) as u8,
+        ((timestamp as u8 & 0x7F) << 1) | 0x01,
+    ]
+}
+
+/// Encode a PCR from a 90kHz base (extension is always zero)
+fn encode_pcr(base: u64) -> [u8; 6] {
+    let base = base & 0x1_FFFF_FFFF;
+    [
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        ((base as u8 & 0x01) << 7) | 0x7E,
+        0x00,
+    ]
+}
+
+/// CRC-32/MPEG-2 used by PSI sections
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::EncodedAudioFrame;
+
+    #[test]
+    fn test_crc32_mpeg2() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_E6E7);
+    }
+
+    #[test]
+    fn test_audio_only_segment_layout() {
+        let frame = vec![0xAB; 300];
+        let segment = EncodedAudioSegment {
+            data: frame.clone(),
+            codec: AudioCodec::AAC,
+            sequence: 0,
+            duration: 1024.0 / 48000.0,
+            timestamp: 0,
+            sample_rate: 48000,
+            channels: 2,
+            pts: 0,
+            frames: vec![EncodedAudioFrame { offset: 0, size: frame.len(), pts: 0 }],
+        };
+
+        let mut muxer = TsMuxer::new();
+        let first = muxer.mux_segment(None, Some(&segment)).unwrap();
+        let second = muxer.mux_segment(None, Some(&segment)).unwrap();
+
+        assert_eq!(first.len() % TS_PACKET_SIZE, 0);
+        assert!(first.chunks(TS_PACKET_SIZE).all(|packet| packet[0] == SYNC_BYTE));
+
+        // PAT, PMT, then two audio packets carrying the 314-byte PES
+        assert_eq!(first.len(), 4 * TS_PACKET_SIZE);
+        let audio_cc = |data: &[u8], index: usize| data[index * TS_PACKET_SIZE + 3] & 0x0F;
+        assert_eq!((audio_cc(&first, 2), audio_cc(&first, 3)), (0, 1));
+        assert_eq!((audio_cc(&second, 2), audio_cc(&second, 3)), (2, 3));
+    }
+}
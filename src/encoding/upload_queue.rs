@@ -0,0 +1,805 @@
+//! Durable Upload Queue
+//!
+//! Delivers segments and playlists to a remote sink through the shared
+//! `UploadScheduler`, retrying failed uploads with capped exponential backoff
+//! until they succeed. A playlist is always queued after the segments it lists
+//! and only starts once everything ahead of it has been uploaded. Only an
+//! upload the sink rejects is dropped; playlists queued after it wait for newer
+//! copies that no longer list it. With a spool directory, pending uploads are
+//! persisted per session and replayed first by the next session, and dropped
+//! uploads are kept in the spool's `failed` directory.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{ObjectDigest, S3ContentType, SegmentSink, UploadPriority, UploadScheduler, fs_sink::write_atomic};
+use napi_derive::napi;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Retry policy for uploads
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts per request retried in place, such as deletes and multipart
+    /// parts; queued uploads retry transient failures until they succeed
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds
+    pub initial_backoff_ms: u32,
+    /// Upper bound for the delay between retries in milliseconds
+    pub max_backoff_ms: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff_ms: 250,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Delay before retry `attempt` (counting from 0): doubles every attempt up
+    /// to `max_backoff_ms`, with the upper half of the delay randomized
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = (self.initial_backoff_ms.max(1) as u64)
+            .saturating_mul(1 << attempt.min(32))
+            .min(self.max_backoff_ms.max(1) as u64);
+        let jitter = delay / 2;
+
+        Duration::from_millis(delay - jitter + fastrand::u64(..=jitter))
+    }
+}
+
+/// Run an operation until it succeeds, is rejected or the policy's attempts are used up
+pub async fn retry_with_backoff<T, F, Fut>(retry: &RetryConfig, what: &str, mut operation: F) -> CaptureResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = CaptureResult<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt + 1 < retry.max_attempts && !matches!(e, CaptureError::UploadRejected(_)) => {
+                let delay = retry.backoff(attempt);
+                log::warn!("{} failed (attempt {}/{}), retrying in {:?}: {}", what, attempt + 1, retry.max_attempts, delay, e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether an HTTP status means the request will fail again however often it is retried
+pub(super) fn is_permanent_status(status: u16) -> bool {
+    (400..500).contains(&status) && !matches!(status, 408 | 429)
+}
+
+/// Result of a queued upload, reported once it succeeds, on its first failure and when it is dropped
+#[derive(Debug)]
+pub struct UploadOutcome {
+    /// Whether the upload was rejected and taken out of the queue
+    pub dropped: bool,
+    /// Object key
+    pub key: String,
+    /// Kind of object
+    pub content_type: S3ContentType,
+    /// Payload size in bytes
+    pub bytes: u64,
+    /// Key on success, or the upload error
+    pub result: CaptureResult<String>,
+}
+
+/// Metadata of a spooled upload; the payload lives next to it
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolEntry {
+    key: String,
+    content_type: S3ContentType,
+    /// SHA-256 of the payload in hex, checked when it is read back
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+/// An upload waiting in the queue
+#[derive(Clone)]
+struct QueuedUpload {
+    id: u64,
+    key: String,
+    content_type: S3ContentType,
+    /// Payload held in memory; `None` if it is read back from the spool
+    data: Option<Arc<Vec<u8>>>,
+    /// Expected SHA-256 of a spooled payload
+    sha256: Option<String>,
+    in_flight: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+    /// A playlist queued after a dropped segment, which it may list; it waits
+    /// until a newer copy replaces it
+    stale: bool,
+}
+
+impl QueuedUpload {
+    fn new(id: u64, key: String, content_type: S3ContentType, data: Option<Arc<Vec<u8>>>, sha256: Option<String>) -> Self {
+        Self {
+            id,
+            key,
+            content_type,
+            data,
+            sha256,
+            in_flight: false,
+            failures: 0,
+            retry_at: None,
+            stale: false,
+        }
+    }
+}
+
+/// What the worker should do next
+enum NextUpload {
+    Ready(QueuedUpload),
+    RetryAt(Instant),
+    Idle,
+}
+
+type OutcomeCallback = Arc<dyn Fn(UploadOutcome) + Send + Sync>;
+
+/// Spool directories in use by queues of this process, which other sessions must not replay
+static ACTIVE_SPOOLS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Directory next to the session spools keeping uploads that were given up on
+const DEAD_LETTER_DIR: &str = "failed";
+
+struct QueueState {
+    sink: Arc<dyn SegmentSink>,
+    retry: RetryConfig,
+    scheduler: Arc<UploadScheduler>,
+    /// This session's spool, `<spool_root>/<sink>/<session>`
+    spool_dir: Option<PathBuf>,
+    /// Where dropped uploads of this session are moved
+    dead_letter_dir: Option<PathBuf>,
+    uploads: Mutex<VecDeque<QueuedUpload>>,
+    next_id: AtomicU64,
+    wake: Notify,
+}
+
+/// Upload queue for one remote sink
+#[derive(Clone)]
+pub struct UploadQueue {
+    state: Arc<QueueState>,
+}
+
+impl UploadQueue {
+    /// Create a queue for `sink`, taking over uploads left in its spool by earlier sessions
+    pub async fn new(
+        sink: Arc<dyn SegmentSink>,
+        retry: RetryConfig,
+        scheduler: Arc<UploadScheduler>,
+        spool_root: Option<&Path>,
+        session_id: &str,
+    ) -> CaptureResult<Self> {
+        let sink_dir = spool_root.map(|root| root.join(spool_dir_name(&sink.id())));
+        let spool_dir = match &sink_dir {
+            Some(sink_dir) => Some(claim_spool(sink_dir, session_id).await?),
+            None => None,
+        };
+        let dead_letter_dir = sink_dir.map(|sink_dir| sink_dir.join(DEAD_LETTER_DIR).join(spool_dir_name(session_id)));
+
+        let uploads = match &spool_dir {
+            Some(dir) => load_spool(dir).await?,
+            None => VecDeque::new(),
+        };
+        if !uploads.is_empty() {
+            log::info!("Replaying {} spooled uploads to {} sink", uploads.len(), sink.name());
+        }
+        let next_id = uploads.back().map_or(0, |upload| upload.id + 1);
+
+        Ok(Self {
+            state: Arc::new(QueueState {
+                sink,
+                retry,
+                scheduler,
+                spool_dir,
+                dead_letter_dir,
+                uploads: Mutex::new(uploads),
+                next_id: AtomicU64::new(next_id),
+                wake: Notify::new(),
+            }),
+        })
+    }
+
+    /// Sink the queue uploads to
+    pub fn sink(&self) -> &Arc<dyn SegmentSink> {
+        &self.state.sink
+    }
+
+    /// Number of uploads not yet confirmed by the sink
+    pub fn pending(&self) -> usize {
+        self.state.uploads.lock().unwrap().len()
+    }
+
+    /// Number of uploads not currently in flight
+    pub fn waiting(&self) -> usize {
+        self.state.uploads.lock().unwrap().iter().filter(|upload| !upload.in_flight).count()
+    }
+
+    /// Queue an object for upload, spooling it to disk first if configured
+    ///
+    /// A queued playlist or manifest replaces older queued copies of itself.
+    pub async fn enqueue(&self, key: String, content_type: S3ContentType, data: Vec<u8>) {
+        let id = self.state.next_id.fetch_add(1, Ordering::SeqCst);
+
+        let (data, sha256) = match &self.state.spool_dir {
+            Some(dir) => match write_spool_entry(dir, id, &key, &content_type, &data).await {
+                Ok(sha256) => (None, Some(sha256)),
+                Err(e) => {
+                    log::warn!("Failed to spool {}, keeping it in memory: {}", key, e);
+                    (Some(Arc::new(data)), None)
+                }
+            },
+            None => (Some(Arc::new(data)), None),
+        };
+
+        let mut superseded = Vec::new();
+        {
+            let mut uploads = self.state.uploads.lock().unwrap();
+            if content_type.is_replaced_in_place() {
+                uploads.retain(|upload| {
+                    let replaced = !upload.in_flight && upload.key == key;
+                    if replaced {
+                        superseded.push(upload.id);
+                    }
+                    !replaced
+                });
+            }
+            uploads.push_back(QueuedUpload::new(id, key, content_type, data, sha256));
+        }
+        self.state.wake.notify_one();
+
+        for id in superseded {
+            self.state.remove_spool_entry(id).await;
+        }
+    }
+
+    /// Delete a segment from the sink, dropping queued uploads of it first
+    ///
+    /// An upload already in flight is waited for, so it cannot recreate the object afterwards.
+    pub async fn delete(&self, content_type: S3ContentType, sequence: u32) -> CaptureResult<String> {
+        let key = self.state.sink.object_key(&content_type, sequence);
+
+        let mut cancelled = Vec::new();
+        self.state.uploads.lock().unwrap().retain(|upload| {
+            let cancel = !upload.in_flight && upload.key == key;
+            if cancel {
+                cancelled.push(upload.id);
+            }
+            !cancel
+        });
+        for id in cancelled {
+            self.state.remove_spool_entry(id).await;
+        }
+
+        while self.state.uploads.lock().unwrap().iter().any(|upload| upload.key == key) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        retry_with_backoff(&self.state.retry, &key, || self.state.sink.delete(content_type.clone(), sequence)).await
+    }
+
+    /// Start the worker that hands queued uploads to the scheduler
+    pub fn spawn(&self, on_outcome: impl Fn(UploadOutcome) + Send + Sync + 'static) -> JoinHandle<()> {
+        let state = self.state.clone();
+        tokio::spawn(state.run(Arc::new(on_outcome)))
+    }
+
+    /// Wait until every queued upload has been confirmed, returning false on timeout
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            while self.pending() > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+impl QueueState {
+    async fn run(self: Arc<Self>, on_outcome: OutcomeCallback) {
+        // Dropping the set when the worker is aborted cancels its uploads
+        let mut uploads = JoinSet::new();
+
+        loop {
+            while uploads.try_join_next().is_some() {}
+
+            match self.next_upload(false) {
+                NextUpload::Ready(upload) => {
+                    let permit = self.scheduler.acquire(UploadPriority::of(&upload.content_type)).await;
+
+                    // Pick again, a more urgent upload may have arrived while waiting
+                    if let NextUpload::Ready(upload) = self.next_upload(true) {
+                        let (state, on_outcome) = (self.clone(), on_outcome.clone());
+                        uploads.spawn(async move {
+                            state.upload(upload, on_outcome.as_ref()).await;
+                            drop(permit);
+                        });
+                    }
+                }
+                NextUpload::RetryAt(retry_at) => {
+                    tokio::select! {
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep_until(retry_at.into()) => {}
+                    }
+                }
+                NextUpload::Idle => self.wake.notified().await,
+            }
+        }
+    }
+
+    /// Most urgent upload that may start now, marking it in flight if `take` is set
+    ///
+    /// Segments may overtake each other by priority, but a playlist waits until
+    /// everything queued before it has been uploaded, so it never lists a
+    /// segment the sink does not have yet.
+    fn next_upload(&self, take: bool) -> NextUpload {
+        let mut uploads = self.uploads.lock().unwrap();
+        let now = Instant::now();
+
+        let mut best: Option<(UploadPriority, usize)> = None;
+        let mut retry_at: Option<Instant> = None;
+        let mut segment_ahead = false;
+        let mut playlists_ahead: Vec<&str> = Vec::new();
+
+        for (index, upload) in uploads.iter().enumerate() {
+            let blocked = if upload.content_type.is_replaced_in_place() {
+                let blocked = segment_ahead || playlists_ahead.contains(&upload.key.as_str());
+                playlists_ahead.push(&upload.key);
+                blocked
+            } else {
+                segment_ahead = true;
+                false
+            };
+            if blocked || upload.in_flight || upload.stale {
+                continue;
+            }
+
+            if let Some(at) = upload.retry_at.filter(|at| *at > now) {
+                retry_at = Some(retry_at.map_or(at, |earliest| earliest.min(at)));
+                continue;
+            }
+
+            let priority = UploadPriority::of(&upload.content_type);
+            if best.map_or(true, |(best_priority, _)| priority < best_priority) {
+                best = Some((priority, index));
+            }
+        }
+
+        match (best, retry_at) {
+            (Some((_, index)), _) => {
+                if take {
+                    uploads[index].in_flight = true;
+                }
+                NextUpload::Ready(uploads[index].clone())
+            }
+            (None, Some(retry_at)) => NextUpload::RetryAt(retry_at),
+            (None, None) => NextUpload::Idle,
+        }
+    }
+
+    async fn upload(&self, upload: QueuedUpload, on_outcome: &(dyn Fn(UploadOutcome) + Send + Sync)) {
+        let QueuedUpload { id, key, content_type, data, sha256, .. } = upload;
+
+        let data = match data {
+            Some(data) => data.as_ref().clone(),
+            None => match self.read_spool_data(id, sha256.as_deref()).await {
+                Ok(data) => data,
+                Err(e) => {
+                    // A damaged spool entry cannot be retried
+                    log::error!("Dropping upload of {} to {} sink: {}", key, self.sink.name(), e);
+                    self.drop_upload(id).await;
+                    on_outcome(UploadOutcome { dropped: true, key, content_type, bytes: 0, result: Err(e) });
+                    return;
+                }
+            },
+        };
+        let bytes = data.len() as u64;
+
+        self.scheduler.throttle(bytes).await;
+        match self.sink.put_object(key.clone(), content_type.clone(), data).await {
+            Ok(stored) => {
+                self.complete(id).await;
+                on_outcome(UploadOutcome { dropped: false, key, content_type, bytes, result: Ok(stored) });
+            }
+            Err(e @ CaptureError::UploadRejected(_)) => {
+                log::error!("Dropping rejected upload of {} to {} sink: {}", key, self.sink.name(), e);
+                self.drop_upload(id).await;
+                on_outcome(UploadOutcome { dropped: true, key, content_type, bytes, result: Err(e) });
+            }
+            Err(e) => {
+                if let Some((failures, delay)) = self.schedule_retry(id) {
+                    log::warn!("Upload of {} to {} sink failed, retrying in {:?}: {}", key, self.sink.name(), delay, e);
+                    if failures == 1 {
+                        on_outcome(UploadOutcome { dropped: false, key, content_type, bytes, result: Err(e) });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Put a failed upload back in line after its backoff delay, returning its
+    /// failure count and the delay
+    ///
+    /// Transient failures are retried for as long as it takes, so the
+    /// playlists queued behind the upload keep waiting for it.
+    fn schedule_retry(&self, id: u64) -> Option<(u32, Duration)> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.iter_mut().find(|upload| upload.id == id)?;
+
+        upload.failures += 1;
+        let delay = self.retry.backoff(upload.failures - 1);
+        upload.in_flight = false;
+        upload.retry_at = Some(Instant::now() + delay);
+        let failures = upload.failures;
+        drop(uploads);
+
+        self.wake.notify_one();
+        Some((failures, delay))
+    }
+
+    /// Take an upload that can never succeed out of the queue and keep its spool entry aside
+    ///
+    /// Playlists queued after a dropped segment may list it, so they are held
+    /// back until newer copies replace them.
+    async fn drop_upload(&self, id: u64) {
+        {
+            let mut uploads = self.uploads.lock().unwrap();
+            let Some(index) = uploads.iter().position(|upload| upload.id == id) else {
+                return;
+            };
+            let dropped = uploads.remove(index);
+            if dropped.is_some_and(|upload| !upload.content_type.is_replaced_in_place()) {
+                for upload in uploads.iter_mut().skip(index) {
+                    upload.stale |= upload.content_type.is_replaced_in_place();
+                }
+            }
+        }
+        self.wake.notify_one();
+        self.dead_letter(id).await;
+    }
+
+    /// Move a dropped upload's spool entry aside, where it is kept but not replayed
+    async fn dead_letter(&self, id: u64) {
+        let (Some(dir), Some(failed)) = (&self.spool_dir, &self.dead_letter_dir) else {
+            return;
+        };
+        if let Err(e) = tokio::fs::create_dir_all(failed).await {
+            log::warn!("Failed to create {}: {}", failed.display(), e);
+            self.remove_spool_entry(id).await;
+            return;
+        }
+        // The metadata goes first, as when removing an entry
+        for extension in ["json", "bin"] {
+            let name = format!("{:020}.{}", id, extension);
+            let _ = tokio::fs::rename(dir.join(&name), failed.join(&name)).await;
+        }
+    }
+
+    /// Remove a finished upload from the queue and the spool
+    async fn complete(&self, id: u64) {
+        self.uploads.lock().unwrap().retain(|upload| upload.id != id);
+        self.wake.notify_one();
+        self.remove_spool_entry(id).await;
+    }
+
+    async fn read_spool_data(&self, id: u64, sha256: Option<&str>) -> CaptureResult<Vec<u8>> {
+        let dir = self.spool_dir.as_ref()
+            .ok_or_else(|| CaptureError::InvalidState("Upload has no data and no spool".to_string()))?;
+        let data = tokio::fs::read(dir.join(format!("{:020}.bin", id))).await?;
+
+        if let Some(expected) = sha256 {
+            let actual = ObjectDigest::of(&data).sha256_hex();
+            if actual != expected {
+                return Err(CaptureError::Upload(format!(
+                    "Spooled upload {} is corrupted: SHA-256 {} does not match {}", id, actual, expected
+                )));
+            }
+        }
+        Ok(data)
+    }
+
+    async fn remove_spool_entry(&self, id: u64) {
+        if let Some(dir) = &self.spool_dir {
+            // The metadata goes first so a crash in between leaves no half entry behind
+            let _ = tokio::fs::remove_file(dir.join(format!("{:020}.json", id))).await;
+            let _ = tokio::fs::remove_file(dir.join(format!("{:020}.bin", id))).await;
+        }
+    }
+}
+
+impl Drop for QueueState {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.spool_dir {
+            ACTIVE_SPOOLS.lock().unwrap().retain(|path| path != dir);
+            // Only removed once empty; leftovers are replayed by the next session
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Create a session's spool directory and move in the uploads left by earlier sessions
+///
+/// Each session spools to its own directory, so concurrent sessions never
+/// share upload ids. Directories of queues still running in this process are
+/// left alone; other processes need their own spool root.
+async fn claim_spool(sink_dir: &Path, session_id: &str) -> CaptureResult<PathBuf> {
+    let dir = sink_dir.join(spool_dir_name(session_id));
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let mut found = Vec::new();
+    let mut entries = tokio::fs::read_dir(sink_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if entry.file_type().await?.is_dir() && path != dir && entry.file_name() != DEAD_LETTER_DIR {
+            found.push(path);
+        }
+    }
+    found.sort();
+
+    let orphans: Vec<PathBuf> = {
+        let mut active = ACTIVE_SPOOLS.lock().unwrap();
+        let orphans: Vec<_> = found.into_iter().filter(|path| !active.contains(path)).collect();
+        active.push(dir.clone());
+        active.extend(orphans.iter().cloned());
+        orphans
+    };
+
+    let adopted = adopt_spools(&dir, &orphans).await;
+    let mut active = ACTIVE_SPOOLS.lock().unwrap();
+    active.retain(|path| !orphans.contains(path));
+    if adopted.is_err() {
+        active.retain(|path| *path != dir);
+    }
+    adopted.map(|_| dir)
+}
+
+/// Move the entries of other sessions' spools into `dir`, numbered after its own
+async fn adopt_spools(dir: &Path, orphans: &[PathBuf]) -> CaptureResult<()> {
+    let mut next_id = load_spool(dir).await?.back().map_or(0, |upload| upload.id + 1);
+
+    for orphan in orphans {
+        for upload in load_spool(orphan).await? {
+            let name = |id: u64, extension: &str| format!("{:020}.{}", id, extension);
+            // Payload first, so the metadata never refers to a missing payload
+            if let Err(e) = tokio::fs::rename(orphan.join(name(upload.id, "bin")), dir.join(name(next_id, "bin"))).await {
+                log::warn!("Dropping spooled upload {} in {}: {}", upload.id, orphan.display(), e);
+                let _ = tokio::fs::remove_file(orphan.join(name(upload.id, "json"))).await;
+                continue;
+            }
+            tokio::fs::rename(orphan.join(name(upload.id, "json")), dir.join(name(next_id, "json"))).await?;
+            next_id += 1;
+        }
+        let _ = tokio::fs::remove_dir(orphan).await;
+    }
+    Ok(())
+}
+
+/// Directory name for a sink id, keeping only filename-safe characters
+fn spool_dir_name(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+/// Persist an upload: payload first, then the metadata that makes it visible
+///
+/// Returns the SHA-256 of the payload recorded in the metadata.
+async fn write_spool_entry(dir: &Path, id: u64, key: &str, content_type: &S3ContentType, data: &[u8]) -> CaptureResult<String> {
+    let sha256 = ObjectDigest::of(data).sha256_hex();
+    let entry = SpoolEntry {
+        key: key.to_string(),
+        content_type: content_type.clone(),
+        sha256: Some(sha256.clone()),
+    };
+    let metadata = serde_json::to_vec(&entry)
+        .map_err(|e| CaptureError::Upload(format!("Failed to serialize spool entry: {}", e)))?;
+
+    write_atomic(&dir.join(format!("{:020}.bin", id)), data).await?;
+    write_atomic(&dir.join(format!("{:020}.json", id)), &metadata).await?;
+    Ok(sha256)
+}
+
+/// Load spooled uploads in the order they were queued
+async fn load_spool(dir: &Path) -> CaptureResult<VecDeque<QueuedUpload>> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let mut ids = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_metadata = path.extension().is_some_and(|extension| extension == "json");
+        let id = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok());
+        if let (true, Some(id)) = (is_metadata, id) {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+
+    let mut uploads: VecDeque<QueuedUpload> = VecDeque::new();
+    for id in ids {
+        let metadata = tokio::fs::read(dir.join(format!("{:020}.json", id))).await?;
+        let entry = match serde_json::from_slice::<SpoolEntry>(&metadata) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("Skipping unreadable spool entry {} in {}: {}", id, dir.display(), e);
+                continue;
+            }
+        };
+
+        // Only the newest copy of a playlist or manifest is worth uploading
+        if entry.content_type.is_replaced_in_place() {
+            if let Some(index) = uploads.iter().position(|upload| upload.key == entry.key) {
+                let old_id = uploads.remove(index).map(|upload| upload.id).unwrap_or(id);
+                let _ = tokio::fs::remove_file(dir.join(format!("{:020}.json", old_id))).await;
+                let _ = tokio::fs::remove_file(dir.join(format!("{:020}.bin", old_id))).await;
+            }
+        }
+        uploads.push_back(QueuedUpload::new(id, entry.key, entry.content_type, None, entry.sha256));
+    }
+    Ok(uploads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{MemorySink, SinkFuture, object_key};
+    use std::sync::atomic::AtomicU32;
+
+    /// Memory sink that fails the first few writes and always rejects one key
+    struct FlakySink {
+        inner: MemorySink,
+        failures_left: AtomicU32,
+        rejected: Option<String>,
+        order: Mutex<Vec<String>>,
+    }
+
+    impl SegmentSink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn object_key(&self, content_type: &S3ContentType, sequence: u32) -> String {
+            object_key("user", "video", content_type, sequence)
+        }
+
+        fn put_object(&self, key: String, content_type: S3ContentType, data: Vec<u8>) -> SinkFuture<'_, String> {
+            if self.rejected.as_deref() == Some(key.as_str()) {
+                return Box::pin(async { Err(CaptureError::UploadRejected("forbidden".to_string())) });
+            }
+            if self.failures_left.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                return Box::pin(async { Err(CaptureError::Upload("unreachable".to_string())) });
+            }
+            self.order.lock().unwrap().push(key.clone());
+            self.inner.put_object(key, content_type, data)
+        }
+
+        fn delete(&self, content_type: S3ContentType, sequence: u32) -> SinkFuture<'_, String> {
+            self.inner.delete(content_type, sequence)
+        }
+
+        fn finalize(&self) -> SinkFuture<'_, ()> {
+            self.inner.finalize()
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let retry = RetryConfig { max_attempts: 5, initial_backoff_ms: 100, max_backoff_ms: 1000 };
+
+        for _ in 0..20 {
+            let first = retry.backoff(0).as_millis();
+            assert!((50..=100).contains(&first));
+            let third = retry.backoff(2).as_millis();
+            assert!((200..=400).contains(&third));
+            let capped = retry.backoff(30).as_millis();
+            assert!((500..=1000).contains(&capped));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spooled_uploads_replay_before_playlist() {
+        let spool = std::env::temp_dir().join(format!("cap-spool-{}", uuid::Uuid::new_v4()));
+        let retry = RetryConfig { max_attempts: 4, initial_backoff_ms: 1, max_backoff_ms: 2 };
+        let scheduler = UploadScheduler::new(2, None);
+        let sink = Arc::new(FlakySink {
+            inner: MemorySink::new("user".to_string(), "video".to_string()),
+            failures_left: AtomicU32::new(3),
+            rejected: None,
+            order: Mutex::new(Vec::new()),
+        });
+
+        // Queue while "offline": nothing is uploaded and everything is spooled
+        let queue = UploadQueue::new(sink.clone(), retry.clone(), scheduler.clone(), Some(&spool), "first").await.unwrap();
+        queue.enqueue("user/video/video/video_recording_0.ts".to_string(), S3ContentType::VideoSegment, vec![0]).await;
+        queue.enqueue("user/video/video/video_recording_1.ts".to_string(), S3ContentType::VideoSegment, vec![1]).await;
+        queue.enqueue("user/video/video/stream.m3u8".to_string(), S3ContentType::VideoPlaylist, b"old".to_vec()).await;
+        queue.enqueue("user/video/video/stream.m3u8".to_string(), S3ContentType::VideoPlaylist, b"new".to_vec()).await;
+        drop(queue);
+
+        // The next session takes the spool over and replays it once the sink recovers
+        let queue = UploadQueue::new(sink.clone(), retry, scheduler, Some(&spool), "second").await.unwrap();
+        assert_eq!(queue.pending(), 3);
+        let worker = queue.spawn(|_| {});
+        assert!(queue.drain(Duration::from_secs(5)).await);
+        worker.abort();
+        let _ = worker.await;
+        drop(queue);
+
+        // Segments may retry out of order, but the playlist only goes up after both
+        let mut order = sink.order.lock().unwrap().clone();
+        assert_eq!(order.pop().as_deref(), Some("user/video/video/stream.m3u8"));
+        order.sort();
+        assert_eq!(order, vec!["user/video/video/video_recording_0.ts", "user/video/video/video_recording_1.ts"]);
+        assert_eq!(sink.inner.get("user/video/video/stream.m3u8"), Some(b"new".to_vec()));
+        assert_eq!(std::fs::read_dir(spool.join("flaky")).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(spool).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_block_playlists_and_rejected_uploads_are_dropped() {
+        let spool = std::env::temp_dir().join(format!("cap-spool-{}", uuid::Uuid::new_v4()));
+        let retry = RetryConfig { max_attempts: 2, initial_backoff_ms: 1, max_backoff_ms: 2 };
+        let sink = Arc::new(FlakySink {
+            inner: MemorySink::new("user".to_string(), "video".to_string()),
+            failures_left: AtomicU32::new(u32::MAX),
+            rejected: Some("user/video/video/video_recording_0.ts".to_string()),
+            order: Mutex::new(Vec::new()),
+        });
+
+        let queue = UploadQueue::new(sink.clone(), retry, UploadScheduler::new(2, None), Some(&spool), "session").await.unwrap();
+        queue.enqueue("user/video/video/video_recording_0.ts".to_string(), S3ContentType::VideoSegment, vec![0]).await;
+        queue.enqueue("user/video/video/video_recording_1.ts".to_string(), S3ContentType::VideoSegment, vec![1]).await;
+        queue.enqueue("user/video/video/stream.m3u8".to_string(), S3ContentType::VideoPlaylist, b"old".to_vec()).await;
+
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let worker = queue.spawn({
+            let outcomes = outcomes.clone();
+            move |outcome| outcomes.lock().unwrap().push((outcome.key, outcome.result.is_ok(), outcome.dropped))
+        });
+
+        // The failing segment is retried well past max_attempts and the playlist waits for it
+        assert!(!queue.drain(Duration::from_millis(200)).await);
+        assert_eq!(queue.pending(), 2);
+        assert!(sink.order.lock().unwrap().is_empty());
+
+        // Once the sink recovers, the playlist that may list the rejected segment still waits for a newer copy
+        sink.failures_left.store(0, Ordering::SeqCst);
+        assert!(!queue.drain(Duration::from_millis(200)).await);
+        assert_eq!(*sink.order.lock().unwrap(), vec!["user/video/video/video_recording_1.ts"]);
+        queue.enqueue("user/video/video/stream.m3u8".to_string(), S3ContentType::VideoPlaylist, b"new".to_vec()).await;
+        assert!(queue.drain(Duration::from_secs(5)).await);
+        worker.abort();
+
+        assert_eq!(sink.inner.get("user/video/video/stream.m3u8"), Some(b"new".to_vec()));
+        let mut outcomes = outcomes.lock().unwrap().clone();
+        outcomes.sort();
+        assert_eq!(outcomes, vec![
+            ("user/video/video/stream.m3u8".to_string(), true, false),
+            ("user/video/video/video_recording_0.ts".to_string(), false, true),
+            ("user/video/video/video_recording_1.ts".to_string(), false, false),
+            ("user/video/video/video_recording_1.ts".to_string(), true, false),
+        ]);
+        // Only the rejected segment is kept in the spool's dead letters
+        assert_eq!(std::fs::read_dir(spool.join("flaky").join("failed").join("session")).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(spool).unwrap();
+    }
+}
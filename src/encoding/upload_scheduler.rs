@@ -0,0 +1,246 @@
+//! Upload Scheduler
+//!
+//! Shared by every upload queue of a recording so uploads do not saturate
+//! the uplink: limits the number of uploads in flight, hands free slots to
+//! playlists before audio and audio before video, and throttles the total
+//! upload rate with a token bucket.
+
+use super::S3ContentType;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Upload priority, highest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UploadPriority {
+    Playlist,
+    Audio,
+    Video,
+}
+
+impl UploadPriority {
+    /// Priority of an object; combined segments count as video, the manifests as playlists
+    pub fn of(content_type: &S3ContentType) -> Self {
+        match content_type {
+            S3ContentType::VideoPlaylist |
+            S3ContentType::AudioPlaylist |
+            S3ContentType::CombinedPlaylist |
+            S3ContentType::MasterPlaylist |
+            S3ContentType::VideoArchivePlaylist |
+            S3ContentType::AudioArchivePlaylist |
+            S3ContentType::CombinedArchivePlaylist |
+            S3ContentType::MasterArchivePlaylist |
+            S3ContentType::Manifest |
+            S3ContentType::DashManifest => UploadPriority::Playlist,
+            S3ContentType::AudioSegment |
+            S3ContentType::AudioInit |
+            S3ContentType::AudioFragment |
+            S3ContentType::AudioPart => UploadPriority::Audio,
+            S3ContentType::VideoSegment |
+            S3ContentType::CombinedSegment |
+            S3ContentType::VideoInit |
+            S3ContentType::VideoFragment |
+            S3ContentType::VideoPart => UploadPriority::Video,
+        }
+    }
+}
+
+/// Upload waiting for a free slot
+struct Waiter {
+    priority: UploadPriority,
+    ticket: u64,
+    grant: oneshot::Sender<UploadPermit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    // Reversed so the max-heap pops the highest priority, then the oldest ticket
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.priority, other.ticket).cmp(&(self.priority, self.ticket))
+    }
+}
+
+struct SchedulerState {
+    in_flight: usize,
+    waiters: BinaryHeap<Waiter>,
+    next_ticket: u64,
+    /// Token bucket balance in bytes; negative while throttled uploads pay off their debt
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Concurrency and bandwidth limits shared by all uploads of a recording
+pub struct UploadScheduler {
+    max_in_flight: usize,
+    bytes_per_second: Option<u64>,
+    state: Mutex<SchedulerState>,
+}
+
+impl UploadScheduler {
+    /// Create a scheduler; `bytes_per_second` of `None` disables throttling
+    pub fn new(max_in_flight: u32, bytes_per_second: Option<u64>) -> Arc<Self> {
+        Arc::new(Self {
+            max_in_flight: max_in_flight.max(1) as usize,
+            bytes_per_second: bytes_per_second.filter(|rate| *rate > 0),
+            state: Mutex::new(SchedulerState {
+                in_flight: 0,
+                waiters: BinaryHeap::new(),
+                next_ticket: 0,
+                tokens: bytes_per_second.unwrap_or(0) as f64,
+                refilled_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// Uploads currently holding a slot
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+
+    /// Uploads waiting for a slot
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiters.len()
+    }
+
+    /// Wait for a free upload slot; the slot is released when the permit is dropped
+    pub async fn acquire(self: &Arc<Self>, priority: UploadPriority) -> UploadPermit {
+        let granted = {
+            let mut state = self.state.lock().unwrap();
+            if state.in_flight < self.max_in_flight && state.waiters.is_empty() {
+                state.in_flight += 1;
+                None
+            } else {
+                let (grant, granted) = oneshot::channel();
+                let ticket = state.next_ticket;
+                state.next_ticket += 1;
+                state.waiters.push(Waiter { priority, ticket, grant });
+                Some(granted)
+            }
+        };
+
+        // A released slot is handed over with its permit, so `in_flight` already counts this upload
+        match granted {
+            Some(granted) => granted.await.expect("upload waiters are only removed to be granted a slot"),
+            None => UploadPermit { scheduler: self.clone() },
+        }
+    }
+
+    /// Take `bytes` from the token bucket, waiting while the upload rate is exceeded
+    pub async fn throttle(&self, bytes: u64) {
+        let Some(rate) = self.bytes_per_second else {
+            return;
+        };
+
+        let delay = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled_at).as_secs_f64() * rate as f64;
+            // At most one second of burst
+            state.tokens = (state.tokens + refill).min(rate as f64) - bytes as f64;
+            state.refilled_at = now;
+            (state.tokens < 0.0).then(|| Duration::from_secs_f64(-state.tokens / rate as f64))
+        };
+
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn release(self: &Arc<Self>) {
+        let waiter = {
+            let mut state = self.state.lock().unwrap();
+            let waiter = state.waiters.pop();
+            if waiter.is_none() {
+                state.in_flight -= 1;
+            }
+            waiter
+        };
+
+        // The slot moves on with the permit. If the waiter gave up, before or after
+        // the permit was sent, dropping the permit releases the slot to the next one
+        if let Some(waiter) = waiter {
+            let _ = waiter.grant.send(UploadPermit { scheduler: self.clone() });
+        }
+    }
+}
+
+/// Upload slot held for the duration of one upload
+pub struct UploadPermit {
+    scheduler: Arc<UploadScheduler>,
+}
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slots_go_to_playlists_then_audio_then_video() {
+        let scheduler = UploadScheduler::new(1, None);
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let first = scheduler.acquire(UploadPriority::Video).await;
+        let mut waiters = Vec::new();
+        for priority in [UploadPriority::Video, UploadPriority::Audio, UploadPriority::Playlist] {
+            let (scheduler, order) = (scheduler.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+            }));
+            tokio::task::yield_now().await;
+        }
+        while scheduler.waiting() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().unwrap(), vec![UploadPriority::Playlist, UploadPriority::Audio, UploadPriority::Video]);
+        assert_eq!(scheduler.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_acquire_releases_its_slot() {
+        let scheduler = UploadScheduler::new(1, None);
+        let first = scheduler.acquire(UploadPriority::Video).await;
+
+        // Cancelled while waiting
+        let mut waiting = Box::pin(scheduler.acquire(UploadPriority::Audio));
+        assert!(futures::poll!(&mut waiting).is_pending());
+        drop(waiting);
+
+        // Cancelled after the slot was handed over but before the permit was received
+        let mut granted = Box::pin(scheduler.acquire(UploadPriority::Video));
+        assert!(futures::poll!(&mut granted).is_pending());
+        drop(first);
+        assert_eq!(scheduler.in_flight(), 1);
+        drop(granted);
+        assert_eq!(scheduler.in_flight(), 0);
+
+        let _permit = scheduler.acquire(UploadPriority::Video).await;
+        assert_eq!(scheduler.in_flight(), 1);
+    }
+}
@@ -1,216 +1,535 @@
 //! FFmpeg-based Video Encoder
-//! 
-//! Implements Cap's real-time H.264 encoding pipeline for screen capture
+//!
+//! Implements Cap's real-time encoding pipeline for screen capture.
+//! Captured RGBA/BGRA frames are converted to YUV420P with swscale, encoded
+//! with libx264, libx265, libvpx-vp9 or an AV1 encoder and grouped into
+//! segments that each start on a keyframe, so every segment can be decoded
+//! on its own. H.264/HEVC segments are Annex-B byte streams.
 
 use crate::error::{CaptureError, CaptureResult};
-use super::{VideoEncodingConfig, VideoCodec, PixelFormat};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::screen::ScreenFrame;
+use super::{VideoEncodingConfig, VideoCodec, PixelFormat, FrameRate, DEFAULT_SEGMENT_DURATION};
+use crate::config::Resolution;
+use std::collections::VecDeque;
+use ffmpeg::{
+    codec::{context, encoder},
+    format::Pixel,
+    software::scaling,
+    Dictionary, Rational,
+};
+
+/// Clock rate of all video timestamps (MPEG-TS 90kHz)
+pub const VIDEO_CLOCK_RATE: i64 = 90_000;
+
+/// Default x264/x265 preset, fast enough for real-time screen capture
+const DEFAULT_PRESET: &str = "veryfast";
 
 /// Encoded video segment ready for upload
 #[derive(Debug, Clone)]
 pub struct EncodedVideoSegment {
-    /// Encoded H.264 data
+    /// Encoded frames (Annex-B byte stream for H.264/HEVC)
     pub data: Vec<u8>,
+    /// Codec of `data`
+    pub codec: VideoCodec,
     /// Segment sequence number
     pub sequence: u32,
     /// Duration in seconds
     pub duration: f64,
-    /// Timestamp when encoded
+    /// Presentation time of the first frame in milliseconds
     pub timestamp: u64,
     /// Frame count in this segment
     pub frame_count: u32,
     /// Resolution
     pub resolution: (u32, u32),
+    /// Presentation time of the first frame in 90kHz units
+    pub pts: i64,
+    /// Access units in `data`, in decode order
+    pub frames: Vec<EncodedVideoFrame>,
+}
+
+/// Location and timing of a single access unit within a segment
+#[derive(Debug, Clone)]
+pub struct EncodedVideoFrame {
+    /// Byte offset into the segment data
+    pub offset: usize,
+    /// Size in bytes
+    pub size: usize,
+    /// Presentation timestamp in 90kHz units
+    pub pts: i64,
+    /// Decode timestamp in 90kHz units
+    pub dts: i64,
+    /// Whether this is a keyframe
+    pub keyframe: bool,
+}
+
+/// Low-Latency HLS partial segment: the next frames of a segment still being encoded
+#[derive(Debug, Clone)]
+pub struct EncodedVideoPart {
+    /// Sequence number of the segment the part belongs to
+    pub segment: u32,
+    /// Position of the part within its segment
+    pub index: u32,
+    /// Whether the part starts with a keyframe
+    pub independent: bool,
+    /// The part's frames; `sequence` is a running part number
+    pub media: EncodedVideoSegment,
+}
+
+/// Segment being filled with encoded packets
+struct PendingSegment {
+    start_pts: i64,
+    data: Vec<u8>,
+    frames: Vec<EncodedVideoFrame>,
+    /// First frame of the part being filled
+    part_start: usize,
+    part_index: u32,
+}
+
+/// Maps capture timestamps to 90kHz PTS and picks the frames that start a segment
+struct FrameClock {
+    frame_duration: i64,
+    segment_duration: i64,
+    first_timestamp: Option<u64>,
+    /// Capture time skipped by pauses, in milliseconds
+    paused_time: u64,
+    /// Set on resume; the next frame follows the last encoded one
+    resuming: bool,
+    last_pts: Option<i64>,
+    next_keyframe_pts: i64,
+}
+
+impl FrameClock {
+    fn new(frame_duration: i64, segment_duration: i64) -> Self {
+        Self {
+            frame_duration,
+            segment_duration,
+            first_timestamp: None,
+            paused_time: 0,
+            resuming: false,
+            last_pts: None,
+            next_keyframe_pts: 0,
+        }
+    }
+
+    /// Map a capture timestamp to a strictly increasing 90kHz PTS
+    fn next_pts(&mut self, timestamp_ms: u64) -> i64 {
+        let first = *self.first_timestamp.get_or_insert(timestamp_ms);
+        let elapsed = timestamp_ms.saturating_sub(first);
+        if let (true, Some(last)) = (std::mem::take(&mut self.resuming), self.last_pts) {
+            let expected = ((last + self.frame_duration) * 1000 / VIDEO_CLOCK_RATE) as u64;
+            self.paused_time = elapsed.saturating_sub(expected);
+        }
+        let mut pts = elapsed.saturating_sub(self.paused_time) as i64 * VIDEO_CLOCK_RATE / 1000;
+
+        if let Some(last) = self.last_pts {
+            pts = pts.max(last + 1);
+        }
+
+        self.last_pts = Some(pts);
+        pts
+    }
+
+    /// Whether the frame at `pts` crosses a segment boundary and must be a keyframe
+    fn starts_segment(&mut self, pts: i64) -> bool {
+        if pts < self.next_keyframe_pts {
+            return false;
+        }
+        while self.next_keyframe_pts <= pts {
+            self.next_keyframe_pts += self.segment_duration;
+        }
+        true
+    }
+}
+
+/// swscale context for a given input format and size
+struct FrameConverter {
+    input: (Pixel, u32, u32),
+    context: scaling::Context,
 }
 
 /// FFmpeg-based video encoder following Cap's implementation
-#[derive(Clone)]
 pub struct VideoEncoder {
     config: VideoEncodingConfig,
-    encoder: Option<()>, // Simplified for now - will use proper FFmpeg encoder type with Cap's fork
+    encoder: encoder::Video,
+    packet: ffmpeg::Packet,
+    converter: Option<FrameConverter>,
+    /// Output size, rounded down to even dimensions for 4:2:0 chroma
+    resolution: (u32, u32),
     sequence_counter: u32,
-    frames_per_segment: u32,
-    current_segment_frames: Vec<Vec<u8>>,
-    frame_counter: u32,
+    clock: FrameClock,
+    /// PTS of forced keyframes that start a new segment
+    segment_starts: VecDeque<i64>,
+    current_segment: Option<PendingSegment>,
+    /// Length of LL-HLS partial segments in 90kHz units, if enabled
+    part_duration: Option<i64>,
+    part_counter: u32,
+    /// Parts completed since the last `take_parts`
+    parts: Vec<EncodedVideoPart>,
+    flushed: bool,
 }
 
 impl VideoEncoder {
-    /// Create new video encoder with Cap's H.264 settings
-    pub fn new(config: VideoEncodingConfig) -> CaptureResult<Self> {
+    /// Create new video encoder for the configured codec, cutting segments of `segment_duration` seconds
+    pub fn new(config: VideoEncodingConfig, segment_duration: f64) -> CaptureResult<Self> {
         log::info!("Initializing FFmpeg video encoder with config: {:?}", config);
-        
+
         // Initialize FFmpeg
         ffmpeg::init().map_err(|e| {
             CaptureError::EncodingError(format!("Failed to initialize FFmpeg: {}", e))
         })?;
 
-        let frames_per_segment = (config.frame_rate.0 as f64 * 2.0) as u32; // 2 second segments
+        if config.frame_rate.numerator == 0 || config.frame_rate.denominator == 0 {
+            return Err(CaptureError::Config(format!("Invalid frame rate: {:?}", config.frame_rate)));
+        }
 
-        let mut encoder = Self {
+        let resolution = (config.resolution.width & !1, config.resolution.height & !1);
+        if resolution.0 == 0 || resolution.1 == 0 {
+            return Err(CaptureError::Config(format!("Invalid resolution: {:?}", config.resolution)));
+        }
+
+        if !segment_duration.is_finite() || segment_duration <= 0.0 {
+            return Err(CaptureError::Config(format!("Invalid segment duration: {}", segment_duration)));
+        }
+
+        let frames_per_segment = ((config.frame_rate.as_f64() * segment_duration).round() as u32).max(1);
+        let encoder = Self::open_encoder(&config, resolution, frames_per_segment)?;
+
+        Ok(Self {
             config: config.clone(),
-            encoder: None,
+            encoder,
+            packet: ffmpeg::Packet::empty(),
+            converter: None,
+            resolution,
             sequence_counter: 0,
-            frames_per_segment,
-            current_segment_frames: Vec::new(),
-            frame_counter: 0,
-        };
+            clock: FrameClock::new(
+                VIDEO_CLOCK_RATE * config.frame_rate.denominator as i64 / config.frame_rate.numerator as i64,
+                (segment_duration * VIDEO_CLOCK_RATE as f64).round() as i64,
+            ),
+            segment_starts: VecDeque::new(),
+            current_segment: None,
+            part_duration: None,
+            part_counter: 0,
+            parts: Vec::new(),
+            flushed: false,
+        })
+    }
+
+    /// Open the first available encoder for the configured codec with Cap's low-latency settings
+    fn open_encoder(
+        config: &VideoEncodingConfig,
+        resolution: (u32, u32),
+        frames_per_segment: u32,
+    ) -> CaptureResult<encoder::Video> {
+        let (encoder_name, codec) = config.codec.encoder_names()
+            .iter()
+            .find_map(|name| encoder::find_by_name(name).map(|codec| (*name, codec)))
+            .ok_or_else(|| CaptureError::EncodingError(format!(
+                "Could not find an encoder for {:?} (tried {})",
+                config.codec, config.codec.encoder_names().join(", ")
+            )))?;
+
+        let mut encoder = context::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .map_err(|e| CaptureError::EncodingError(format!("Failed to create video encoder: {}", e)))?;
+
+        encoder.set_width(resolution.0);
+        encoder.set_height(resolution.1);
+        encoder.set_format(Pixel::YUV420P);
+        encoder.set_time_base(Rational(1, VIDEO_CLOCK_RATE as i32));
+        encoder.set_frame_rate(Some(Rational(
+            config.frame_rate.numerator as i32,
+            config.frame_rate.denominator as i32,
+        )));
+        encoder.set_bit_rate(config.bitrate as usize);
+        encoder.set_gop(config.gop_size.unwrap_or(frames_per_segment));
+        // B-frames would reorder output and delay segment boundaries
+        encoder.set_max_b_frames(0);
+
+        if config.hardware_acceleration {
+            log::debug!("Hardware acceleration requested, using software {} encoder", encoder_name);
+        }
+
+        encoder.open_with(Self::encoder_options(config, encoder_name))
+            .map_err(|e| CaptureError::EncodingError(format!("Failed to open {}: {}", encoder_name, e)))
+    }
+
+    /// Real-time options for each supported FFmpeg encoder
+    fn encoder_options(config: &VideoEncodingConfig, encoder_name: &str) -> Dictionary<'static> {
+        let mut options = Dictionary::new();
+        let preset = config.preset.as_deref();
+
+        match encoder_name {
+            "libx264" | "libx265" => {
+                options.set("preset", preset.unwrap_or(DEFAULT_PRESET));
+                options.set("tune", "zerolatency");
+                // Frames we mark as I at segment boundaries must be IDR frames
+                options.set("forced-idr", "1");
+
+                if encoder_name == "libx265" {
+                    // Keep VPS/SPS/PPS in-band on every IDR so segments stay self-contained;
+                    // muxers extract them for the `hvc1` sample entry
+                    options.set("x265-params", "repeat-headers=1:log-level=error");
+                }
+            }
+            "libvpx-vp9" | "libaom-av1" => {
+                options.set("cpu-used", preset.unwrap_or("8"));
+                options.set("lag-in-frames", "0");
+                options.set("row-mt", "1");
+                if encoder_name == "libvpx-vp9" {
+                    options.set("deadline", "realtime");
+                } else {
+                    options.set("usage", "realtime");
+                }
+            }
+            "libsvtav1" => {
+                options.set("preset", preset.unwrap_or("10"));
+            }
+            _ => {}
+        }
+
+        if let Some(profile) = config.profile.as_deref().or(config.codec.default_profile()) {
+            options.set("profile", profile);
+        }
+
+        options
+    }
+
+    /// Encoder configuration
+    pub fn config(&self) -> &VideoEncodingConfig {
+        &self.config
+    }
+
+    /// Size of the encoded frames
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
 
-        encoder.initialize_encoder()?;
-        Ok(encoder)
+    /// Also cut segments into partial segments of `seconds`, for LL-HLS
+    pub fn set_part_duration(&mut self, seconds: Option<f64>) {
+        self.part_duration = seconds.map(|seconds| (seconds * VIDEO_CLOCK_RATE as f64).round() as i64);
     }
 
-    /// Initialize the H.264 encoder with Cap's settings
-    fn initialize_encoder(&mut self) -> CaptureResult<()> {
-        // For now, use a simplified approach that doesn't rely on specific FFmpeg constants
-        // This will be fully implemented once we have the correct Cap FFmpeg fork
-        log::warn!("Using simplified video encoder initialization - full FFmpeg integration pending");
-        
-        // Create a placeholder encoder that indicates the structure is ready
-        // but actual encoding will need the proper FFmpeg setup
-        self.encoder = None; // Will be properly initialized with Cap's FFmpeg fork
-        
-        log::info!("Video encoder structure initialized (FFmpeg integration pending)");
-        Ok(())
+    /// Take the parts completed so far; they come before the segment that contains them
+    pub fn take_parts(&mut self) -> Vec<EncodedVideoPart> {
+        std::mem::take(&mut self.parts)
     }
 
-    /// Convert our pixel format enum to FFmpeg format
-    #[allow(dead_code)]
-    fn get_pixel_format(&self) -> ffmpeg::format::Pixel {
-        match self.config.pixel_format {
-            PixelFormat::YUV420P => ffmpeg::format::Pixel::YUV420P,
-            PixelFormat::RGBA => ffmpeg::format::Pixel::RGBA,
-            PixelFormat::BGRA => ffmpeg::format::Pixel::BGRA,
+    /// Continue the timeline from the last encoded frame, skipping the capture time until the next frame
+    ///
+    /// Called when a paused recording resumes, so video stays in step with the
+    /// audio, whose timestamps count the samples encoded.
+    pub fn resume(&mut self) {
+        self.clock.resuming = true;
+    }
+
+    /// Encode a captured frame, returning any segments it completed
+    pub fn process_frame(&mut self, frame: &ScreenFrame) -> CaptureResult<Vec<EncodedVideoSegment>> {
+        if self.flushed {
+            return Err(CaptureError::EncodingError("Encoder has already been flushed".to_string()));
+        }
+
+        let pts = self.clock.next_pts(frame.timestamp);
+        let mut yuv_frame = self.convert_frame(frame)?;
+        yuv_frame.set_pts(Some(pts));
+
+        // Force a keyframe on every segment boundary
+        if self.clock.starts_segment(pts) {
+            yuv_frame.set_kind(ffmpeg::picture::Type::I);
+            self.segment_starts.push_back(pts);
         }
+
+        self.encoder.send_frame(&yuv_frame)
+            .map_err(|e| CaptureError::EncodingError(format!("Failed to send video frame: {}", e)))?;
+
+        Ok(self.receive_packets())
     }
 
-    /// Process video frames and encode to H.264 segments
-    pub fn process_frame(&mut self, rgba_frame: &[u8]) -> CaptureResult<Option<EncodedVideoSegment>> {
-        // Add frame to current segment buffer
-        self.current_segment_frames.push(rgba_frame.to_vec());
-        self.frame_counter += 1;
+    /// Convert a packed RGBA/BGRA frame to a YUV420P frame at the output size
+    fn convert_frame(&mut self, frame: &ScreenFrame) -> CaptureResult<ffmpeg::frame::Video> {
+        let input_format = match frame.pixel_format {
+            PixelFormat::RGBA => Pixel::RGBA,
+            PixelFormat::BGRA => Pixel::BGRA,
+            PixelFormat::YUV420P => {
+                return Err(CaptureError::EncodingError("Expected packed RGBA or BGRA frame data".to_string()));
+            }
+        };
+
+        let row_bytes = frame.width as usize * 4;
+        if frame.data.len() < row_bytes * frame.height as usize {
+            return Err(CaptureError::EncodingError(format!(
+                "Frame data too short: {} bytes for {}x{}",
+                frame.data.len(), frame.width, frame.height
+            )));
+        }
+
+        // Captured size can change (display switch), so rebuild the scaler on demand
+        let input = (input_format, frame.width, frame.height);
+        if self.converter.as_ref().map(|converter| converter.input) != Some(input) {
+            let context = scaling::Context::get(
+                input_format, frame.width, frame.height,
+                Pixel::YUV420P, self.resolution.0, self.resolution.1,
+                scaling::Flags::BILINEAR,
+            ).map_err(|e| CaptureError::EncodingError(format!("Failed to create scaler: {}", e)))?;
+
+            self.converter = Some(FrameConverter { input, context });
+        }
+
+        let mut source = ffmpeg::frame::Video::new(input_format, frame.width, frame.height);
+        let stride = source.stride(0);
+        let plane = source.data_mut(0);
+        for (row, pixels) in frame.data.chunks_exact(row_bytes).take(frame.height as usize).enumerate() {
+            plane[row * stride..row * stride + row_bytes].copy_from_slice(pixels);
+        }
 
-        // Check if we have enough frames for a complete segment
-        if self.current_segment_frames.len() >= self.frames_per_segment as usize {
-            let frames = std::mem::take(&mut self.current_segment_frames);
-            let segment = self.encode_video_segment(frames)?;
-            return Ok(Some(segment));
+        let mut output = ffmpeg::frame::Video::empty();
+        if let Some(converter) = &mut self.converter {
+            converter.context.run(&source, &mut output)
+                .map_err(|e| CaptureError::EncodingError(format!("Failed to convert frame: {}", e)))?;
         }
 
-        Ok(None)
+        Ok(output)
     }
 
-    /// Encode a single video segment to H.264
-    fn encode_video_segment(&mut self, frames: Vec<Vec<u8>>) -> CaptureResult<EncodedVideoSegment> {
-        log::debug!("Encoding video segment {} ({} frames)", self.sequence_counter, frames.len());
-        
-        // Mock H.264-encoded data (in production, this would be actual FFmpeg H.264 encoding)
-        let mock_h264_data = vec![0u8; 4096]; // Placeholder for actual H.264 encoding
+    /// Drain encoded packets into the current segment
+    fn receive_packets(&mut self) -> Vec<EncodedVideoSegment> {
+        let mut segments = Vec::new();
+
+        while self.encoder.receive_packet(&mut self.packet).is_ok() {
+            let pts = self.packet.pts().unwrap_or(0);
+            let dts = self.packet.dts().unwrap_or(pts);
+            let keyframe = self.packet.is_key();
+
+            // Some encoders place their own keyframe near a forced one, so cut
+            // on the first keyframe at or after each requested boundary
+            if keyframe && self.segment_starts.front().is_some_and(|&start| pts >= start) {
+                while self.segment_starts.front().is_some_and(|&start| pts >= start) {
+                    self.segment_starts.pop_front();
+                }
+                if let Some(segment) = self.finish_segment(pts) {
+                    segments.push(segment);
+                }
+            }
+
+            if self.packet.data().is_none() {
+                continue;
+            }
+
+            // Parts end on the first frame past their duration
+            let part_start_dts = self.current_segment.as_ref()
+                .and_then(|segment| segment.frames.get(segment.part_start))
+                .map(|frame| frame.dts);
+            if let (Some(start), Some(part_duration)) = (part_start_dts, self.part_duration) {
+                if dts - start >= part_duration {
+                    self.finish_part(dts);
+                }
+            }
+
+            let Some(data) = self.packet.data() else {
+                continue;
+            };
+            let segment = self.current_segment.get_or_insert_with(|| PendingSegment {
+                start_pts: pts,
+                data: Vec::new(),
+                frames: Vec::new(),
+                part_start: 0,
+                part_index: 0,
+            });
+            segment.frames.push(EncodedVideoFrame {
+                offset: segment.data.len(),
+                size: data.len(),
+                pts,
+                dts,
+                keyframe,
+            });
+            segment.data.extend_from_slice(data);
+        }
+
+        segments
+    }
+
+    /// Close the open part of the current segment at `end_dts`
+    fn finish_part(&mut self, end_dts: i64) {
+        let Some(segment) = self.current_segment.as_mut() else {
+            return;
+        };
+        let Some(first) = segment.frames.get(segment.part_start).cloned() else {
+            return;
+        };
+
+        let frames: Vec<_> = segment.frames[segment.part_start..].iter()
+            .map(|frame| EncodedVideoFrame { offset: frame.offset - first.offset, ..frame.clone() })
+            .collect();
+        let part = EncodedVideoPart {
+            segment: self.sequence_counter,
+            index: segment.part_index,
+            independent: first.keyframe,
+            media: EncodedVideoSegment {
+                data: segment.data[first.offset..].to_vec(),
+                codec: self.config.codec,
+                sequence: self.part_counter,
+                duration: (end_dts - first.dts) as f64 / VIDEO_CLOCK_RATE as f64,
+                timestamp: (first.pts * 1000 / VIDEO_CLOCK_RATE) as u64,
+                frame_count: frames.len() as u32,
+                resolution: self.resolution,
+                pts: first.pts,
+                frames,
+            },
+        };
+
+        segment.part_start = segment.frames.len();
+        segment.part_index += 1;
+        self.part_counter += 1;
+        self.parts.push(part);
+    }
+
+    /// Close the current segment at `end_pts`
+    fn finish_segment(&mut self, end_pts: i64) -> Option<EncodedVideoSegment> {
+        if self.part_duration.is_some() {
+            self.finish_part(end_pts);
+        }
+        let pending = self.current_segment.take()?;
 
         let segment = EncodedVideoSegment {
-            data: mock_h264_data,
+            data: pending.data,
+            codec: self.config.codec,
             sequence: self.sequence_counter,
-            duration: 2.0, // Cap uses 2-second segments
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
-            frame_count: frames.len() as u32,
-            resolution: self.config.resolution,
+            duration: (end_pts - pending.start_pts) as f64 / VIDEO_CLOCK_RATE as f64,
+            timestamp: (pending.start_pts * 1000 / VIDEO_CLOCK_RATE) as u64,
+            frame_count: pending.frames.len() as u32,
+            resolution: self.resolution,
+            pts: pending.start_pts,
+            frames: pending.frames,
         };
 
         self.sequence_counter += 1;
-        
-        log::debug!("Encoded video segment {} ({} bytes, {} frames)", 
+
+        log::debug!("Encoded video segment {} ({} bytes, {} frames)",
                    segment.sequence, segment.data.len(), segment.frame_count);
 
-        Ok(segment)
-    }
-
-    /// Encode a single frame
-    #[allow(dead_code)]
-    fn encode_single_frame(&mut self, rgba_frame: &[u8]) -> CaptureResult<Vec<u8>> {
-        // Mock single frame encoding for demonstration
-        log::debug!("Encoding single frame ({} bytes)", rgba_frame.len());
-        
-        // Mock H.264 frame data
-        let mock_frame_data = vec![0u8; 512];
-        Ok(mock_frame_data)
-    }
-
-    /// Convert RGBA to YUV420P color space
-    #[allow(dead_code)]
-    fn convert_rgba_to_yuv420p(&self, rgba_data: &[u8]) -> CaptureResult<Vec<u8>> {
-        let width = self.config.resolution.0 as usize;
-        let height = self.config.resolution.1 as usize;
-        
-        let y_plane_size = width * height;
-        let uv_plane_size = (width / 2) * (height / 2);
-        let total_size = y_plane_size + 2 * uv_plane_size;
-        
-        let mut yuv_data = vec![0u8; total_size];
-        
-        // Convert RGBA to YUV420P
-        for y in 0..height {
-            for x in 0..width {
-                let rgba_idx = (y * width + x) * 4;
-                let r = rgba_data[rgba_idx] as f32;
-                let g = rgba_data[rgba_idx + 1] as f32;
-                let b = rgba_data[rgba_idx + 2] as f32;
-                
-                // Convert to YUV
-                let y_val = (0.257 * r + 0.504 * g + 0.098 * b + 16.0) as u8;
-                let u_val = (-0.148 * r - 0.291 * g + 0.439 * b + 128.0) as u8;
-                let v_val = (0.439 * r - 0.368 * g - 0.071 * b + 128.0) as u8;
-                
-                // Y plane
-                yuv_data[y * width + x] = y_val;
-                
-                // U and V planes (subsampled)
-                if x % 2 == 0 && y % 2 == 0 {
-                    let uv_idx = (y / 2) * (width / 2) + (x / 2);
-                    yuv_data[y_plane_size + uv_idx] = u_val;
-                    yuv_data[y_plane_size + uv_plane_size + uv_idx] = v_val;
-                }
-            }
-        }
-        
-        Ok(yuv_data)
-    }
-
-    /// Copy YUV420P data to FFmpeg frame
-    #[allow(dead_code)]
-    fn copy_yuv420p_to_frame(&self, frame: &mut ffmpeg::frame::Video, yuv_data: &[u8]) -> CaptureResult<()> {
-        let width = self.config.resolution.0 as usize;
-        let height = self.config.resolution.1 as usize;
-        
-        let y_plane_size = width * height;
-        let uv_plane_size = (width / 2) * (height / 2);
-        
-        // Copy Y plane
-        frame.data_mut(0)[..y_plane_size].copy_from_slice(&yuv_data[..y_plane_size]);
-        
-        // Copy U plane
-        frame.data_mut(1)[..uv_plane_size].copy_from_slice(
-            &yuv_data[y_plane_size..y_plane_size + uv_plane_size]
-        );
-        
-        // Copy V plane
-        frame.data_mut(2)[..uv_plane_size].copy_from_slice(
-            &yuv_data[y_plane_size + uv_plane_size..]
-        );
-        
-        Ok(())
+        Some(segment)
     }
 
     /// Flush any remaining video frames
     pub fn flush(&mut self) -> CaptureResult<Vec<EncodedVideoSegment>> {
-        let mut segments = Vec::new();
+        // The encoder has already received EOF; flushing again is a no-op
+        if self.flushed {
+            return Ok(Vec::new());
+        }
+        self.flushed = true;
 
-        // Encode any remaining frames
-        if !self.current_segment_frames.is_empty() {
-            let frames = std::mem::take(&mut self.current_segment_frames);
-            let segment = self.encode_video_segment(frames)?;
-            segments.push(segment);
+        self.encoder.send_eof()
+            .map_err(|e| CaptureError::EncodingError(format!("Failed to flush video encoder: {}", e)))?;
+
+        let mut segments = self.receive_packets();
+
+        // The last segment ends one frame after its final frame
+        if let Some(last_pts) = self.current_segment.as_ref()
+            .and_then(|segment| segment.frames.iter().map(|frame| frame.pts).max())
+        {
+            if let Some(segment) = self.finish_segment(last_pts + self.clock.frame_duration) {
+                segments.push(segment);
+            }
         }
 
         log::debug!("Flushed video encoder with {} remaining segments", segments.len());
@@ -231,11 +550,53 @@ pub fn create_screen_recording_encoder(resolution: (u32, u32)) -> CaptureResult<
     let config = super::VideoEncodingConfig {
         codec: VideoCodec::H264,
         bitrate: 2000000, // 2Mbps
-        frame_rate: (30, 1), // 30fps
-        resolution,
+        frame_rate: FrameRate::new(30, 1), // 30fps
+        resolution: Resolution::new(resolution.0, resolution.1),
         pixel_format: PixelFormat::YUV420P,
         hardware_acceleration: true,
+        preset: None,
+        profile: None,
+        gop_size: None,
     };
 
-    VideoEncoder::new(config)
+    VideoEncoder::new(config, DEFAULT_SEGMENT_DURATION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 30fps with 2-second segments
+    fn clock() -> FrameClock {
+        FrameClock::new(3000, 2 * VIDEO_CLOCK_RATE)
+    }
+
+    #[test]
+    fn test_pts_monotonic_and_pause_skipped() {
+        let mut clock = clock();
+        assert_eq!(clock.next_pts(1_000), 0);
+        assert_eq!(clock.next_pts(1_033), 2970);
+        // A repeated or out-of-order capture timestamp still advances
+        assert_eq!(clock.next_pts(1_033), 2971);
+        assert_eq!(clock.next_pts(1_020), 2972);
+
+        // After a 10 second pause the next frame follows the last one, to the millisecond
+        clock.resuming = true;
+        assert_eq!(clock.next_pts(11_066), 5940);
+        assert_eq!(clock.next_pts(11_099), 5940 + 2970);
+    }
+
+    #[test]
+    fn test_keyframes_on_segment_boundaries() {
+        let mut clock = clock();
+        let mut starts = Vec::new();
+        for frame in 0..200u64 {
+            let pts = clock.next_pts(frame * 1000 / 30);
+            if clock.starts_segment(pts) {
+                starts.push(pts);
+            }
+        }
+
+        assert_eq!(starts, vec![0, 180_000, 360_000, 540_000]);
+    }
 }
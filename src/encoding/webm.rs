@@ -0,0 +1,472 @@
+//! WebM Muxer
+//!
+//! Minimal Matroska/WebM writer for VP9/AV1 video with Opus audio. Output is
+//! laid out as a live stream: the EBML header and an unknown-size Segment are
+//! followed by one Cluster per encoded segment, so chunks can be streamed or
+//! appended to a file as they are produced.
+
+use crate::error::{CaptureError, CaptureResult};
+use super::{AudioCodec, EncodedAudioSegment, EncodedVideoSegment, VideoCodec, VIDEO_CLOCK_RATE};
+
+// EBML header
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+// Segment
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+
+// Tracks
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CODEC_DELAY: u32 = 0x56AA;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+
+// Clusters
+const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+/// Timestamps are written in milliseconds
+const TIMECODE_SCALE_NS: u64 = 1_000_000;
+
+/// Opus decoders need 80ms of pre-roll after seeking
+const OPUS_SEEK_PRE_ROLL_NS: u64 = 80_000_000;
+
+/// AV1 OBU types the muxer cares about
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+/// Video track description
+#[derive(Debug, Clone)]
+pub struct WebmVideoTrack {
+    /// Video codec (VP9 or AV1)
+    pub codec: VideoCodec,
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+}
+
+/// Audio track description
+#[derive(Debug, Clone)]
+pub struct WebmAudioTrack {
+    /// Audio codec (Opus)
+    pub codec: AudioCodec,
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+    /// Number of channels
+    pub channels: u16,
+    /// `OpusHead` identification header
+    pub codec_private: Vec<u8>,
+}
+
+/// Muxed WebM data for one encoded segment
+///
+/// The first chunk starts with the EBML header and track definitions, so
+/// concatenating all chunks in order yields a playable file.
+#[derive(Debug, Clone)]
+pub struct WebmChunk {
+    /// Segment sequence number
+    pub sequence: u32,
+    /// Presentation time of the chunk in milliseconds
+    pub timestamp: u64,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Muxed bytes
+    pub data: Vec<u8>,
+}
+
+/// Block queued for a cluster
+struct Block {
+    timestamp: i64,
+    track: u64,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+/// Streaming WebM muxer
+pub struct WebmMuxer {
+    video: Option<WebmVideoTrack>,
+    audio: Option<WebmAudioTrack>,
+    header_written: bool,
+}
+
+impl WebmMuxer {
+    /// Create a muxer for the given tracks
+    pub fn new(video: Option<WebmVideoTrack>, audio: Option<WebmAudioTrack>) -> CaptureResult<Self> {
+        if let Some(track) = &video {
+            if !track.codec.is_webm_compatible() {
+                return Err(CaptureError::Config(format!("{:?} video cannot be stored in WebM", track.codec)));
+            }
+        }
+        if let Some(track) = &audio {
+            if !matches!(track.codec, AudioCodec::Opus) {
+                return Err(CaptureError::Config(format!("{:?} audio cannot be stored in WebM", track.codec)));
+            }
+        }
+        if video.is_none() && audio.is_none() {
+            return Err(CaptureError::Config("WebM output needs at least one track".to_string()));
+        }
+
+        Ok(Self { video, audio, header_written: false })
+    }
+
+    fn video_track_number(&self) -> u64 {
+        1
+    }
+
+    fn audio_track_number(&self) -> u64 {
+        if self.video.is_some() { 2 } else { 1 }
+    }
+
+    /// Mux one pair of encoded segments into a cluster
+    pub fn write_segment(
+        &mut self,
+        sequence: u32,
+        video: Option<&EncodedVideoSegment>,
+        audio: Option<&EncodedAudioSegment>,
+    ) -> CaptureResult<WebmChunk> {
+        let mut data = Vec::new();
+        if !self.header_written {
+            self.write_header(&mut data, video)?;
+            self.header_written = true;
+        }
+
+        let mut blocks = Vec::new();
+        if let (Some(track), Some(segment)) = (&self.video, video) {
+            for frame in &segment.frames {
+                let payload = &segment.data[frame.offset..frame.offset + frame.size];
+                blocks.push(Block {
+                    timestamp: frame.pts * 1000 / VIDEO_CLOCK_RATE,
+                    track: self.video_track_number(),
+                    keyframe: frame.keyframe,
+                    data: match track.codec {
+                        // Matroska stores AV1 without temporal delimiters
+                        VideoCodec::AV1 => strip_temporal_delimiters(payload),
+                        _ => payload.to_vec(),
+                    },
+                });
+            }
+        }
+        if let (Some(_), Some(segment)) = (&self.audio, audio) {
+            for frame in &segment.frames {
+                blocks.push(Block {
+                    timestamp: frame.pts * 1000 / segment.sample_rate as i64,
+                    track: self.audio_track_number(),
+                    keyframe: true,
+                    data: segment.data[frame.offset..frame.offset + frame.size].to_vec(),
+                });
+            }
+        }
+        blocks.sort_by_key(|block| block.timestamp);
+
+        let cluster_timestamp = blocks.first().map(|block| block.timestamp).unwrap_or(0);
+        let mut cluster = Vec::new();
+        write_uint(&mut cluster, TIMECODE, cluster_timestamp as u64);
+        for block in &blocks {
+            let relative = i16::try_from(block.timestamp - cluster_timestamp).map_err(|_| {
+                CaptureError::EncodingError(format!("Segment {} is too long for a single cluster", sequence))
+            })?;
+
+            let mut simple_block = Vec::with_capacity(block.data.len() + 4);
+            write_size(&mut simple_block, block.track);
+            simple_block.extend_from_slice(&relative.to_be_bytes());
+            simple_block.push(if block.keyframe { 0x80 } else { 0x00 });
+            simple_block.extend_from_slice(&block.data);
+            write_element(&mut cluster, SIMPLE_BLOCK, &simple_block);
+        }
+        write_element(&mut data, CLUSTER, &cluster);
+
+        let duration = video.map(|segment| segment.duration)
+            .into_iter()
+            .chain(audio.map(|segment| segment.duration))
+            .fold(0.0, f64::max);
+
+        Ok(WebmChunk {
+            sequence,
+            timestamp: cluster_timestamp.max(0) as u64,
+            duration,
+            data,
+        })
+    }
+
+    /// Write the EBML header, Segment start, Info and Tracks
+    fn write_header(&self, out: &mut Vec<u8>, first_video: Option<&EncodedVideoSegment>) -> CaptureResult<()> {
+        let mut ebml = Vec::new();
+        write_uint(&mut ebml, EBML_VERSION, 1);
+        write_uint(&mut ebml, EBML_READ_VERSION, 1);
+        write_uint(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        write_uint(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        write_string(&mut ebml, DOC_TYPE, "webm");
+        write_uint(&mut ebml, DOC_TYPE_VERSION, 4);
+        write_uint(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        write_element(out, EBML, &ebml);
+
+        // Live layout: the Segment size is unknown until the recording ends
+        write_id(out, SEGMENT);
+        out.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let mut info = Vec::new();
+        write_uint(&mut info, TIMECODE_SCALE, TIMECODE_SCALE_NS);
+        write_string(&mut info, MUXING_APP, "cap-electron-capture");
+        write_string(&mut info, WRITING_APP, "cap-electron-capture");
+        write_element(out, INFO, &info);
+
+        let mut tracks = Vec::new();
+        if let Some(track) = &self.video {
+            let mut entry = Vec::new();
+            write_uint(&mut entry, TRACK_NUMBER, self.video_track_number());
+            write_uint(&mut entry, TRACK_UID, self.video_track_number());
+            write_uint(&mut entry, TRACK_TYPE, TRACK_TYPE_VIDEO);
+            write_uint(&mut entry, FLAG_LACING, 0);
+            write_string(&mut entry, CODEC_ID, track.codec.matroska_codec_id());
+
+            if matches!(track.codec, VideoCodec::AV1) {
+                let codec_private = first_video
+                    .and_then(|segment| av1_codec_configuration(&segment.data))
+                    .ok_or_else(|| CaptureError::EncodingError(
+                        "AV1 stream is missing a sequence header".to_string()
+                    ))?;
+                write_element(&mut entry, CODEC_PRIVATE, &codec_private);
+            }
+
+            let mut video = Vec::new();
+            write_uint(&mut video, PIXEL_WIDTH, track.width as u64);
+            write_uint(&mut video, PIXEL_HEIGHT, track.height as u64);
+            write_element(&mut entry, VIDEO, &video);
+
+            write_element(&mut tracks, TRACK_ENTRY, &entry);
+        }
+        if let Some(track) = &self.audio {
+            let mut entry = Vec::new();
+            write_uint(&mut entry, TRACK_NUMBER, self.audio_track_number());
+            write_uint(&mut entry, TRACK_UID, self.audio_track_number());
+            write_uint(&mut entry, TRACK_TYPE, TRACK_TYPE_AUDIO);
+            write_uint(&mut entry, FLAG_LACING, 0);
+            write_string(&mut entry, CODEC_ID, track.codec.matroska_codec_id());
+            write_element(&mut entry, CODEC_PRIVATE, &track.codec_private);
+
+            // OpusHead carries the pre-skip (at 48kHz) at bytes 10..12
+            if let Some(pre_skip) = track.codec_private.get(10..12) {
+                let pre_skip = u16::from_le_bytes([pre_skip[0], pre_skip[1]]) as u64;
+                write_uint(&mut entry, CODEC_DELAY, pre_skip * 1_000_000_000 / 48000);
+            }
+            write_uint(&mut entry, SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL_NS);
+
+            let mut audio = Vec::new();
+            write_float(&mut audio, SAMPLING_FREQUENCY, track.sample_rate as f64);
+            write_uint(&mut audio, CHANNELS, track.channels as u64);
+            write_element(&mut entry, AUDIO, &audio);
+
+            write_element(&mut tracks, TRACK_ENTRY, &entry);
+        }
+        write_element(out, TRACKS, &tracks);
+
+        Ok(())
+    }
+}
+
+/// Write an element ID (IDs already include their length marker)
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(3);
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+/// Write an EBML variable-size integer using the shortest encoding
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    // All-ones values are reserved for "unknown size"
+    let length = (1..=8usize).find(|&n| size < (1u64 << (7 * n)) - 1).unwrap_or(8);
+    let value = size | (1u64 << (7 * length));
+    out.extend_from_slice(&value.to_be_bytes()[8 - length..]);
+}
+
+fn write_element(out: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(out, id);
+    write_size(out, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(7);
+    write_element(out, id, &bytes[skip..]);
+}
+
+fn write_float(out: &mut Vec<u8>, id: u32, value: f64) {
+    write_element(out, id, &value.to_be_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, id: u32, value: &str) {
+    write_element(out, id, value.as_bytes());
+}
+
+/// A single AV1 OBU within a temporal unit
+struct Obu<'a> {
+    obu_type: u8,
+    /// Whole OBU including its header
+    bytes: &'a [u8],
+    /// OBU payload
+    payload: &'a [u8],
+}
+
+/// Split a low-overhead AV1 bitstream into OBUs
+fn parse_obus(data: &[u8]) -> Vec<Obu<'_>> {
+    let mut obus = Vec::new();
+    let mut position = 0;
+
+    while position < data.len() {
+        let header = data[position];
+        let obu_type = (header >> 3) & 0x0F;
+        let has_extension = (header >> 2) & 1 == 1;
+        let has_size = (header >> 1) & 1 == 1;
+
+        let mut payload_start = position + 1 + has_extension as usize;
+        let end = if has_size {
+            let Some((size, length)) = data.get(payload_start..).and_then(read_leb128) else {
+                break;
+            };
+            payload_start += length;
+            payload_start + size as usize
+        } else {
+            data.len()
+        };
+        if end > data.len() || payload_start > end {
+            break;
+        }
+
+        obus.push(Obu {
+            obu_type,
+            bytes: &data[position..end],
+            payload: &data[payload_start..end],
+        });
+        position = end;
+    }
+
+    obus
+}
+
+/// Read an unsigned LEB128 value, returning it and its encoded length
+fn read_leb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (index * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+    None
+}
+
+/// Remove temporal delimiter OBUs, which Matroska blocks must not contain
+pub(super) fn strip_temporal_delimiters(data: &[u8]) -> Vec<u8> {
+    parse_obus(data)
+        .into_iter()
+        .filter(|obu| obu.obu_type != OBU_TEMPORAL_DELIMITER)
+        .flat_map(|obu| obu.bytes.iter().copied())
+        .collect()
+}
+
+/// Build the `av1C` CodecPrivate from the first sequence header OBU
+pub(super) fn av1_codec_configuration(data: &[u8]) -> Option<Vec<u8>> {
+    let obus = parse_obus(data);
+    let sequence_header = obus.iter().find(|obu| obu.obu_type == OBU_SEQUENCE_HEADER)?;
+    let mut bits = BitReader::new(sequence_header.payload);
+
+    let seq_profile = bits.read(3)? as u8;
+    let _still_picture = bits.read(1)?;
+    let reduced_still_picture_header = bits.read(1)? == 1;
+
+    let (seq_level_idx, seq_tier) = if reduced_still_picture_header {
+        (bits.read(5)? as u8, 0)
+    } else if bits.read(1)? == 1 {
+        // Timing info shifts the operating points; 31 means "unconstrained"
+        (31, 0)
+    } else {
+        let _initial_display_delay_present = bits.read(1)?;
+        let _operating_points_cnt_minus_1 = bits.read(5)?;
+        let _operating_point_idc = bits.read(12)?;
+        let level = bits.read(5)? as u8;
+        let tier = if level > 7 { bits.read(1)? as u8 } else { 0 };
+        (level, tier)
+    };
+
+    let mut configuration = vec![
+        0x81, // Marker and version 1
+        (seq_profile << 5) | seq_level_idx,
+        // 8-bit 4:2:0, which is all the encoder produces
+        (seq_tier << 7) | 0x0C,
+        0x00, // No initial presentation delay
+    ];
+    configuration.extend_from_slice(sequence_header.bytes);
+    Some(configuration)
+}
+
+/// MSB-first bit reader for AV1 headers
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            let byte = *self.data.get(self.position / 8)?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_encoding() {
+        let mut out = Vec::new();
+        write_size(&mut out, 1);
+        write_size(&mut out, 126);
+        write_size(&mut out, 127);
+        assert_eq!(out, vec![0x81, 0xFE, 0x40, 0x7F]);
+    }
+
+    #[test]
+    fn test_strip_temporal_delimiters() {
+        // Temporal delimiter (type 2, size 0) followed by a padding OBU (type 15) with two bytes
+        let data = [0x12, 0x00, 0x7A, 0x02, 0xAA, 0xBB];
+        assert_eq!(strip_temporal_delimiters(&data), vec![0x7A, 0x02, 0xAA, 0xBB]);
+    }
+}
@@ -53,6 +53,10 @@ pub enum CaptureError {
     /// Upload errors (S3, etc.)
     #[error("Upload error: {0}")]
     Upload(String),
+
+    /// Uploads the destination refused, e.g. with a 4xx response; retrying cannot help
+    #[error("Upload rejected: {0}")]
+    UploadRejected(String),
     
     /// Invalid state errors
     #[error("Invalid state: {0}")]
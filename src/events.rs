@@ -0,0 +1,144 @@
+//! Pipeline Events
+//!
+//! Push-based notifications emitted by the recording pipeline's processing
+//! tasks. Listeners are plain Rust closures so the pipeline stays independent
+//! of the napi layer, which bridges them to JavaScript callbacks.
+
+use crate::{
+    audio::AudioSegment,
+    encoding::{EncodedAudioSegment, EncodedVideoSegment, HLSSegment, WebmChunk},
+    recording::RecordingStatus,
+};
+use std::sync::{Arc, Mutex};
+
+/// Event produced while a recording session is running
+#[derive(Debug, Clone)]
+pub enum PipelineEvent {
+    /// Raw captured audio, before encoding
+    AudioSegment(AudioSegment),
+    /// Segment produced by the audio encoder
+    EncodedAudio(EncodedAudioSegment),
+    /// Video segment produced by the video encoder
+    EncodedVideo(EncodedVideoSegment),
+    /// HLS segment assembled from encoded audio and video
+    HlsSegment(HLSSegment),
+    /// WebM cluster muxed from encoded audio and video
+    WebmChunk(WebmChunk),
+    /// Result of uploading a segment or playlist
+    Upload(UploadResult),
+    /// Recording status transition
+    StatusChange(RecordingStatus),
+    /// Non-fatal error raised by a processing task
+    Error(String),
+}
+
+impl PipelineEvent {
+    /// Whether this event carries segment data
+    pub fn is_segment(&self) -> bool {
+        matches!(
+            self,
+            PipelineEvent::AudioSegment(_)
+                | PipelineEvent::EncodedAudio(_)
+                | PipelineEvent::EncodedVideo(_)
+                | PipelineEvent::HlsSegment(_)
+                | PipelineEvent::WebmChunk(_)
+        )
+    }
+}
+
+/// Outcome of a single upload
+#[derive(Debug, Clone)]
+pub struct UploadResult {
+    /// Object key the data was uploaded to
+    pub key: String,
+    /// MIME type of the uploaded data
+    pub content_type: String,
+    /// Number of bytes uploaded
+    pub bytes: u64,
+    /// Whether the upload succeeded
+    pub success: bool,
+    /// Error message if the upload failed
+    pub error: Option<String>,
+}
+
+/// Callback invoked for every emitted event
+pub type EventListener = Arc<dyn Fn(&PipelineEvent) + Send + Sync>;
+
+/// Fan-out of pipeline events to registered listeners
+#[derive(Clone, Default)]
+pub struct EventEmitter {
+    listeners: Arc<Mutex<Vec<(u32, EventListener)>>>,
+    next_id: Arc<Mutex<u32>>,
+}
+
+impl EventEmitter {
+    /// Create an emitter with no listeners
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a listener, returning an ID that can be used to remove it
+    pub fn subscribe(&self, listener: EventListener) -> u32 {
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = *next_id;
+        *next_id += 1;
+
+        self.listeners.lock().unwrap().push((id, listener));
+        id
+    }
+
+    /// Remove a listener, returning whether it was registered
+    pub fn unsubscribe(&self, id: u32) -> bool {
+        let mut listeners = self.listeners.lock().unwrap();
+        let before = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        listeners.len() != before
+    }
+
+    /// Remove all listeners, e.g. when the session is destroyed
+    pub fn clear(&self) {
+        self.listeners.lock().unwrap().clear();
+    }
+
+    /// Whether any listeners are registered
+    pub fn has_listeners(&self) -> bool {
+        !self.listeners.lock().unwrap().is_empty()
+    }
+
+    /// Deliver an event to all listeners
+    pub fn emit(&self, event: PipelineEvent) {
+        // Clone the list so listeners can (un)subscribe without deadlocking
+        let listeners: Vec<EventListener> = self.listeners.lock().unwrap()
+            .iter()
+            .map(|(_, listener)| listener.clone())
+            .collect();
+
+        for listener in listeners {
+            listener(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let emitter = EventEmitter::new();
+        let count = Arc::new(AtomicU32::new(0));
+
+        let counter = count.clone();
+        let id = emitter.subscribe(Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        emitter.emit(PipelineEvent::Error("first".to_string()));
+        assert!(emitter.unsubscribe(id));
+        emitter.emit(PipelineEvent::Error("second".to_string()));
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(!emitter.has_listeners());
+    }
+}
@@ -5,8 +5,14 @@
 //! functionality from Cap's screen recording pipeline for use in transcription
 //! and real-time audio processing applications.
 
+use napi::bindgen_prelude::{Buffer, Promise};
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, JsFunction, JsObject};
 use napi_derive::napi;
 use serde_json;
+use std::sync::Arc;
 
 pub mod audio;
 pub mod screen;
@@ -16,17 +22,24 @@ pub mod platform;
 pub mod permissions;
 pub mod encoding;
 pub mod recording;
+pub mod session;
+pub mod events;
 pub mod screencapturekit; // 🎯 Real ScreenCaptureKit integration
 
 // Re-export main types
 pub use audio::{AudioProcessor, AudioSegment};
 pub use screen::{ScreenCapture};
-pub use recording::{CapRecordingPipeline, RecordingConfig, RecordingSession};
-pub use encoding::{AudioEncoder, VideoEncoder, HLSSegmenter, S3Uploader};
+pub use recording::{CapRecordingPipeline, RecordingConfig, RecordingSession, RecordingStats, RecordingStatus};
+pub use session::{SessionManager, session_manager};
+pub use events::{EventEmitter, PipelineEvent, UploadResult};
+pub use encoding::{AudioEncoder, VideoEncoder, HLSSegmenter, S3Uploader, PresignRequest, PresignedRequest};
 pub use config::{CaptureConfig, OutputFormat, AudioCaptureConfig, ScreenCaptureConfig};
 pub use error::{CaptureError, CaptureResult};
 
 /// Initialize the library and check platform capabilities
+///
+/// Returns the capabilities as a JSON string; prefer `getPlatformCapabilities`
+/// for a typed result.
 #[napi]
 pub fn init() -> napi::Result<String> {
     env_logger::init();
@@ -37,6 +50,12 @@ pub fn init() -> napi::Result<String> {
         .map_err(|e| napi::Error::from_reason(format!("Failed to serialize capabilities: {}", e)))?)
 }
 
+/// Get typed platform capabilities
+#[napi(js_name = "getPlatformCapabilities")]
+pub fn get_platform_capabilities() -> platform::PlatformCapabilities {
+    platform::get_platform_capabilities()
+}
+
 /// Get information about available audio devices
 #[napi]
 pub fn get_audio_devices() -> napi::Result<String> {
@@ -273,21 +292,195 @@ pub fn test_native_system_audio() -> napi::Result<String> {
     }
 }
 
+/// Recording pipeline exposed to JavaScript as a typed class
+///
+/// Instances are registered with the session manager, so `sessionId` can also
+/// be used with the session functions and `on*` event subscriptions. The
+/// session is destroyed when the object is garbage collected without `destroy()`.
+#[napi(js_name = "CapRecordingPipeline")]
+pub struct JsRecordingPipeline {
+    session_id: String,
+    pipeline: session::SharedPipeline,
+}
+
+#[napi]
+impl JsRecordingPipeline {
+    /// Create a pipeline for the given configuration
+    #[napi(constructor)]
+    pub fn new(config: RecordingConfig) -> napi::Result<Self> {
+        let pipeline = recording::CapRecordingPipeline::new(config)
+            .map_err(|e| napi::Error::from_reason(format!("Failed to create pipeline: {}", e)))?;
+
+        let session_id = session_manager().register(pipeline);
+        let pipeline = session_manager().get(&session_id)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+
+        Ok(Self { session_id, pipeline })
+    }
+
+    /// Unique session ID of this pipeline
+    #[napi(getter)]
+    pub fn session_id(&self) -> String {
+        self.session_id.clone()
+    }
+
+    /// Initialize capture, encoders and uploaders
+    #[napi]
+    pub async fn initialize(&self) -> napi::Result<()> {
+        self.pipeline.lock().await.initialize().await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to initialize pipeline: {}", e)))
+    }
+
+    /// Start recording
+    #[napi]
+    pub async fn start(&self) -> napi::Result<RecordingSession> {
+        self.pipeline.lock().await.start_recording().await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Stop recording and finalize segments
+    #[napi]
+    pub async fn stop(&self) -> napi::Result<RecordingSession> {
+        self.pipeline.lock().await.stop_recording().await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Pause recording; nothing is encoded until it resumes
+    #[napi]
+    pub async fn pause(&self) -> napi::Result<RecordingSession> {
+        self.pipeline.lock().await.pause_recording()
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Resume a paused recording, starting a new discontinuity in the playlists
+    #[napi]
+    pub async fn resume(&self) -> napi::Result<RecordingSession> {
+        self.pipeline.lock().await.resume_recording()
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Current recording status
+    #[napi]
+    pub async fn status(&self) -> RecordingStatus {
+        self.pipeline.lock().await.get_status()
+    }
+
+    /// Current recording statistics
+    #[napi]
+    pub async fn stats(&self) -> RecordingStats {
+        self.pipeline.lock().await.get_session().stats
+    }
+
+    /// Snapshot of the full session state
+    #[napi]
+    pub async fn session(&self) -> RecordingSession {
+        self.pipeline.lock().await.get_session()
+    }
+
+    /// Stop (if recording) and release the session
+    #[napi]
+    pub async fn destroy(&self) -> napi::Result<()> {
+        session_manager().destroy_session(&self.session_id).await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Upload a finished file, such as the WebM recording, to the pipeline's S3 bucket
+    ///
+    /// Uses S3 multipart upload; calling it again after an interruption resumes
+    /// the upload of the same file and key.
+    #[napi]
+    pub async fn upload_file(&self, path: String, key: String, content_type: Option<String>) -> napi::Result<String> {
+        // The pipeline is only locked while the uploader is created, not for the upload
+        let uploader = self.pipeline.lock().await.file_uploader().await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_string());
+
+        uploader.upload_file_multipart(std::path::Path::new(&path), &key, &content_type).await
+            .map_err(|e| napi::Error::from_reason(format!("Failed to upload {}: {}", path, e)))
+    }
+
+    /// Upload to URLs presigned by `provider`, so no AWS credentials are needed
+    ///
+    /// `id` names the sink's spool directory and should stay the same across restarts.
+    #[napi(
+        ts_args_type = "provider: (request: PresignRequest) => Promise<PresignedRequest>, id?: string",
+        ts_return_type = "Promise<void>"
+    )]
+    pub fn add_presigned_sink(&self, env: Env, provider: JsFunction, id: Option<String>) -> napi::Result<JsObject> {
+        let mut callback: ThreadsafeFunction<PresignRequest, ErrorStrategy::Fatal> = provider
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<PresignRequest>| Ok(vec![ctx.value]))?;
+        // Don't keep Node alive for the sink; it is released when the session is destroyed
+        callback.unref(&env)?;
+        let pipeline = self.pipeline.clone();
+
+        env.execute_tokio_future(
+            async move {
+                let mut pipeline = pipeline.lock().await;
+                let sink = encoding::PresignedPutSink::new(
+                    Arc::new(JsPresignProvider { callback }),
+                    id.unwrap_or_else(|| "js".to_string()),
+                    std::time::Duration::from_secs(10),
+                    pipeline.get_config().user_id.clone(),
+                    pipeline.get_session_id().to_string(),
+                ).map_err(|e| napi::Error::from_reason(e.to_string()))?;
+                pipeline.add_sink(Arc::new(sink))
+                    .map_err(|e| napi::Error::from_reason(e.to_string()))
+            },
+            |env, ()| env.get_undefined(),
+        )
+    }
+}
+
+impl Drop for JsRecordingPipeline {
+    fn drop(&mut self) {
+        // Already released through `destroy()` or `destroyRecording`
+        if session_manager().get(&self.session_id).is_err() {
+            return;
+        }
+
+        let session_id = std::mem::take(&mut self.session_id);
+        napi::bindgen_prelude::spawn(async move {
+            if let Err(e) = session_manager().destroy_session(&session_id).await {
+                log::warn!("Failed to destroy collected recording session {}: {}", session_id, e);
+            }
+        });
+    }
+}
+
+/// Presign provider backed by an async JavaScript function
+struct JsPresignProvider {
+    callback: ThreadsafeFunction<PresignRequest, ErrorStrategy::Fatal>,
+}
+
+impl encoding::PresignProvider for JsPresignProvider {
+    fn presign(&self, request: PresignRequest) -> encoding::SinkFuture<'_, PresignedRequest> {
+        Box::pin(async move {
+            let key = request.key.clone();
+            let failed = |e: napi::Error| CaptureError::Upload(format!("Presign callback for {} failed: {}", key, e));
+
+            let promise = self.callback.call_async::<Promise<PresignedRequest>>(request).await.map_err(failed)?;
+            promise.await.map_err(failed)
+        })
+    }
+}
+
 /// Create a new recording pipeline with Cap's architecture
+///
+/// Takes the configuration as a JSON string; prefer the `CapRecordingPipeline`
+/// class for compile-time checked configuration.
 #[napi(js_name = "createRecordingPipeline")]
 pub async fn create_recording_pipeline(config: String) -> napi::Result<String> {
     let recording_config: recording::RecordingConfig = serde_json::from_str(&config)
         .map_err(|e| napi::Error::from_reason(format!("Invalid recording config: {}", e)))?;
     
-    let mut pipeline = recording::CapRecordingPipeline::new(recording_config)
+    let enable_streaming = recording_config.enable_streaming;
+    let enable_transcription = recording_config.enable_transcription;
+
+    let session_id = session_manager().create_session(recording_config).await
         .map_err(|e| napi::Error::from_reason(format!("Failed to create pipeline: {}", e)))?;
     
-    pipeline.initialize().await
-        .map_err(|e| napi::Error::from_reason(format!("Failed to initialize pipeline: {}", e)))?;
-    
-    // Store pipeline instance (in production, use a session manager)
     let session_info = serde_json::json!({
-        "session_id": pipeline.get_session_id(),
+        "session_id": session_id,
         "status": "initialized",
         "capabilities": {
             "encoding": {
@@ -295,8 +488,8 @@ pub async fn create_recording_pipeline(config: String) -> napi::Result<String> {
                 "video": "H.264",
                 "hls": true
             },
-            "streaming": pipeline.get_config().enable_streaming,
-            "transcription": pipeline.get_config().enable_transcription
+            "streaming": enable_streaming,
+            "transcription": enable_transcription
         }
     });
     
@@ -307,30 +500,8 @@ pub async fn create_recording_pipeline(config: String) -> napi::Result<String> {
 /// Start recording with the specified session
 #[napi(js_name = "startRecording")]
 pub async fn start_recording(session_id: String) -> napi::Result<String> {
-    // In production, retrieve pipeline from session manager
-    // For now, return mock data demonstrating the structure
-    let session = serde_json::json!({
-        "id": session_id,
-        "user_id": "user_123",
-        "start_time": std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64,
-        "status": "recording",
-        "stream_urls": {
-            "master": format!("https://s3.amazonaws.com/cap-recordings/user_123/{}/stream.m3u8", session_id),
-            "video": format!("https://s3.amazonaws.com/cap-recordings/user_123/{}/video/stream.m3u8", session_id),
-            "audio": format!("https://s3.amazonaws.com/cap-recordings/user_123/{}/audio/stream.m3u8", session_id),
-            "combined": format!("https://s3.amazonaws.com/cap-recordings/user_123/{}/combined-source/stream.m3u8", session_id)
-        },
-        "stats": {
-            "duration": 0.0,
-            "video_frames": 0,
-            "audio_segments": 0,
-            "bytes_uploaded": 0,
-            "avg_fps": 0.0
-        }
-    });
+    let session = session_manager().start_session(&session_id).await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?;
     
     Ok(serde_json::to_string(&session)
         .map_err(|e| napi::Error::from_reason(format!("Failed to serialize session: {}", e)))?)
@@ -339,33 +510,177 @@ pub async fn start_recording(session_id: String) -> napi::Result<String> {
 /// Stop recording and finalize segments
 #[napi(js_name = "stopRecording")]
 pub async fn stop_recording(session_id: String) -> napi::Result<String> {
-    // In production, retrieve pipeline from session manager and stop
-    let session = serde_json::json!({
-        "id": session_id,
-        "status": "stopped",
-        "final_stats": {
-            "total_duration": 120.5,
-            "total_segments": 60,
-            "total_bytes": 1024000,
-            "avg_fps": 29.8
-        },
-        "files": {
-            "master_playlist": format!("https://s3.amazonaws.com/cap-recordings/user_123/{}/stream.m3u8", session_id),
-            "final_video": format!("https://s3.amazonaws.com/cap-recordings/user_123/{}/output/video_recording_000.m3u8", session_id)
-        }
-    });
+    let session = session_manager().stop_session(&session_id).await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?;
     
     Ok(serde_json::to_string(&session)
         .map_err(|e| napi::Error::from_reason(format!("Failed to serialize session: {}", e)))?)
 }
 
+/// Get the current status and statistics of a recording session
+#[napi(js_name = "getRecordingSession")]
+pub async fn get_recording_session(session_id: String) -> napi::Result<String> {
+    let session = session_manager().get_session(&session_id).await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+    
+    Ok(serde_json::to_string(&session)
+        .map_err(|e| napi::Error::from_reason(format!("Failed to serialize session: {}", e)))?)
+}
+
+/// Stop (if recording) and release a recording session
+#[napi(js_name = "destroyRecording")]
+pub async fn destroy_recording(session_id: String) -> napi::Result<()> {
+    session_manager().destroy_session(&session_id).await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+}
+
+/// List IDs of all active recording sessions
+#[napi(js_name = "listRecordingSessions")]
+pub fn list_recording_sessions() -> Vec<String> {
+    session_manager().session_ids()
+}
+
+/// Subscribe to audio, encoded and HLS segments produced by a recording session
+///
+/// Returns a listener ID that can be passed to `removeListener`.
+#[napi(js_name = "onSegment")]
+pub fn on_segment(env: Env, session_id: String, callback: JsFunction) -> napi::Result<u32> {
+    subscribe_events(&env, &session_id, callback, PipelineEvent::is_segment)
+}
+
+/// Subscribe to upload results of a recording session
+#[napi(js_name = "onUpload")]
+pub fn on_upload(env: Env, session_id: String, callback: JsFunction) -> napi::Result<u32> {
+    subscribe_events(&env, &session_id, callback, |event| matches!(event, PipelineEvent::Upload(_)))
+}
+
+/// Subscribe to non-fatal errors raised while a recording session runs
+#[napi(js_name = "onError")]
+pub fn on_error(env: Env, session_id: String, callback: JsFunction) -> napi::Result<u32> {
+    subscribe_events(&env, &session_id, callback, |event| matches!(event, PipelineEvent::Error(_)))
+}
+
+/// Subscribe to status changes of a recording session
+#[napi(js_name = "onStatusChange")]
+pub fn on_status_change(env: Env, session_id: String, callback: JsFunction) -> napi::Result<u32> {
+    subscribe_events(&env, &session_id, callback, |event| matches!(event, PipelineEvent::StatusChange(_)))
+}
+
+/// Remove a listener registered with one of the `on*` functions
+#[napi(js_name = "removeListener")]
+pub fn remove_listener(session_id: String, listener_id: u32) -> napi::Result<bool> {
+    let events = session_manager().events(&session_id)
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+    Ok(events.unsubscribe(listener_id))
+}
+
+/// Bridge matching pipeline events to a JavaScript callback
+fn subscribe_events(
+    env: &Env,
+    session_id: &str,
+    callback: JsFunction,
+    filter: fn(&PipelineEvent) -> bool,
+) -> napi::Result<u32> {
+    let events = session_manager().events(session_id)
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+
+    let mut tsfn: ThreadsafeFunction<PipelineEvent, ErrorStrategy::Fatal> = callback
+        .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<PipelineEvent>| {
+            pipeline_event_to_js(&ctx.env, ctx.value).map(|event| vec![event])
+        })?;
+    // A listener must not keep Node alive; dropping it (removeListener or destroy) releases it
+    tsfn.unref(env)?;
+
+    Ok(events.subscribe(Arc::new(move |event: &PipelineEvent| {
+        if filter(event) {
+            tsfn.call(event.clone(), ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    })))
+}
+
+/// Convert a pipeline event into a plain JS object, passing binary payloads as `Buffer`
+fn pipeline_event_to_js(env: &Env, event: PipelineEvent) -> napi::Result<JsObject> {
+    let mut object = env.create_object()?;
+
+    match event {
+        PipelineEvent::AudioSegment(segment) => {
+            object.set_named_property("type", "audio")?;
+            object.set_named_property("timestamp", segment.timestamp as i64)?;
+            object.set_named_property("durationMs", segment.duration_ms)?;
+            object.set_named_property("sampleRate", segment.sample_rate)?;
+            object.set_named_property("channels", segment.channels as u32)?;
+            object.set_named_property("source", format!("{:?}", segment.source))?;
+            // Interleaved little-endian f32 PCM, matching processAudioChunk's input
+            let pcm: Vec<u8> = segment.data.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+            object.set_named_property("data", Buffer::from(pcm))?;
+        }
+        PipelineEvent::EncodedAudio(segment) => {
+            object.set_named_property("type", "encodedAudio")?;
+            object.set_named_property("sequence", segment.sequence)?;
+            object.set_named_property("duration", segment.duration)?;
+            object.set_named_property("timestamp", segment.timestamp as i64)?;
+            object.set_named_property("sampleRate", segment.sample_rate)?;
+            object.set_named_property("channels", segment.channels as u32)?;
+            object.set_named_property("data", Buffer::from(segment.data))?;
+        }
+        PipelineEvent::EncodedVideo(segment) => {
+            object.set_named_property("type", "encodedVideo")?;
+            object.set_named_property("codec", segment.codec.codecs_attribute())?;
+            object.set_named_property("sequence", segment.sequence)?;
+            object.set_named_property("duration", segment.duration)?;
+            object.set_named_property("timestamp", segment.timestamp as i64)?;
+            object.set_named_property("frameCount", segment.frame_count)?;
+            object.set_named_property("pts", segment.pts)?;
+            object.set_named_property("width", segment.resolution.0)?;
+            object.set_named_property("height", segment.resolution.1)?;
+            object.set_named_property("data", Buffer::from(segment.data))?;
+        }
+        PipelineEvent::HlsSegment(segment) => {
+            object.set_named_property("type", "hls")?;
+            object.set_named_property("sequenceNumber", segment.sequence_number)?;
+            object.set_named_property("duration", segment.duration)?;
+            object.set_named_property("videoUrl", segment.video_url)?;
+            object.set_named_property("audioUrl", segment.audio_url)?;
+            object.set_named_property("combinedUrl", segment.combined_url)?;
+            object.set_named_property("timestamp", segment.timestamp as i64)?;
+            object.set_named_property("videoSize", segment.video_size as i64)?;
+            object.set_named_property("audioSize", segment.audio_size as i64)?;
+        }
+        PipelineEvent::WebmChunk(chunk) => {
+            object.set_named_property("type", "webm")?;
+            object.set_named_property("sequence", chunk.sequence)?;
+            object.set_named_property("duration", chunk.duration)?;
+            object.set_named_property("timestamp", chunk.timestamp as i64)?;
+            object.set_named_property("data", Buffer::from(chunk.data))?;
+        }
+        PipelineEvent::Upload(result) => {
+            object.set_named_property("type", "upload")?;
+            object.set_named_property("key", result.key)?;
+            object.set_named_property("contentType", result.content_type)?;
+            object.set_named_property("bytes", result.bytes as i64)?;
+            object.set_named_property("success", result.success)?;
+            object.set_named_property("error", result.error)?;
+        }
+        PipelineEvent::StatusChange(status) => {
+            object.set_named_property("type", "status")?;
+            object.set_named_property("status", format!("{:?}", status))?;
+        }
+        PipelineEvent::Error(message) => {
+            object.set_named_property("type", "error")?;
+            object.set_named_property("message", message)?;
+        }
+    }
+
+    Ok(object)
+}
+
 /// Get encoding capabilities and configuration options
 #[napi(js_name = "getEncodingCapabilities")]
 pub fn get_encoding_capabilities() -> napi::Result<String> {
     let capabilities = serde_json::json!({
-        "audio_codecs": ["AAC"],
-        "video_codecs": ["H.264", "H.265"],
-        "container_formats": ["HLS", "MP4"],
+        "audio_codecs": ["AAC", "Opus"],
+        "video_codecs": ["H.264", "H.265", "VP9", "AV1"],
+        "container_formats": ["HLS", "MP4", "CMAF", "WebM"],
         "streaming": {
             "hls": true,
             "segment_duration": 2.0,
@@ -396,8 +711,16 @@ pub fn get_encoding_capabilities() -> napi::Result<String> {
 }
 
 /// Process audio chunk and return encoded segments
+///
+/// Chunks are accumulated by a per-session streaming encoder, so segments are
+/// only returned once enough audio has been pushed to complete one.
 #[napi]
-pub async fn process_audio_chunk(session_id: String, pcm_data: Vec<u8>) -> napi::Result<String> {
+pub async fn process_audio_chunk(
+    session_id: String,
+    pcm_data: Vec<u8>,
+    sample_rate: Option<u32>,
+    channels: Option<u32>,
+) -> napi::Result<String> {
     log::debug!("Processing audio chunk for session {}: {} bytes", session_id, pcm_data.len());
     
     // Convert bytes to f32 samples (assuming little-endian 32-bit floats)
@@ -413,19 +736,20 @@ pub async fn process_audio_chunk(session_id: String, pcm_data: Vec<u8>) -> napi:
         samples.push(sample);
     }
     
-    // Create a temporary audio encoder for this test
-    let audio_config = encoding::AudioEncodingConfig {
-        codec: encoding::AudioCodec::AAC,
-        bitrate: 128000,
-        sample_rate: 48000,
-        channels: 2,
-        channel_layout: encoding::AudioChannelLayout::Stereo,
+    let channels = channels
+        .map(u16::try_from)
+        .transpose()
+        .map_err(|_| napi::Error::from_reason("Invalid channel count".to_string()))?;
+    
+    // Encoding is CPU-bound and waits for the session's encoder, so it runs off the async workers
+    let encoded = {
+        let session_id = session_id.clone();
+        tokio::task::spawn_blocking(move || {
+            session_manager().process_audio_chunk(&session_id, &samples, sample_rate, channels)
+        }).await
     };
-    
-    let mut encoder = encoding::AudioEncoder::new(audio_config)
-        .map_err(|e| napi::Error::from_reason(format!("Failed to create encoder: {}", e)))?;
-    
-    let segments = encoder.process_audio(&samples)
+    let segments = encoded
+        .map_err(|e| napi::Error::from_reason(format!("Audio encoding task failed: {}", e)))?
         .map_err(|e| napi::Error::from_reason(format!("Failed to process audio: {}", e)))?;
     
     let result = serde_json::json!({
@@ -449,24 +773,17 @@ pub async fn process_audio_chunk(session_id: String, pcm_data: Vec<u8>) -> napi:
     Ok(result.to_string())
 }
 
-/// Flush encoder and return any remaining segments
+/// Flush the session's streaming encoder and return any remaining segments
 #[napi]
 pub async fn flush_encoder(session_id: String) -> napi::Result<String> {
     log::debug!("Flushing encoder for session {}", session_id);
     
-    // Create a temporary audio encoder for this test
-    let audio_config = encoding::AudioEncodingConfig {
-        codec: encoding::AudioCodec::AAC,
-        bitrate: 128000,
-        sample_rate: 48000,
-        channels: 2,
-        channel_layout: encoding::AudioChannelLayout::Stereo,
+    let flushed = {
+        let session_id = session_id.clone();
+        tokio::task::spawn_blocking(move || session_manager().flush_audio_encoder(&session_id)).await
     };
-    
-    let mut encoder = encoding::AudioEncoder::new(audio_config)
-        .map_err(|e| napi::Error::from_reason(format!("Failed to create encoder: {}", e)))?;
-    
-    let segments = encoder.flush()
+    let segments = flushed
+        .map_err(|e| napi::Error::from_reason(format!("Audio encoding task failed: {}", e)))?
         .map_err(|e| napi::Error::from_reason(format!("Failed to flush encoder: {}", e)))?;
     
     let result = serde_json::json!({
@@ -1,10 +1,13 @@
 use crate::CaptureResult;
+use crate::config::Resolution;
 use crate::screencapturekit::is_screencapturekit_available;
 use cpal::traits::HostTrait;
+use napi_derive::napi;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// Platform capabilities information
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlatformCapabilities {
     /// Operating system
@@ -22,7 +25,8 @@ pub struct PlatformCapabilities {
 }
 
 /// Platform types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Platform {
     MacOS,
     Windows,
@@ -42,6 +46,7 @@ impl fmt::Display for Platform {
 }
 
 /// Audio capabilities
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioCapabilities {
     /// System audio capture supported
@@ -53,27 +58,29 @@ pub struct AudioCapabilities {
     /// Supported audio formats
     pub formats: Vec<String>,
     /// Number of available input devices
-    pub input_devices: usize,
+    pub input_devices: u32,
     /// Number of available output devices
-    pub output_devices: usize,
+    pub output_devices: u32,
 }
 
 /// Screen capture capabilities
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScreenCapabilities {
     /// Screen capture supported
     pub supported: bool,
     /// Number of available displays
-    pub display_count: usize,
+    pub display_count: u32,
     /// Window capture supported
     pub window_capture: bool,
     /// Maximum resolution supported
-    pub max_resolution: Option<(u32, u32)>,
+    pub max_resolution: Option<Resolution>,
     /// Supported frame rates
     pub frame_rates: Vec<u32>,
 }
 
 /// Permission status
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PermissionStatus {
     /// Microphone permission
@@ -85,7 +92,8 @@ pub struct PermissionStatus {
 }
 
 /// Permission state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Permission {
     Granted,
     Denied,
@@ -128,11 +136,11 @@ fn get_audio_capabilities() -> AudioCapabilities {
     let host = cpal::default_host();
     
     let input_devices = host.input_devices()
-        .map(|devices| devices.count())
+        .map(|devices| devices.count() as u32)
         .unwrap_or(0);
     
     let output_devices = host.output_devices()
-        .map(|devices| devices.count())
+        .map(|devices| devices.count() as u32)
         .unwrap_or(0);
     
     AudioCapabilities {
@@ -231,17 +239,17 @@ fn supports_window_capture() -> bool {
 }
 
 /// Get number of displays
-fn get_display_count() -> usize {
+fn get_display_count() -> u32 {
     // This would need platform-specific implementation
     // For now, return 1 as a default
     1
 }
 
 /// Get maximum resolution
-fn get_max_resolution() -> Option<(u32, u32)> {
+fn get_max_resolution() -> Option<Resolution> {
     // This would need platform-specific implementation
     // For now, return common 4K resolution
-    Some((3840, 2160))
+    Some(Resolution::new(3840, 2160))
 }
 
 /// Check microphone permission
@@ -7,41 +7,78 @@ use crate::{
     screen::{ScreenCapture, ScreenFrame},
     encoding::{
         AudioEncoder, VideoEncoder, HLSSegmenter, S3Uploader,
-        EncodingConfig, create_transcription_encoder, create_screen_recording_encoder,
-        create_cap_hls_segmenter, create_cap_s3_uploader,
-        PlaylistType, S3ContentType
+        EncodedAudioSegment, EncodedVideoSegment,
+        EncodingConfig, AudioCodec, WebmMuxer, WebmVideoTrack, WebmAudioTrack, TsMuxer,
+        CmafAudioMuxer, CmafVideoMuxer, CmafFragment, FileSystemSink,
+        SegmentSink, SinkConfig, UploadQueue, UploadScheduler, create_sink,
+        UploadConfig, create_cap_s3_uploader, SessionManifest,
+        HLSPart, PlaylistType, S3ContentType, StreamInfo, object_key, sequence_from_key, PART_DURATION_RANGE
     },
     error::{CaptureError, CaptureResult},
-    config::{AudioCaptureConfig, ScreenCaptureConfig},
+    config::{AudioCaptureConfig, ScreenCaptureConfig, OutputFormat, VideoFormat},
+    events::{EventEmitter, PipelineEvent, UploadResult},
 };
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use uuid::Uuid;
+use napi_derive::napi;
 use serde::{Deserialize, Serialize};
 
+/// How long stopping waits for queued uploads before leaving them to the spool
+const UPLOAD_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long stopping waits for the processing tasks to encode what was captured
+const PROCESSING_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Cap's complete real-time recording pipeline
 pub struct CapRecordingPipeline {
     /// Screen capture component
     screen_capture: Option<ScreenCapture>,
     /// Audio capture component
     audio_processor: Option<AudioProcessor>,
-    /// Video encoder (H.264)
+    /// Video encoder
     video_encoder: Option<VideoEncoder>,
-    /// Audio encoder (AAC)
+    /// Audio encoder
     audio_encoder: Option<AudioEncoder>,
-    /// HLS segmenter
-    hls_segmenter: Option<HLSSegmenter>,
-    /// S3 uploader
-    s3_uploader: Option<S3Uploader>,
+    /// HLS segmenter shared with the processing tasks
+    hls_segmenter: Option<Arc<Mutex<HLSSegmenter>>>,
+    /// Destinations for segments and playlists
+    sinks: Vec<Arc<dyn SegmentSink>>,
+    /// Sinks and upload queues used by the current session
+    destinations: SegmentDestinations,
     /// Recording configuration
     config: RecordingConfig,
     /// Recording state
     is_recording: Arc<Mutex<bool>>,
+    /// Set while paused; the processing tasks drop captured media instead of encoding it
+    paused: Arc<Mutex<bool>>,
+    /// When the current pause started, in milliseconds
+    paused_at: Option<u64>,
+    /// Length of the pauses that have ended, in milliseconds
+    paused_time: u64,
     /// Unique recording session ID
     session_id: String,
+    /// Start timestamp in milliseconds (set once recording starts)
+    start_time: Option<u64>,
+    /// Live statistics updated by the processing tasks
+    stats: Arc<Mutex<RecordingStats>>,
+    /// Encoding and segment assembly tasks, which finish once capture stops
+    task_handles: Vec<JoinHandle<()>>,
+    /// Periodic playlist update task
+    playlist_task: Option<JoinHandle<()>>,
+    /// Upload queue workers, stopped once the queues have drained
+    upload_workers: Vec<JoinHandle<()>>,
+    /// Event listeners notified by the processing tasks
+    events: EventEmitter,
 }
 
 /// Complete recording configuration
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordingConfig {
     /// Audio capture settings
@@ -58,19 +95,28 @@ pub struct RecordingConfig {
     pub enable_transcription: bool,
     /// Enable real-time streaming
     pub enable_streaming: bool,
+    /// Container output; WebM muxes VP9/AV1 video and Opus audio instead of HLS
+    #[serde(default)]
+    pub output: Option<OutputFormat>,
+    /// Additional destinations for HLS segments and playlists
+    #[serde(default)]
+    pub sinks: Option<Vec<SinkConfig>>,
 }
 
 /// Recording session information
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordingSession {
     /// Session ID
     pub id: String,
     /// User ID
     pub user_id: String,
-    /// Start timestamp
-    pub start_time: u64,
+    /// Start timestamp in milliseconds
+    pub start_time: i64,
     /// Current status
     pub status: RecordingStatus,
+    /// Error message when status is `Error`
+    pub error: Option<String>,
     /// Stream URLs (if streaming enabled)
     pub stream_urls: StreamUrls,
     /// Recording statistics
@@ -78,17 +124,19 @@ pub struct RecordingSession {
 }
 
 /// Recording status
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[napi]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum RecordingStatus {
     Initializing,
     Recording,
     Paused,
     Stopping,
     Stopped,
-    Error(String),
+    Error,
 }
 
 /// Stream URLs for different content types
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamUrls {
     /// Master playlist URL
@@ -99,9 +147,16 @@ pub struct StreamUrls {
     pub audio: Option<String>,
     /// Combined stream URL
     pub combined: Option<String>,
+    /// Master playlist of the whole recording, unless segments are deleted after the live window
+    #[serde(default)]
+    pub archive: Option<String>,
+    /// DASH MPD, with CMAF segments
+    #[serde(default)]
+    pub dash: Option<String>,
 }
 
 /// Recording statistics
+#[napi(object)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordingStats {
     /// Duration in seconds
@@ -111,9 +166,13 @@ pub struct RecordingStats {
     /// Number of audio segments processed
     pub audio_segments: u32,
     /// Total bytes uploaded
-    pub bytes_uploaded: u64,
+    pub bytes_uploaded: i64,
     /// Average encoding FPS
-    pub avg_fps: f32,
+    pub avg_fps: f64,
+    /// Uploads queued but not yet in flight
+    pub upload_queue_depth: u32,
+    /// Uploads currently in flight
+    pub uploads_in_flight: u32,
 }
 
 impl CapRecordingPipeline {
@@ -129,10 +188,20 @@ impl CapRecordingPipeline {
             video_encoder: None,
             audio_encoder: None,
             hls_segmenter: None,
-            s3_uploader: None,
+            sinks: Vec::new(),
+            destinations: SegmentDestinations::default(),
             config,
             is_recording: Arc::new(Mutex::new(false)),
+            paused: Arc::new(Mutex::new(false)),
+            paused_at: None,
+            paused_time: 0,
             session_id,
+            start_time: None,
+            stats: Arc::new(Mutex::new(RecordingStats::default())),
+            task_handles: Vec::new(),
+            playlist_task: None,
+            upload_workers: Vec::new(),
+            events: EventEmitter::new(),
         })
     }
 
@@ -141,48 +210,113 @@ impl CapRecordingPipeline {
         log::info!("Initializing recording pipeline for session {}", self.session_id);
 
         // 1. Initialize audio processor
-        self.audio_processor = Some(AudioProcessor::new(self.config.audio.clone())?);
+        if self.config.audio.enabled {
+            self.audio_processor = Some(AudioProcessor::new(self.config.audio.clone())?);
+        }
 
         // 2. Initialize screen capture
-        self.screen_capture = Some(ScreenCapture::new(self.config.screen.clone())?);
+        if self.config.screen.enabled {
+            self.screen_capture = Some(ScreenCapture::new(self.config.screen.clone())?);
+        }
 
-        // 3. Initialize encoders
-        self.audio_encoder = Some(create_transcription_encoder()?);
-        
-        if let Some(screen) = &self.screen_capture {
-            let displays = screen.get_available_displays()?;
-            if let Some(primary_display) = displays.first() {
-                let resolution = (primary_display.width, primary_display.height);
-                self.video_encoder = Some(create_screen_recording_encoder(resolution)?);
+        // 3. Check the codecs against the container
+        if self.webm_output() {
+            if self.config.screen.enabled && !self.config.encoding.video.codec.is_webm_compatible() {
+                return Err(CaptureError::Config(format!(
+                    "WebM output requires VP9 or AV1 video, got {:?}", self.config.encoding.video.codec
+                )));
+            }
+            if self.config.audio.enabled && !matches!(self.config.encoding.audio.codec, AudioCodec::Opus) {
+                return Err(CaptureError::Config(format!(
+                    "WebM output requires Opus audio, got {:?}", self.config.encoding.audio.codec
+                )));
             }
         }
 
-        // 4. Initialize HLS segmenter
-        self.hls_segmenter = Some(create_cap_hls_segmenter(
-            self.config.user_id.clone(),
-            self.session_id.clone()
-        ));
+        // LL-HLS parts are packaged as CMAF fragments
+        let hls = &self.config.encoding.hls;
+        let part_duration = hls.part_duration.filter(|_| !self.webm_output());
+        if let Some(part_duration) = part_duration {
+            if !hls.is_cmaf() {
+                return Err(CaptureError::Config("Low-Latency HLS requires CMAF segments".to_string()));
+            }
+            if !PART_DURATION_RANGE.contains(&part_duration) {
+                return Err(CaptureError::Config(format!(
+                    "Part duration {}s must be between {}s and {}s",
+                    part_duration, PART_DURATION_RANGE.start(), PART_DURATION_RANGE.end()
+                )));
+            }
+            if part_duration >= hls.segment_duration {
+                return Err(CaptureError::Config(format!(
+                    "Part duration {}s must be shorter than the {}s segments", part_duration, hls.segment_duration
+                )));
+            }
+        }
+
+        // 4. Initialize encoders and HLS segmenter
+        self.create_encoders()?;
 
         // 5. Initialize S3 uploader if streaming enabled
         if self.config.enable_streaming {
-            if let Some(bucket) = &self.config.s3_bucket {
-                let upload_config = create_cap_s3_uploader(
-                    bucket.clone(),
-                    self.config.user_id.clone(),
-                    self.session_id.clone()
-                );
-                self.s3_uploader = Some(S3Uploader::new(
+            if let Some(upload_config) = self.s3_upload_config() {
+                self.sinks.push(Arc::new(S3Uploader::new(
                     upload_config,
                     self.config.user_id.clone(),
                     self.session_id.clone()
-                ).await?);
+                ).await?));
             }
         }
 
+        // 6. Initialize the filesystem sink; WebM output writes its own file there
+        if !self.webm_output() {
+            if let Some(sink) = self.local_output() {
+                log::info!("Writing HLS output to {}", sink.root().display());
+                self.sinks.push(Arc::new(sink));
+            }
+        }
+
+        // 7. Initialize additional sinks
+        for sink_config in self.config.sinks.iter().flatten() {
+            let sink = create_sink(sink_config, &self.config.user_id, &self.session_id).await?;
+            log::info!("Added {} sink", sink.name());
+            self.sinks.push(sink);
+        }
+
         log::info!("Recording pipeline initialized successfully");
         Ok(())
     }
 
+    /// Create the encoders and the HLS segmenter; each recording consumes a set
+    fn create_encoders(&mut self) -> CaptureResult<()> {
+        let part_duration = self.config.encoding.hls.part_duration.filter(|_| !self.webm_output());
+
+        if self.audio_processor.is_some() {
+            let mut encoder = AudioEncoder::new(self.config.encoding.audio.clone(), self.config.encoding.hls.segment_duration)?;
+            encoder.set_part_duration(part_duration)?;
+            self.audio_encoder = Some(encoder);
+        }
+
+        if self.screen_capture.is_some() {
+            // Captured frames are scaled to the configured output resolution
+            let mut encoder = VideoEncoder::new(self.config.encoding.video.clone(), self.config.encoding.hls.segment_duration)?;
+            encoder.set_part_duration(part_duration);
+            self.video_encoder = Some(encoder);
+        }
+
+        let mut segmenter = HLSSegmenter::new(
+            self.config.encoding.hls.clone(),
+            self.config.user_id.clone(),
+            self.session_id.clone()
+        );
+        segmenter.set_stream_info(StreamInfo::from_config(
+            &self.config.encoding,
+            self.video_encoder.is_some(),
+            self.audio_encoder.is_some(),
+        ));
+        self.hls_segmenter = Some(Arc::new(Mutex::new(segmenter)));
+        Ok(())
+    }
+
     /// Start recording with Cap's real-time pipeline
     pub async fn start_recording(&mut self) -> CaptureResult<RecordingSession> {
         let mut is_recording = self.is_recording.lock().unwrap();
@@ -194,6 +328,25 @@ impl CapRecordingPipeline {
 
         log::info!("Starting recording session {}", self.session_id);
 
+        if let Err(e) = self.begin_recording().await {
+            log::error!("Failed to start recording session {}: {}", self.session_id, e);
+            self.abort_start().await;
+            return Err(e);
+        }
+
+        self.events.emit(PipelineEvent::StatusChange(RecordingStatus::Recording));
+
+        log::info!("Recording session started: {}", self.session_id);
+        Ok(self.get_session())
+    }
+
+    /// Start capture and the processing tasks for a new recording
+    async fn begin_recording(&mut self) -> CaptureResult<()> {
+        // A previous recording flushed and consumed the encoders
+        if self.start_time.is_some() {
+            self.create_encoders()?;
+        }
+
         // Start audio capture
         let audio_rx = if let Some(audio_processor) = &mut self.audio_processor {
             Some(audio_processor.start().await?)
@@ -208,23 +361,50 @@ impl CapRecordingPipeline {
             None
         };
 
+        self.start_time = Some(unix_millis());
+        *self.stats.lock().unwrap() = RecordingStats::default();
+        *self.paused.lock().unwrap() = false;
+        self.paused_at = None;
+        self.paused_time = 0;
+        if let (Some(segmenter), Some(start_time)) = (&self.hls_segmenter, self.start_time) {
+            segmenter.lock().unwrap().set_start_time(start_time);
+        }
+
         // Start the real-time processing pipeline
-        self.start_processing_pipeline(audio_rx, video_rx).await?;
+        self.start_processing_pipeline(audio_rx, video_rx).await
+    }
 
-        let session = RecordingSession {
-            id: self.session_id.clone(),
-            user_id: self.config.user_id.clone(),
-            start_time: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
-            status: RecordingStatus::Recording,
-            stream_urls: self.generate_stream_urls(),
-            stats: RecordingStats::default(),
-        };
+    /// Undo a partly started recording so it can be started again
+    async fn abort_start(&mut self) {
+        self.stop_capture().await;
 
-        log::info!("Recording session started: {}", self.session_id);
-        Ok(session)
+        if let Some(handle) = self.playlist_task.take() {
+            handle.abort();
+        }
+        for handle in self.task_handles.drain(..).chain(self.upload_workers.drain(..)) {
+            handle.abort();
+        }
+        self.destinations.abort_cleanup();
+        self.destinations = SegmentDestinations::default();
+
+        *self.is_recording.lock().unwrap() = false;
+    }
+
+    /// Stop audio and screen capture, reporting failures instead of returning them
+    /// so the rest of the teardown still runs
+    async fn stop_capture(&mut self) {
+        if let Some(audio_processor) = &mut self.audio_processor {
+            if let Err(e) = audio_processor.stop().await {
+                log::warn!("Failed to stop audio capture: {}", e);
+                self.events.emit(PipelineEvent::Error(format!("Failed to stop audio capture: {}", e)));
+            }
+        }
+        if let Some(screen_capture) = &mut self.screen_capture {
+            if let Err(e) = screen_capture.stop_capture().await {
+                log::warn!("Failed to stop screen capture: {}", e);
+                self.events.emit(PipelineEvent::Error(format!("Failed to stop screen capture: {}", e)));
+            }
+        }
     }
 
     /// Start the real-time processing pipeline
@@ -233,123 +413,106 @@ impl CapRecordingPipeline {
         audio_rx: Option<mpsc::UnboundedReceiver<AudioSegment>>,
         video_rx: Option<mpsc::UnboundedReceiver<ScreenFrame>>,
     ) -> CaptureResult<()> {
-        
-        // Audio processing pipeline
-        if let Some(mut audio_rx) = audio_rx {
-            let audio_encoder = self.audio_encoder.take();
-            let _hls_segmenter_audio = self.hls_segmenter.clone();
-            let s3_uploader = self.s3_uploader.clone();
-            let enable_transcription = self.config.enable_transcription;
-            let enable_streaming = self.config.enable_streaming;
-
-            tokio::spawn(async move {
-                if let Some(mut encoder) = audio_encoder {
-                    while let Some(audio_segment) = audio_rx.recv().await {
-                        // Convert audio segment to PCM samples
-                        let pcm_samples = audio_segment.data;
-                        
-                        // Encode to AAC
-                        match encoder.process_audio(&pcm_samples) {
-                            Ok(encoded_segments) => {
-                                for encoded_segment in encoded_segments {
-                                    // Create HLS segment if segmenter available
-                                    if let Some(_segmenter) = &_hls_segmenter_audio {
-                                        // In a real implementation, we'd properly synchronize this
-                                        // with the video processing pipeline
-                                    }
-
-                                    // Upload to S3 if streaming enabled
-                                    if enable_streaming {
-                                        if let Some(uploader) = &s3_uploader {
-                                            if let Err(e) = uploader.upload_audio_segment_realtime(encoded_segment).await {
-                                                log::error!("Failed to upload audio segment: {}", e);
-                                            }
-                                        }
-                                    }
-
-                                    // Process for transcription if enabled
-                                    if enable_transcription {
-                                        // In production, send to transcription service
-                                        log::debug!("Audio segment ready for transcription");
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                log::error!("Audio encoding error: {}", e);
-                            }
-                        }
+        // Encoded segments are paired by sequence number into HLS segments or WebM clusters
+        let expect_audio = audio_rx.is_some() && self.audio_encoder.is_some();
+        let expect_video = video_rx.is_some() && self.video_encoder.is_some();
+
+        let cmaf = self.config.encoding.hls.is_cmaf();
+        let low_latency = cmaf && self.config.encoding.hls.is_low_latency() && !self.webm_output();
+        let destinations = self.create_destinations().await?;
+        for queue in &destinations.queues {
+            let events = self.events.clone();
+            let stats = self.stats.clone();
+            let segmenter = self.hls_segmenter.clone();
+            let unlisted = destinations.clone();
+            self.upload_workers.push(queue.spawn(move |outcome| {
+                // A rejected segment will never be stored, so the playlists stop listing it
+                match &segmenter {
+                    Some(segmenter) if outcome.dropped && outcome.content_type.is_segment() => {
+                        unlisted.unlist_segment(&events, segmenter, &outcome.content_type, &outcome.key);
                     }
+                    _ => {}
                 }
-            });
+                report_upload(&events, &stats, outcome.key, outcome.content_type, outcome.bytes, outcome.result);
+            }));
+        }
+        self.destinations = destinations.clone();
+
+        let output = if self.webm_output() {
+            Some(self.create_webm_output().await?)
+        } else if expect_audio || expect_video {
+            self.hls_segmenter.clone().map(|segmenter| SegmentOutput::Hls {
+                segmenter,
+                muxer: TsMuxer::new(),
+                destinations: destinations.clone(),
+            })
+        } else {
+            None
+        };
+        let media_tx = output.map(|output| {
+            let (media_tx, media_rx) = mpsc::unbounded_channel();
+            self.task_handles.push(tokio::spawn(assemble_segments(
+                media_rx,
+                output,
+                expect_audio,
+                expect_video,
+                self.events.clone(),
+            )));
+            media_tx
+        });
+        
+        // Audio processing pipeline
+        if let (Some(audio_rx), Some(encoder)) = (audio_rx, self.audio_encoder.take()) {
+            let track = AudioTrack {
+                cmaf_muxer: cmaf.then(|| CmafAudioMuxer::new(encoder.opus_head())),
+                part_muxer: low_latency.then(|| CmafAudioMuxer::new(encoder.opus_head())),
+                encoder,
+                hls_segmenter: self.hls_segmenter.clone().filter(|_| low_latency),
+                destinations: destinations.clone(),
+                media_tx: media_tx.clone(),
+                enable_transcription: self.config.enable_transcription,
+                stats: self.stats.clone(),
+                events: self.events.clone(),
+            };
+            self.task_handles.push(tokio::spawn(track.run(audio_rx, self.paused.clone())));
         }
 
         // Video processing pipeline
-        if let Some(mut video_rx) = video_rx {
-            let video_encoder = self.video_encoder.take();
-            let _hls_segmenter = self.hls_segmenter.clone();
-            let s3_uploader = self.s3_uploader.clone();
-            let enable_streaming = self.config.enable_streaming;
-
-            tokio::spawn(async move {
-                if let Some(mut encoder) = video_encoder {
-                    while let Some(screen_frame) = video_rx.recv().await {
-                        // Convert ScreenFrame to raw frame data
-                        let frame_data = screen_frame.data;
-                        
-                        // Encode frame to H.264
-                        match encoder.process_frame(&frame_data) {
-                            Ok(Some(encoded_segment)) => {
-                                // Upload to S3 if streaming enabled
-                                if enable_streaming {
-                                    if let Some(uploader) = &s3_uploader {
-                                        if let Err(e) = uploader.upload_video_segment_realtime(encoded_segment).await {
-                                            log::error!("Failed to upload video segment: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                            Ok(None) => {
-                                // No complete segment yet
-                            }
-                            Err(e) => {
-                                log::error!("Video encoding error: {}", e);
-                            }
-                        }
-                    }
-                }
-            });
+        if let (Some(video_rx), Some(encoder)) = (video_rx, self.video_encoder.take()) {
+            let track = VideoTrack {
+                encoder,
+                ts_muxer: TsMuxer::new(),
+                cmaf_muxer: cmaf.then(CmafVideoMuxer::new),
+                part_muxer: low_latency.then(CmafVideoMuxer::new),
+                hls_segmenter: self.hls_segmenter.clone().filter(|_| low_latency),
+                destinations: destinations.clone(),
+                media_tx: media_tx.clone(),
+                stats: self.stats.clone(),
+                events: self.events.clone(),
+            };
+            self.task_handles.push(tokio::spawn(track.run(video_rx, self.paused.clone())));
         }
 
+        // The tracks hold the only senders, so assembly ends once both have flushed
+        drop(media_tx);
+
         // HLS playlist update pipeline
-        if self.config.enable_streaming && self.s3_uploader.is_some() {
+        if !destinations.is_empty() {
             let hls_segmenter = self.hls_segmenter.clone();
-            let s3_uploader = self.s3_uploader.clone();
+            let destinations = destinations.clone();
+            let events = self.events.clone();
 
-            tokio::spawn(async move {
+            self.playlist_task = Some(tokio::spawn(async move {
                 let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(2));
                 
                 loop {
                     interval.tick().await;
                     
-                    if let (Some(segmenter), Some(uploader)) = (&hls_segmenter, &s3_uploader) {
-                        // Generate and upload updated playlists
-                        let video_playlist = segmenter.generate_m3u8_playlist(PlaylistType::Video);
-                        let audio_playlist = segmenter.generate_m3u8_playlist(PlaylistType::Audio);
-                        let master_playlist = segmenter.generate_master_playlist();
-
-                        // Upload playlists
-                        if let Err(e) = uploader.update_playlist(video_playlist, S3ContentType::VideoPlaylist).await {
-                            log::error!("Failed to update video playlist: {}", e);
-                        }
-                        if let Err(e) = uploader.update_playlist(audio_playlist, S3ContentType::AudioPlaylist).await {
-                            log::error!("Failed to update audio playlist: {}", e);
-                        }
-                        if let Err(e) = uploader.update_playlist(master_playlist, S3ContentType::MasterPlaylist).await {
-                            log::error!("Failed to update master playlist: {}", e);
-                        }
+                    if let Some(segmenter) = &hls_segmenter {
+                        destinations.put_playlists(&events, segmenter).await;
                     }
                 }
-            });
+            }));
         }
 
         Ok(())
@@ -365,72 +528,186 @@ impl CapRecordingPipeline {
         drop(is_recording);
 
         log::info!("Stopping recording session {}", self.session_id);
+        self.events.emit(PipelineEvent::StatusChange(RecordingStatus::Stopping));
 
-        // Stop audio capture
-        if let Some(audio_processor) = &mut self.audio_processor {
-            audio_processor.stop().await?;
+        // A capture that fails to stop must not leave the tasks and upload workers running
+        self.stop_capture().await;
+
+        // Capture has closed its channels: the tracks encode what is left, flush
+        // their encoders and hand the last segments to the segmenter
+        if let Some(handle) = self.playlist_task.take() {
+            handle.abort();
+        }
+        let deadline = tokio::time::Instant::now() + PROCESSING_DRAIN_TIMEOUT;
+        for mut handle in self.task_handles.drain(..) {
+            if tokio::time::timeout_at(deadline, &mut handle).await.is_err() {
+                log::warn!("Processing task still running after {:?}, aborting it", PROCESSING_DRAIN_TIMEOUT);
+                handle.abort();
+            }
         }
 
-        // Stop screen capture
-        if let Some(screen_capture) = &mut self.screen_capture {
-            screen_capture.stop_capture().await?;
+        // The flushed tail is in the segmenter now: write the playlists once more so
+        // the live and archive playlists cover it and end the stream
+        let destinations = std::mem::take(&mut self.destinations);
+        if let Some(segmenter) = &self.hls_segmenter {
+            segmenter.lock().unwrap().finish();
+            destinations.put_playlists(&self.events, segmenter).await;
         }
 
-        // Flush encoders
-        if let Some(audio_encoder) = &mut self.audio_encoder {
-            let _remaining_segments = audio_encoder.flush()?;
-            // Process remaining audio segments
+        // Give queued uploads a chance to finish before letting the sinks wrap up. Evicted
+        // objects are deleted right away, while the upload workers still run
+        destinations.drain(UPLOAD_DRAIN_TIMEOUT).await;
+        destinations.finish_cleanup(&self.events).await;
+        for handle in self.upload_workers.drain(..) {
+            handle.abort();
         }
+        destinations.finalize(&self.events).await;
+
+        // Freeze the final duration so later queries report the recorded length
+        let duration = self.elapsed_seconds();
+        self.stats.lock().unwrap().duration = duration;
 
-        if let Some(video_encoder) = &mut self.video_encoder {
-            let _remaining_segments = video_encoder.flush()?;
-            // Process remaining video segments
+        self.events.emit(PipelineEvent::StatusChange(RecordingStatus::Stopped));
+
+        log::info!("Recording session stopped: {}", self.session_id);
+        Ok(self.get_session())
+    }
+
+    /// Pause recording; captured media is dropped instead of encoded until it resumes
+    pub fn pause_recording(&mut self) -> CaptureResult<RecordingSession> {
+        if !self.is_recording() {
+            return Err(CaptureError::InvalidState("No recording in progress".to_string()));
         }
+        let mut paused = self.paused.lock().unwrap();
+        if *paused {
+            return Err(CaptureError::InvalidState("Recording is already paused".to_string()));
+        }
+        *paused = true;
+        drop(paused);
+        self.paused_at = Some(unix_millis());
+
+        log::info!("Paused recording session {}", self.session_id);
+        self.events.emit(PipelineEvent::StatusChange(RecordingStatus::Paused));
+        Ok(self.get_session())
+    }
+
+    /// Resume a paused recording; the next segment starts with a discontinuity
+    pub fn resume_recording(&mut self) -> CaptureResult<RecordingSession> {
+        if !self.is_recording() {
+            return Err(CaptureError::InvalidState("No recording in progress".to_string()));
+        }
+        let Some(paused_at) = self.paused_at else {
+            return Err(CaptureError::InvalidState("Recording is not paused".to_string()));
+        };
+
+        // Media time is the recorded time, which stops while paused
+        let media_time = self.elapsed_seconds();
+        let paused_ms = unix_millis().saturating_sub(paused_at);
+        self.paused_time += paused_ms;
+        self.paused_at = None;
+        if let Some(segmenter) = &self.hls_segmenter {
+            segmenter.lock().unwrap().add_discontinuity(media_time, paused_ms);
+        }
+        *self.paused.lock().unwrap() = false;
 
-        let session = RecordingSession {
+        log::info!("Resumed recording session {} after {} ms", self.session_id, paused_ms);
+        self.events.emit(PipelineEvent::StatusChange(RecordingStatus::Recording));
+        Ok(self.get_session())
+    }
+
+    /// Snapshot of the current session state
+    pub fn get_session(&self) -> RecordingSession {
+        RecordingSession {
             id: self.session_id.clone(),
             user_id: self.config.user_id.clone(),
-            start_time: 0, // Would be stored from start
-            status: RecordingStatus::Stopped,
+            start_time: self.start_time.unwrap_or(0) as i64,
+            status: self.get_status(),
+            error: None,
             stream_urls: self.generate_stream_urls(),
-            stats: RecordingStats::default(), // Would be calculated from actual data
+            stats: self.calculate_stats(),
+        }
+    }
+
+    /// Calculate statistics, deriving duration and FPS while recording
+    fn calculate_stats(&self) -> RecordingStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+
+        if self.is_recording() {
+            stats.duration = self.elapsed_seconds();
+        }
+
+        if stats.duration > 0.0 {
+            stats.avg_fps = stats.video_frames as f64 / stats.duration;
+        }
+
+        stats.upload_queue_depth = self.destinations.queues.iter().map(UploadQueue::waiting).sum::<usize>() as u32;
+        if let Some(scheduler) = &self.destinations.scheduler {
+            stats.uploads_in_flight = scheduler.in_flight() as u32;
+        }
+
+        stats
+    }
+
+    /// Seconds recorded since recording started, not counting pauses
+    fn elapsed_seconds(&self) -> f64 {
+        let Some(start_time) = self.start_time else {
+            return 0.0;
         };
 
-        log::info!("Recording session stopped: {}", self.session_id);
-        Ok(session)
+        // While paused, the clock stops at the start of the pause
+        let now = self.paused_at.unwrap_or_else(unix_millis);
+        now.saturating_sub(start_time).saturating_sub(self.paused_time) as f64 / 1000.0
     }
 
     /// Generate stream URLs for the current session
     fn generate_stream_urls(&self) -> StreamUrls {
-        if let Some(bucket) = &self.config.s3_bucket {
-            let base_url = format!("https://{}.s3.amazonaws.com/{}/{}", 
-                                 bucket, self.config.user_id, self.session_id);
+        let has_archive = !self.config.encoding.hls.is_live_only();
+        let has_dash = self.config.encoding.hls.is_cmaf();
+        if let Some(upload_config) = self.s3_upload_config() {
+            let base_url = format!("{}/{}/{}", upload_config.bucket_url(), self.config.user_id, self.session_id);
             
             StreamUrls {
                 master: Some(format!("{}/stream.m3u8", base_url)),
                 video: Some(format!("{}/video/stream.m3u8", base_url)),
                 audio: Some(format!("{}/audio/stream.m3u8", base_url)),
                 combined: Some(format!("{}/combined-source/stream.m3u8", base_url)),
+                archive: has_archive.then(|| format!("{}/archive.m3u8", base_url)),
+                dash: has_dash.then(|| format!("{}/stream.mpd", base_url)),
             }
-        } else {
+        } else if let Some(sink) = self.local_output() {
+            let path = |content_type| Some(sink.path(&content_type, 0).to_string_lossy().into_owned());
+
             StreamUrls {
-                master: None,
-                video: None,
-                audio: None,
-                combined: None,
+                master: path(S3ContentType::MasterPlaylist),
+                video: path(S3ContentType::VideoPlaylist),
+                audio: path(S3ContentType::AudioPlaylist),
+                combined: path(S3ContentType::CombinedPlaylist),
+                archive: path(S3ContentType::MasterArchivePlaylist).filter(|_| has_archive),
+                dash: path(S3ContentType::DashManifest).filter(|_| has_dash),
             }
+        } else {
+            StreamUrls::default()
         }
     }
 
     /// Get current recording status
     pub fn get_status(&self) -> RecordingStatus {
         let is_recording = self.is_recording.lock().unwrap();
-        if *is_recording {
+        if *is_recording && *self.paused.lock().unwrap() {
+            RecordingStatus::Paused
+        } else if *is_recording {
             RecordingStatus::Recording
-        } else {
+        } else if self.start_time.is_some() {
             RecordingStatus::Stopped
+        } else {
+            RecordingStatus::Initializing
         }
     }
+
+    /// Check whether the pipeline is currently recording
+    pub fn is_recording(&self) -> bool {
+        *self.is_recording.lock().unwrap()
+    }
     
     /// Get session ID
     pub fn get_session_id(&self) -> &str {
@@ -441,6 +718,783 @@ impl CapRecordingPipeline {
     pub fn get_config(&self) -> &RecordingConfig {
         &self.config
     }
+
+    /// S3 uploader for publishing finished files such as the WebM recording,
+    /// with the same bucket and upload settings as the segments
+    pub async fn file_uploader(&self) -> CaptureResult<S3Uploader> {
+        let upload_config = self.s3_upload_config()
+            .ok_or_else(|| CaptureError::Config("Uploading files requires s3_bucket".to_string()))?;
+        S3Uploader::new(upload_config, self.config.user_id.clone(), self.session_id.clone()).await
+    }
+
+    /// Get the event emitter for subscribing to pipeline events
+    pub fn events(&self) -> EventEmitter {
+        self.events.clone()
+    }
+
+    /// Add a destination for segments and playlists, e.g. a custom ingest backend
+    pub fn add_sink(&mut self, sink: Arc<dyn SegmentSink>) -> CaptureResult<()> {
+        if self.is_recording() {
+            return Err(CaptureError::InvalidState("Sinks must be added before recording starts".to_string()));
+        }
+
+        self.sinks.push(sink);
+        Ok(())
+    }
+
+    /// Drop all sinks, releasing any JavaScript callbacks they hold
+    pub fn release_sinks(&mut self) -> CaptureResult<()> {
+        if self.is_recording() {
+            return Err(CaptureError::InvalidState("Sinks cannot be released while recording".to_string()));
+        }
+
+        self.sinks.clear();
+        Ok(())
+    }
+
+    /// Destinations for HLS segments and playlists, with an upload queue per
+    /// remote sink; WebM output has none
+    async fn create_destinations(&self) -> CaptureResult<SegmentDestinations> {
+        let mut destinations = SegmentDestinations::default();
+        if self.webm_output() {
+            return Ok(destinations);
+        }
+        destinations.manifest = Arc::new(Mutex::new(SessionManifest::new(self.config.user_id.clone(), self.session_id.clone())));
+
+        let upload = self.config.encoding.upload.as_ref();
+        let retry = upload.and_then(|upload| upload.retry.clone()).unwrap_or_default();
+        let spool_dir = upload.and_then(|upload| upload.spool_dir.as_deref()).map(Path::new);
+
+        // One scheduler for all remote sinks so they share the uplink
+        let scheduler = UploadScheduler::new(
+            upload.and_then(|upload| upload.max_concurrent_uploads).unwrap_or(4),
+            upload.and_then(|upload| upload.max_bytes_per_second).map(u64::from),
+        );
+
+        for sink in &self.sinks {
+            if sink.is_remote() {
+                destinations.queues.push(UploadQueue::new(sink.clone(), retry.clone(), scheduler.clone(), spool_dir, &self.session_id).await?);
+            } else {
+                destinations.local.push(sink.clone());
+            }
+        }
+        if !destinations.queues.is_empty() {
+            destinations.scheduler = Some(scheduler);
+        }
+        Ok(destinations)
+    }
+
+    /// Upload settings for `s3_bucket`, based on `encoding.upload` if present
+    fn s3_upload_config(&self) -> Option<UploadConfig> {
+        let bucket = self.config.s3_bucket.clone()?;
+        let mut upload_config = self.config.encoding.upload.clone().unwrap_or_else(|| {
+            create_cap_s3_uploader(bucket.clone(), self.config.user_id.clone(), self.session_id.clone())
+        });
+        upload_config.bucket = bucket;
+        Some(upload_config)
+    }
+
+    /// Filesystem sink for `OutputFormat.output_dir`, if set
+    fn local_output(&self) -> Option<FileSystemSink> {
+        let output_dir = self.config.output.as_ref()?.output_dir.clone()?;
+        Some(FileSystemSink::new(output_dir, self.config.user_id.clone(), self.session_id.clone()))
+    }
+
+    /// Whether recordings are muxed to WebM instead of HLS
+    fn webm_output(&self) -> bool {
+        self.config.output.as_ref()
+            .is_some_and(|output| matches!(output.video, VideoFormat::WebM))
+    }
+
+    /// Build the WebM muxer and, if an output directory is set, the file it appends to
+    async fn create_webm_output(&self) -> CaptureResult<SegmentOutput> {
+        let video = self.video_encoder.as_ref().map(|encoder| {
+            let (width, height) = encoder.resolution();
+            WebmVideoTrack { codec: encoder.config().codec, width, height }
+        });
+
+        let audio = match &self.audio_encoder {
+            Some(encoder) => {
+                let config = encoder.config();
+                Some(WebmAudioTrack {
+                    codec: config.codec,
+                    sample_rate: config.sample_rate,
+                    channels: config.channels,
+                    codec_private: encoder.opus_head().ok_or_else(|| {
+                        CaptureError::Config("WebM output requires Opus audio".to_string())
+                    })?,
+                })
+            }
+            None => None,
+        };
+
+        let muxer = WebmMuxer::new(video, audio)?;
+
+        let output_dir = self.config.output.as_ref().and_then(|output| output.output_dir.as_ref());
+        let file = match output_dir {
+            Some(dir) => {
+                tokio::fs::create_dir_all(dir).await?;
+                let path = std::path::Path::new(dir).join(format!("{}.webm", self.session_id));
+                log::info!("Writing WebM recording to {}", path.display());
+                Some(tokio::fs::File::create(path).await?)
+            }
+            None => None,
+        };
+
+        Ok(SegmentOutput::WebM { muxer, file })
+    }
+}
+
+/// Encoded media handed to the segment assembly task
+enum EncodedMedia {
+    Audio(EncodedAudioSegment),
+    Video(EncodedVideoSegment),
+}
+
+/// Encoding and delivery of the captured audio, run as a processing task
+struct AudioTrack {
+    encoder: AudioEncoder,
+    cmaf_muxer: Option<CmafAudioMuxer>,
+    /// LL-HLS parts get their own packager so their fragments are numbered by part
+    part_muxer: Option<CmafAudioMuxer>,
+    /// Segmenter listing the parts, with LL-HLS
+    hls_segmenter: Option<Arc<Mutex<HLSSegmenter>>>,
+    destinations: SegmentDestinations,
+    media_tx: Option<mpsc::UnboundedSender<EncodedMedia>>,
+    enable_transcription: bool,
+    stats: Arc<Mutex<RecordingStats>>,
+    events: EventEmitter,
+}
+
+impl AudioTrack {
+    /// Encode captured audio until capture stops, then flush the encoder's last segment
+    async fn run(mut self, mut audio_rx: mpsc::UnboundedReceiver<AudioSegment>, paused: Arc<Mutex<bool>>) {
+        while let Some(audio_segment) = audio_rx.recv().await {
+            // Audio captured while paused is dropped, so its timestamps skip the pause
+            if *paused.lock().unwrap() {
+                continue;
+            }
+
+            // Encode to AAC/Opus
+            let encode_result = self.encoder.process_audio(&audio_segment.data);
+            self.events.emit(PipelineEvent::AudioSegment(audio_segment));
+
+            match encode_result {
+                Ok(encoded_segments) => self.deliver(encoded_segments).await,
+                Err(e) => {
+                    log::error!("Audio encoding error: {}", e);
+                    self.events.emit(PipelineEvent::Error(format!("Audio encoding error: {}", e)));
+                }
+            }
+        }
+
+        match self.encoder.flush() {
+            Ok(encoded_segments) => self.deliver(encoded_segments).await,
+            Err(e) => {
+                log::error!("Failed to flush audio encoder: {}", e);
+                self.events.emit(PipelineEvent::Error(format!("Failed to flush audio encoder: {}", e)));
+            }
+        }
+    }
+
+    /// Store new parts and segments and pass the segments on for assembly
+    async fn deliver(&mut self, encoded_segments: Vec<EncodedAudioSegment>) {
+        if let (Some(muxer), Some(segmenter)) = (&mut self.part_muxer, &self.hls_segmenter) {
+            for part in self.encoder.take_parts() {
+                let hls_part = HLSPart {
+                    segment: part.segment,
+                    index: part.index,
+                    number: part.media.sequence,
+                    duration: part.media.duration,
+                    independent: true,
+                };
+                let fragment = muxer.mux_segment(&part.media);
+                self.destinations.put_part(&self.events, segmenter, PlaylistType::Audio, fragment, hls_part).await;
+            }
+        }
+
+        for encoded_segment in encoded_segments {
+            self.stats.lock().unwrap().audio_segments += 1;
+            self.events.emit(PipelineEvent::EncodedAudio(encoded_segment.clone()));
+
+            // Write to disk and/or S3
+            if !self.destinations.is_empty() {
+                if let Some(muxer) = &mut self.cmaf_muxer {
+                    let fragment = muxer.mux_segment(&encoded_segment);
+                    self.destinations.put_fragment(&self.events, fragment, S3ContentType::AudioInit, S3ContentType::AudioFragment).await;
+                } else {
+                    let data = encoded_segment.data.clone();
+                    self.destinations.put_segment(&self.events, S3ContentType::AudioSegment, encoded_segment.sequence, data).await;
+                }
+            }
+
+            // Process for transcription if enabled
+            if self.enable_transcription {
+                // In production, send to transcription service
+                log::debug!("Audio segment ready for transcription");
+            }
+
+            if let Some(media_tx) = &self.media_tx {
+                let _ = media_tx.send(EncodedMedia::Audio(encoded_segment));
+            }
+        }
+    }
+}
+
+/// Encoding and delivery of the captured frames, run as a processing task
+struct VideoTrack {
+    encoder: VideoEncoder,
+    /// Packages video-only TS segments; one muxer keeps the continuity counters running
+    ts_muxer: TsMuxer,
+    cmaf_muxer: Option<CmafVideoMuxer>,
+    part_muxer: Option<CmafVideoMuxer>,
+    /// Segmenter listing the parts, with LL-HLS
+    hls_segmenter: Option<Arc<Mutex<HLSSegmenter>>>,
+    destinations: SegmentDestinations,
+    media_tx: Option<mpsc::UnboundedSender<EncodedMedia>>,
+    stats: Arc<Mutex<RecordingStats>>,
+    events: EventEmitter,
+}
+
+impl VideoTrack {
+    /// Encode captured frames until capture stops, then flush the encoder's last segment
+    async fn run(mut self, mut video_rx: mpsc::UnboundedReceiver<ScreenFrame>, paused: Arc<Mutex<bool>>) {
+        let mut was_paused = false;
+
+        while let Some(screen_frame) = video_rx.recv().await {
+            // Frames captured while paused are dropped and the timeline resumes after the last one
+            if *paused.lock().unwrap() {
+                was_paused = true;
+                continue;
+            }
+            if std::mem::take(&mut was_paused) {
+                self.encoder.resume();
+            }
+
+            // Encode frame
+            match self.encoder.process_frame(&screen_frame) {
+                Ok(encoded_segments) => self.deliver(encoded_segments).await,
+                Err(e) => {
+                    log::error!("Video encoding error: {}", e);
+                    self.events.emit(PipelineEvent::Error(format!("Video encoding error: {}", e)));
+                }
+            }
+        }
+
+        match self.encoder.flush() {
+            Ok(encoded_segments) => self.deliver(encoded_segments).await,
+            Err(e) => {
+                log::error!("Failed to flush video encoder: {}", e);
+                self.events.emit(PipelineEvent::Error(format!("Failed to flush video encoder: {}", e)));
+            }
+        }
+    }
+
+    /// Store new parts and segments and pass the segments on for assembly
+    async fn deliver(&mut self, encoded_segments: Vec<EncodedVideoSegment>) {
+        if let (Some(muxer), Some(segmenter)) = (&mut self.part_muxer, &self.hls_segmenter) {
+            for part in self.encoder.take_parts() {
+                let hls_part = HLSPart {
+                    segment: part.segment,
+                    index: part.index,
+                    number: part.media.sequence,
+                    duration: part.media.duration,
+                    independent: part.independent,
+                };
+                let fragment = muxer.mux_segment(&part.media);
+                self.destinations.put_part(&self.events, segmenter, PlaylistType::Video, fragment, hls_part).await;
+            }
+        }
+
+        for encoded_segment in encoded_segments {
+            self.stats.lock().unwrap().video_frames += encoded_segment.frame_count;
+            self.events.emit(PipelineEvent::EncodedVideo(encoded_segment.clone()));
+
+            // Write to disk and/or S3
+            if !self.destinations.is_empty() {
+                if let Some(muxer) = &mut self.cmaf_muxer {
+                    let fragment = muxer.mux_segment(&encoded_segment);
+                    self.destinations.put_fragment(&self.events, fragment, S3ContentType::VideoInit, S3ContentType::VideoFragment).await;
+                } else {
+                    match self.ts_muxer.mux_segment(Some(&encoded_segment), None) {
+                        Ok(data) => {
+                            self.destinations.put_segment(&self.events, S3ContentType::VideoSegment, encoded_segment.sequence, data).await;
+                        }
+                        Err(e) => {
+                            log::error!("Failed to mux video segment {}: {}", encoded_segment.sequence, e);
+                            self.events.emit(PipelineEvent::Error(format!("Failed to mux video segment {}: {}", encoded_segment.sequence, e)));
+                        }
+                    }
+                }
+            }
+
+            if let Some(media_tx) = &self.media_tx {
+                let _ = media_tx.send(EncodedMedia::Video(encoded_segment));
+            }
+        }
+    }
+}
+
+/// Destination of paired audio/video segments
+enum SegmentOutput {
+    /// Feed the shared HLS segmenter, storing combined MPEG-TS segments
+    Hls {
+        segmenter: Arc<Mutex<HLSSegmenter>>,
+        muxer: TsMuxer,
+        destinations: SegmentDestinations,
+    },
+    /// Mux into WebM clusters, optionally appended to a local file
+    WebM {
+        muxer: WebmMuxer,
+        file: Option<tokio::fs::File>,
+    },
+}
+
+impl SegmentOutput {
+    /// Flush the WebM file, so it is complete once the task has ended
+    async fn finish(&mut self, events: &EventEmitter) {
+        if let SegmentOutput::WebM { file: Some(file), .. } = self {
+            if let Err(e) = file.flush().await {
+                log::error!("Failed to flush WebM recording: {}", e);
+                events.emit(PipelineEvent::Error(format!("Failed to flush WebM recording: {}", e)));
+            }
+        }
+    }
+
+    async fn write(
+        &mut self,
+        sequence: u32,
+        audio_segment: Option<EncodedAudioSegment>,
+        video_segment: Option<EncodedVideoSegment>,
+        events: &EventEmitter,
+    ) {
+        match self {
+            SegmentOutput::Hls { segmenter, muxer, destinations } => {
+                // Combined segments are MPEG-TS only, and need both tracks
+                let combined = !destinations.is_empty() && !segmenter.lock().unwrap().config().is_cmaf();
+                if let (Some(video), Some(audio), true) = (&video_segment, &audio_segment, combined) {
+                    match muxer.mux_segment(Some(video), Some(audio)) {
+                        Ok(ts_data) => {
+                            destinations.put_segment(events, S3ContentType::CombinedSegment, sequence, ts_data).await;
+                        }
+                        Err(e) => {
+                            log::error!("Failed to mux combined segment {}: {}", sequence, e);
+                            events.emit(PipelineEvent::Error(format!("Failed to mux combined segment {}: {}", sequence, e)));
+                        }
+                    }
+                }
+
+                let (result, evicted, grace) = {
+                    let mut segmenter = segmenter.lock().unwrap();
+                    let result = segmenter.create_hls_segment(audio_segment, video_segment);
+                    let mut evicted: Vec<_> = segmenter.take_evicted().iter()
+                        .flat_map(|segment| segmenter.segment_objects(segment))
+                        .collect();
+                    evicted.extend(segmenter.take_expired_parts());
+                    (result, evicted, segmenter.config().cleanup_grace())
+                };
+                match result {
+                    Ok(hls_segment) => events.emit(PipelineEvent::HlsSegment(hls_segment)),
+                    Err(e) => {
+                        log::error!("Failed to create HLS segment {}: {}", sequence, e);
+                        events.emit(PipelineEvent::Error(format!("Failed to create HLS segment {}: {}", sequence, e)));
+                    }
+                }
+
+                if !evicted.is_empty() {
+                    destinations.schedule_cleanup(events, evicted, grace);
+                }
+            }
+            SegmentOutput::WebM { muxer, file } => {
+                let chunk = match muxer.write_segment(sequence, video_segment.as_ref(), audio_segment.as_ref()) {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        log::error!("Failed to mux WebM segment {}: {}", sequence, e);
+                        events.emit(PipelineEvent::Error(format!("Failed to mux WebM segment {}: {}", sequence, e)));
+                        return;
+                    }
+                };
+
+                if let Some(file) = file {
+                    if let Err(e) = file.write_all(&chunk.data).await {
+                        log::error!("Failed to write WebM segment {}: {}", sequence, e);
+                        events.emit(PipelineEvent::Error(format!("Failed to write WebM segment {}: {}", sequence, e)));
+                    }
+                }
+
+                events.emit(PipelineEvent::WebmChunk(chunk));
+            }
+        }
+    }
+}
+
+/// Pair encoded audio and video segments by sequence number and hand them to the output
+async fn assemble_segments(
+    mut media_rx: mpsc::UnboundedReceiver<EncodedMedia>,
+    mut output: SegmentOutput,
+    expect_audio: bool,
+    expect_video: bool,
+    events: EventEmitter,
+) {
+    let mut pending_audio: BTreeMap<u32, EncodedAudioSegment> = BTreeMap::new();
+    let mut pending_video: BTreeMap<u32, EncodedVideoSegment> = BTreeMap::new();
+
+    while let Some(media) = media_rx.recv().await {
+        match media {
+            EncodedMedia::Audio(segment) => { pending_audio.insert(segment.sequence, segment); }
+            EncodedMedia::Video(segment) => { pending_video.insert(segment.sequence, segment); }
+        }
+
+        // Emit segments in order once every expected stream has delivered them
+        loop {
+            let next = if expect_audio { pending_audio.keys().next() } else { pending_video.keys().next() };
+            let Some(&sequence) = next else {
+                break;
+            };
+            if (expect_audio && !pending_audio.contains_key(&sequence))
+                || (expect_video && !pending_video.contains_key(&sequence))
+            {
+                break;
+            }
+
+            let audio_segment = pending_audio.remove(&sequence);
+            let video_segment = pending_video.remove(&sequence);
+            output.write(sequence, audio_segment, video_segment, &events).await;
+        }
+    }
+
+    // Capture has stopped and the encoders are flushed: the tail of the recording
+    // may be missing a stream, so write what is left rather than drop it
+    pending_audio.retain(|_, segment| segment.duration > 0.0);
+    let mut sequences: Vec<u32> = pending_audio.keys().chain(pending_video.keys()).copied().collect();
+    sequences.sort_unstable();
+    sequences.dedup();
+    for sequence in sequences {
+        let audio_segment = pending_audio.remove(&sequence);
+        let video_segment = pending_video.remove(&sequence);
+        output.write(sequence, audio_segment, video_segment, &events).await;
+    }
+    output.finish(&events).await;
+}
+
+/// Where packaged segments and playlists are delivered
+///
+/// Local sinks are written directly. Remote sinks are fed through an
+/// `UploadQueue`, so a playlist only reaches them after its segments.
+#[derive(Clone, Default)]
+struct SegmentDestinations {
+    local: Vec<Arc<dyn SegmentSink>>,
+    queues: Vec<UploadQueue>,
+    scheduler: Option<Arc<UploadScheduler>>,
+    /// Digests of everything stored, published as `manifest.json` with the playlists
+    manifest: Arc<Mutex<SessionManifest>>,
+    /// Scheduled deletions of evicted objects and the objects they delete
+    cleanups: Arc<Mutex<Vec<(JoinHandle<()>, Vec<(S3ContentType, u32)>)>>>,
+}
+
+impl SegmentDestinations {
+    fn is_empty(&self) -> bool {
+        self.local.is_empty() && self.queues.is_empty()
+    }
+
+    /// Store one packaged segment in every sink
+    async fn put_segment(&self, events: &EventEmitter, content_type: S3ContentType, sequence: u32, data: Vec<u8>) {
+        self.record_digest(&content_type, sequence, &data);
+        for sink in &self.local {
+            let result = sink.put_segment(content_type.clone(), sequence, data.clone()).await;
+            report_local_write(events, sink.as_ref(), result);
+        }
+        for queue in &self.queues {
+            let key = queue.sink().object_key(&content_type, sequence);
+            queue.enqueue(key, content_type.clone(), data.clone()).await;
+        }
+    }
+
+    /// Store an fMP4 fragment, preceded by the track's init segment the first time
+    async fn put_fragment(
+        &self,
+        events: &EventEmitter,
+        fragment: CaptureResult<CmafFragment>,
+        init_type: S3ContentType,
+        fragment_type: S3ContentType,
+    ) {
+        let fragment = match fragment {
+            Ok(fragment) => fragment,
+            Err(e) => {
+                log::error!("Failed to package fMP4 fragment: {}", e);
+                events.emit(PipelineEvent::Error(format!("Failed to package fMP4 fragment: {}", e)));
+                return;
+            }
+        };
+
+        if let Some(init) = fragment.init {
+            self.put_segment(events, init_type, 0, init).await;
+        }
+        self.put_segment(events, fragment_type, fragment.sequence, fragment.data).await;
+    }
+
+    /// Regenerate the media and master playlists and store them
+    async fn put_playlists(&self, events: &EventEmitter, segmenter: &Mutex<HLSSegmenter>) {
+        if self.is_empty() {
+            return;
+        }
+
+        let playlists = {
+            let segmenter = segmenter.lock().unwrap();
+            let live = segmenter.live_playlist();
+            let mut playlists = vec![
+                (segmenter.render_playlist(&live, PlaylistType::Video), S3ContentType::VideoPlaylist),
+                (segmenter.render_playlist(&live, PlaylistType::Audio), S3ContentType::AudioPlaylist),
+            ];
+            // Combined segments are MPEG-TS only
+            let combined = !segmenter.config().is_cmaf();
+            if combined {
+                playlists.push((segmenter.render_playlist(&live, PlaylistType::Combined), S3ContentType::CombinedPlaylist));
+            }
+            playlists.push((segmenter.generate_master_playlist(), S3ContentType::MasterPlaylist));
+            // DASH players can fetch the same fMP4 fragments
+            if segmenter.config().is_cmaf() {
+                playlists.push((segmenter.generate_dash_manifest(), S3ContentType::DashManifest));
+            }
+
+            if segmenter.has_archive() {
+                let archive = segmenter.archive_playlist();
+                playlists.push((segmenter.render_playlist(&archive, PlaylistType::Video), S3ContentType::VideoArchivePlaylist));
+                playlists.push((segmenter.render_playlist(&archive, PlaylistType::Audio), S3ContentType::AudioArchivePlaylist));
+                if combined {
+                    playlists.push((segmenter.render_playlist(&archive, PlaylistType::Combined), S3ContentType::CombinedArchivePlaylist));
+                }
+                playlists.push((segmenter.generate_archive_master_playlist(), S3ContentType::MasterArchivePlaylist));
+            }
+            playlists
+        };
+        self.store_playlists(events, playlists).await;
+
+        // The manifest goes last so it covers the playlists just written
+        let manifest = match self.manifest.lock().unwrap().to_json() {
+            Ok(manifest) => manifest.into_bytes(),
+            Err(e) => {
+                log::error!("{}", e);
+                return;
+            }
+        };
+        for sink in &self.local {
+            let key = sink.object_key(&S3ContentType::Manifest, 0);
+            let result = sink.put_object(key, S3ContentType::Manifest, manifest.clone()).await;
+            report_local_write(events, sink.as_ref(), result);
+        }
+        for queue in &self.queues {
+            let key = queue.sink().object_key(&S3ContentType::Manifest, 0);
+            queue.enqueue(key, S3ContentType::Manifest, manifest.clone()).await;
+        }
+    }
+
+    /// Take a segment a sink rejected out of the playlists and the manifest, then rewrite the playlists
+    ///
+    /// The queues hold back playlists queued after the segment, which may list
+    /// it, until these newer copies replace them.
+    fn unlist_segment(&self, events: &EventEmitter, segmenter: &Arc<Mutex<HLSSegmenter>>, content_type: &S3ContentType, key: &str) {
+        let Some(sequence) = sequence_from_key(key) else {
+            return;
+        };
+        segmenter.lock().unwrap().remove_segment(sequence);
+        {
+            let mut manifest = self.manifest.lock().unwrap();
+            let key = object_key(&manifest.user_id, &manifest.session_id, content_type, sequence);
+            manifest.remove(&key);
+        }
+
+        let (destinations, events, segmenter) = (self.clone(), events.clone(), segmenter.clone());
+        tokio::spawn(async move {
+            destinations.put_playlists(&events, &segmenter).await;
+        });
+    }
+
+    /// Store an LL-HLS part and rewrite the live media playlists to list it
+    async fn put_part(
+        &self,
+        events: &EventEmitter,
+        segmenter: &Mutex<HLSSegmenter>,
+        playlist_type: PlaylistType,
+        fragment: CaptureResult<CmafFragment>,
+        part: HLSPart,
+    ) {
+        let (init_type, part_type) = match playlist_type {
+            PlaylistType::Video => (S3ContentType::VideoInit, S3ContentType::VideoPart),
+            _ => (S3ContentType::AudioInit, S3ContentType::AudioPart),
+        };
+        let packaged = fragment.is_ok();
+        self.put_fragment(events, fragment, init_type, part_type).await;
+        if !packaged {
+            return;
+        }
+
+        let playlists = {
+            let mut segmenter = segmenter.lock().unwrap();
+            segmenter.add_part(playlist_type, part);
+            let live = segmenter.live_playlist();
+            vec![
+                (segmenter.render_playlist(&live, PlaylistType::Video), S3ContentType::VideoPlaylist),
+                (segmenter.render_playlist(&live, PlaylistType::Audio), S3ContentType::AudioPlaylist),
+            ]
+        };
+        self.store_playlists(events, playlists).await;
+    }
+
+    /// Store rendered playlists in every sink
+    async fn store_playlists(&self, events: &EventEmitter, playlists: Vec<(String, S3ContentType)>) {
+        for (playlist, content_type) in playlists {
+            self.record_digest(&content_type, 0, playlist.as_bytes());
+            for sink in &self.local {
+                let result = sink.put_playlist(content_type.clone(), playlist.clone()).await;
+                report_local_write(events, sink.as_ref(), result);
+            }
+            for queue in &self.queues {
+                let key = queue.sink().object_key(&content_type, 0);
+                queue.enqueue(key, content_type.clone(), playlist.clone().into_bytes()).await;
+            }
+        }
+    }
+
+    /// List an object's CRC32C and SHA-256 in the session manifest
+    fn record_digest(&self, content_type: &S3ContentType, sequence: u32, data: &[u8]) {
+        let mut manifest = self.manifest.lock().unwrap();
+        let key = object_key(&manifest.user_id, &manifest.session_id, content_type, sequence);
+        manifest.record(&key, content_type, data);
+    }
+
+    /// Delete objects that left the live window from every sink once `grace` has passed
+    ///
+    /// The grace period lets players that loaded an older playlist finish fetching them.
+    fn schedule_cleanup(&self, events: &EventEmitter, objects: Vec<(S3ContentType, u32)>, grace: Duration) {
+        let (destinations, events) = (self.clone(), events.clone());
+        let deleted = objects.clone();
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            destinations.delete_objects(&events, deleted).await;
+        });
+
+        let mut cleanups = self.cleanups.lock().unwrap();
+        cleanups.retain(|(handle, _)| !handle.is_finished());
+        cleanups.push((handle, objects));
+    }
+
+    /// Delete objects from every sink and from the session manifest
+    async fn delete_objects(&self, events: &EventEmitter, objects: Vec<(S3ContentType, u32)>) {
+        for (content_type, sequence) in objects {
+            {
+                let mut manifest = self.manifest.lock().unwrap();
+                let key = object_key(&manifest.user_id, &manifest.session_id, &content_type, sequence);
+                manifest.remove(&key);
+            }
+            for sink in &self.local {
+                if let Err(e) = sink.delete(content_type.clone(), sequence).await {
+                    report_cleanup_error(events, sink.as_ref(), e);
+                }
+            }
+            for queue in &self.queues {
+                if let Err(e) = queue.delete(content_type.clone(), sequence).await {
+                    report_cleanup_error(events, queue.sink().as_ref(), e);
+                }
+            }
+        }
+    }
+
+    /// Run the scheduled deletions now instead of after their grace period
+    ///
+    /// Deleting is idempotent, so a deletion interrupted midway simply runs again.
+    async fn finish_cleanup(&self, events: &EventEmitter) {
+        let cleanups = std::mem::take(&mut *self.cleanups.lock().unwrap());
+        for (handle, objects) in cleanups {
+            if handle.is_finished() {
+                continue;
+            }
+            handle.abort();
+            self.delete_objects(events, objects).await;
+        }
+    }
+
+    /// Cancel the scheduled deletions
+    fn abort_cleanup(&self) {
+        for (handle, _) in self.cleanups.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+
+    /// Wait for the upload queues to empty
+    async fn drain(&self, timeout: Duration) {
+        for queue in &self.queues {
+            if !queue.drain(timeout).await {
+                log::warn!(
+                    "{} uploads to {} sink still pending after {:?}",
+                    queue.pending(), queue.sink().name(), timeout
+                );
+            }
+        }
+    }
+
+    /// Let every sink finish up after the last playlists were written
+    async fn finalize(&self, events: &EventEmitter) {
+        let sinks = self.local.iter().chain(self.queues.iter().map(UploadQueue::sink));
+        for sink in sinks {
+            if let Err(e) = sink.finalize().await {
+                log::error!("Failed to finalize {} sink: {}", sink.name(), e);
+                events.emit(PipelineEvent::Error(format!("Failed to finalize {} sink: {}", sink.name(), e)));
+            }
+        }
+    }
+}
+
+/// Current wall-clock time in Unix milliseconds
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Surface a failed write to a local sink
+fn report_local_write(events: &EventEmitter, sink: &dyn SegmentSink, result: CaptureResult<String>) {
+    if let Err(e) = result {
+        log::error!("Failed to write to {} sink: {}", sink.name(), e);
+        events.emit(PipelineEvent::Error(format!("Failed to write to {} sink: {}", sink.name(), e)));
+    }
+}
+
+/// Surface a failed deletion of an evicted segment
+fn report_cleanup_error(events: &EventEmitter, sink: &dyn SegmentSink, error: CaptureError) {
+    log::warn!("Failed to delete evicted segment from {} sink: {}", sink.name(), error);
+    events.emit(PipelineEvent::Error(format!("Failed to delete evicted segment from {} sink: {}", sink.name(), error)));
+}
+
+/// Record the outcome of an upload in the stats and notify listeners
+fn report_upload(
+    events: &EventEmitter,
+    stats: &Mutex<RecordingStats>,
+    key: String,
+    content_type: S3ContentType,
+    bytes: u64,
+    result: CaptureResult<String>,
+) {
+    let error = match result {
+        Ok(_) => {
+            stats.lock().unwrap().bytes_uploaded += bytes as i64;
+            None
+        }
+        Err(e) => {
+            log::error!("Failed to upload {}: {}", key, e);
+            events.emit(PipelineEvent::Error(e.to_string()));
+            Some(e.to_string())
+        }
+    };
+
+    events.emit(PipelineEvent::Upload(UploadResult {
+        key,
+        content_type: content_type.mime_type().to_string(),
+        bytes,
+        success: error.is_none(),
+        error,
+    }));
 }
 
 // Manual Send + Sync implementation for cross-thread compatibility
@@ -456,6 +1510,8 @@ impl Default for RecordingStats {
             audio_segments: 0,
             bytes_uploaded: 0,
             avg_fps: 0.0,
+            upload_queue_depth: 0,
+            uploads_in_flight: 0,
         }
     }
 }
@@ -467,6 +1523,66 @@ impl Default for StreamUrls {
             video: None,
             audio: None,
             combined: None,
+            archive: None,
+            dash: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ScreenError;
+
+    #[tokio::test]
+    async fn test_failed_capture_stop_still_tears_down() {
+        let config = RecordingConfig {
+            audio: AudioCaptureConfig::default(),
+            screen: ScreenCaptureConfig::default(),
+            encoding: EncodingConfig::default(),
+            user_id: "user".to_string(),
+            s3_bucket: None,
+            enable_transcription: false,
+            enable_streaming: false,
+            output: None,
+            sinks: None,
+        };
+        let mut pipeline = CapRecordingPipeline::new(config.clone()).unwrap();
+        let mut screen_capture = ScreenCapture::new(config.screen).unwrap();
+        screen_capture.fail_next_stop(ScreenError::CaptureError("display disconnected".to_string()));
+        pipeline.screen_capture = Some(screen_capture);
+
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        pipeline.events.subscribe(Arc::new({
+            let errors = errors.clone();
+            move |event: &PipelineEvent| {
+                if let PipelineEvent::Error(message) = event {
+                    errors.lock().unwrap().push(message.clone());
+                }
+            }
+        }));
+
+        // A recording whose playlist task and upload worker only stop when aborted
+        *pipeline.is_recording.lock().unwrap() = true;
+        pipeline.start_time = Some(unix_millis());
+        let playlist_task = tokio::spawn(std::future::pending::<()>());
+        let upload_worker = tokio::spawn(std::future::pending::<()>());
+        let aborted = [playlist_task.abort_handle(), upload_worker.abort_handle()];
+        pipeline.playlist_task = Some(playlist_task);
+        pipeline.upload_workers.push(upload_worker);
+
+        let session = pipeline.stop_recording().await.unwrap();
+        assert_eq!(session.status, RecordingStatus::Stopped);
+        assert!(!pipeline.is_recording());
+        assert!(pipeline.playlist_task.is_none() && pipeline.upload_workers.is_empty());
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !aborted.iter().all(|handle| handle.is_finished()) {
+                tokio::task::yield_now().await;
+            }
+        }).await.unwrap();
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec!["Failed to stop screen capture: Screen capture error: Frame capture failed: display disconnected".to_string()]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{config::ScreenCaptureConfig, error::{CaptureError, CaptureResult, ScreenError}};
+use crate::{config::ScreenCaptureConfig, encoding::PixelFormat, error::{CaptureError, CaptureResult, ScreenError}};
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
@@ -44,12 +44,14 @@ pub struct Window {
 /// Screen frame data
 #[derive(Debug, Clone)]
 pub struct ScreenFrame {
-    /// Raw frame data (RGBA)
+    /// Raw packed frame data
     pub data: Vec<u8>,
     /// Frame width
     pub width: u32,
     /// Frame height
     pub height: u32,
+    /// Layout of `data` (RGBA or BGRA)
+    pub pixel_format: PixelFormat,
     /// Timestamp in milliseconds
     pub timestamp: u64,
     /// Frame number
@@ -63,6 +65,9 @@ pub struct ScreenCapture {
     is_running: Arc<Mutex<bool>>,
     frame_sender: Option<mpsc::UnboundedSender<ScreenFrame>>,
     frame_counter: Arc<Mutex<u64>>,
+    /// Error reported by the next stop, to exercise teardown after a failed stop
+    #[cfg(test)]
+    stop_error: Option<ScreenError>,
 }
 
 impl ScreenCapture {
@@ -88,25 +93,40 @@ impl ScreenCapture {
             is_running: Arc::new(Mutex::new(false)),
             frame_sender: None,
             frame_counter: Arc::new(Mutex::new(0)),
+            #[cfg(test)]
+            stop_error: None,
         })
     }
 
+    /// Make the next stop fail with `error`
+    #[cfg(test)]
+    pub(crate) fn fail_next_stop(&mut self, error: ScreenError) {
+        self.stop_error = Some(error);
+    }
+
     /// Start screen capture
     pub async fn start(&mut self) -> CaptureResult<mpsc::UnboundedReceiver<ScreenFrame>> {
-        let mut is_running = self.is_running.lock().unwrap();
-        if *is_running {
-            return Err(CaptureError::Screen(ScreenError::InitializationFailed(
-                "Screen capture is already running".to_string()
-            )));
+        {
+            let mut is_running = self.is_running.lock().unwrap();
+            if *is_running {
+                return Err(CaptureError::Screen(ScreenError::InitializationFailed(
+                    "Screen capture is already running".to_string()
+                )));
+            }
+            // Mark as running before the capture loop is spawned so it doesn't exit immediately
+            *is_running = true;
         }
 
         let (tx, rx) = mpsc::unbounded_channel();
         self.frame_sender = Some(tx.clone());
 
         // Start platform-specific capture
-        self.start_platform_capture(tx).await?;
+        if let Err(e) = self.start_platform_capture(tx).await {
+            *self.is_running.lock().unwrap() = false;
+            self.frame_sender = None;
+            return Err(e);
+        }
 
-        *is_running = true;
         log::info!("Screen capture started successfully");
 
         Ok(rx)
@@ -114,16 +134,24 @@ impl ScreenCapture {
 
     /// Stop screen capture
     pub async fn stop(&mut self) -> CaptureResult<()> {
-        let mut is_running = self.is_running.lock().unwrap();
-        if !*is_running {
-            return Ok(());
+        #[cfg(test)]
+        {
+            if let Some(error) = self.stop_error.take() {
+                return Err(CaptureError::Screen(error));
+            }
+        }
+        {
+            let mut is_running = self.is_running.lock().unwrap();
+            if !*is_running {
+                return Ok(());
+            }
+            *is_running = false;
         }
 
         // Stop capture
         self.stop_platform_capture().await?;
         
         self.frame_sender = None;
-        *is_running = false;
 
         log::info!("Screen capture stopped successfully");
         Ok(())
@@ -201,6 +229,7 @@ impl ScreenCapture {
                     data: vec![0; 1920 * 1080 * 4], // Placeholder RGBA data
                     width: 1920,
                     height: 1080,
+                    pixel_format: PixelFormat::RGBA,
                     timestamp: std::time::SystemTime::now()
                         .duration_since(std::time::UNIX_EPOCH)
                         .unwrap()
@@ -244,6 +273,7 @@ impl ScreenCapture {
                     data: vec![0; 1920 * 1080 * 4], // Placeholder RGBA data
                     width: 1920,
                     height: 1080,
+                    pixel_format: PixelFormat::RGBA,
                     timestamp: std::time::SystemTime::now()
                         .duration_since(std::time::UNIX_EPOCH)
                         .unwrap()
@@ -287,6 +317,7 @@ impl ScreenCapture {
                     data: vec![0; 1920 * 1080 * 4], // Placeholder RGBA data
                     width: 1920,
                     height: 1080,
+                    pixel_format: PixelFormat::RGBA,
                     timestamp: std::time::SystemTime::now()
                         .duration_since(std::time::UNIX_EPOCH)
                         .unwrap()
@@ -0,0 +1,256 @@
+//! Recording Session Manager
+//!
+//! Process-wide registry that owns live `CapRecordingPipeline` instances and
+//! streaming audio encoders so the napi layer can address them by session ID
+//! across calls
+
+use crate::{
+    encoding::{AudioEncoder, AudioEncodingConfig, AudioChannelLayout, EncodedAudioSegment, DEFAULT_SEGMENT_DURATION},
+    error::{CaptureError, CaptureResult},
+    events::EventEmitter,
+    recording::{CapRecordingPipeline, RecordingConfig, RecordingSession},
+};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Pipeline handle shared between the registry and in-flight calls
+pub type SharedPipeline = Arc<tokio::sync::Mutex<CapRecordingPipeline>>;
+
+lazy_static! {
+    static ref SESSION_MANAGER: SessionManager = SessionManager::new();
+}
+
+/// Get the process-wide session manager
+pub fn session_manager() -> &'static SessionManager {
+    &SESSION_MANAGER
+}
+
+/// Registered recording session
+struct SessionEntry {
+    pipeline: SharedPipeline,
+    /// Kept outside the pipeline lock so listeners can subscribe while it is busy
+    events: EventEmitter,
+}
+
+/// Registry of recording pipelines and streaming encoders keyed by session ID
+pub struct SessionManager {
+    pipelines: Mutex<HashMap<String, SessionEntry>>,
+    /// Each encoder has its own lock so encoding never blocks other sessions
+    audio_encoders: Mutex<HashMap<String, Arc<Mutex<AudioEncoder>>>>,
+}
+
+impl SessionManager {
+    /// Create an empty session manager
+    pub fn new() -> Self {
+        Self {
+            pipelines: Mutex::new(HashMap::new()),
+            audio_encoders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create, initialize and register a new pipeline, returning its session ID
+    pub async fn create_session(&self, config: RecordingConfig) -> CaptureResult<String> {
+        let mut pipeline = CapRecordingPipeline::new(config)?;
+        pipeline.initialize().await?;
+        Ok(self.register(pipeline))
+    }
+
+    /// Register an already initialized pipeline
+    pub fn register(&self, pipeline: CapRecordingPipeline) -> String {
+        let session_id = pipeline.get_session_id().to_string();
+        let entry = SessionEntry {
+            events: pipeline.events(),
+            pipeline: Arc::new(tokio::sync::Mutex::new(pipeline)),
+        };
+        self.pipelines.lock().unwrap().insert(session_id.clone(), entry);
+
+        log::info!("Registered recording session {}", session_id);
+        session_id
+    }
+
+    /// Look up a registered pipeline
+    pub fn get(&self, session_id: &str) -> CaptureResult<SharedPipeline> {
+        self.pipelines.lock().unwrap()
+            .get(session_id)
+            .map(|entry| entry.pipeline.clone())
+            .ok_or_else(|| CaptureError::Session(format!("Unknown session: {}", session_id)))
+    }
+
+    /// Get the event emitter of a registered session
+    pub fn events(&self, session_id: &str) -> CaptureResult<EventEmitter> {
+        self.pipelines.lock().unwrap()
+            .get(session_id)
+            .map(|entry| entry.events.clone())
+            .ok_or_else(|| CaptureError::Session(format!("Unknown session: {}", session_id)))
+    }
+
+    /// Start recording for a registered session
+    pub async fn start_session(&self, session_id: &str) -> CaptureResult<RecordingSession> {
+        let pipeline = self.get(session_id)?;
+        let mut pipeline = pipeline.lock().await;
+        pipeline.start_recording().await
+    }
+
+    /// Stop recording for a registered session
+    pub async fn stop_session(&self, session_id: &str) -> CaptureResult<RecordingSession> {
+        let pipeline = self.get(session_id)?;
+        let mut pipeline = pipeline.lock().await;
+        pipeline.stop_recording().await
+    }
+
+    /// Get the current state of a registered session
+    pub async fn get_session(&self, session_id: &str) -> CaptureResult<RecordingSession> {
+        let pipeline = self.get(session_id)?;
+        let pipeline = pipeline.lock().await;
+        Ok(pipeline.get_session())
+    }
+
+    /// Stop (if needed) and remove a session and its streaming audio encoder from the registry
+    pub async fn destroy_session(&self, session_id: &str) -> CaptureResult<()> {
+        // Audio that was never flushed has no one left to receive it
+        if self.audio_encoders.lock().unwrap().remove(session_id).is_some() {
+            log::info!("Dropped streaming audio encoder of session {}", session_id);
+        }
+
+        let entry = self.pipelines.lock().unwrap()
+            .remove(session_id)
+            .ok_or_else(|| CaptureError::Session(format!("Unknown session: {}", session_id)))?;
+        // Listeners and sinks may hold JavaScript callbacks; dropping them releases those
+        entry.events.clear();
+
+        let mut pipeline = entry.pipeline.lock().await;
+        if pipeline.is_recording() {
+            pipeline.stop_recording().await?;
+        }
+        pipeline.release_sinks()?;
+
+        log::info!("Destroyed recording session {}", session_id);
+        Ok(())
+    }
+
+    /// IDs of all registered sessions
+    pub fn session_ids(&self) -> Vec<String> {
+        self.pipelines.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Feed interleaved PCM samples to the session's streaming audio encoder
+    ///
+    /// The encoder is created on first use and keeps its sample buffer, PTS
+    /// and sequence counter across calls until it is flushed.
+    pub fn process_audio_chunk(
+        &self,
+        session_id: &str,
+        samples: &[f32],
+        sample_rate: Option<u32>,
+        channels: Option<u16>,
+    ) -> CaptureResult<Vec<EncodedAudioSegment>> {
+        let existing = self.audio_encoders.lock().unwrap().get(session_id).cloned();
+        let encoder = match existing {
+            Some(encoder) => encoder,
+            None => {
+                let config = streaming_audio_config(sample_rate, channels)?;
+                let encoder = Arc::new(Mutex::new(AudioEncoder::new(config, DEFAULT_SEGMENT_DURATION)?));
+                // Another call may have created one meanwhile; everyone uses the registered encoder
+                let encoder = self.audio_encoders.lock().unwrap()
+                    .entry(session_id.to_string())
+                    .or_insert(encoder)
+                    .clone();
+                log::info!("Created streaming audio encoder for session {}", session_id);
+                encoder
+            }
+        };
+
+        // Only this session's encoder stays locked while encoding
+        let mut encoder = encoder.lock().unwrap();
+        let config = encoder.config();
+        if sample_rate.is_some_and(|rate| rate != config.sample_rate)
+            || channels.is_some_and(|count| count != config.channels)
+        {
+            return Err(CaptureError::Config(format!(
+                "Session {} encoder is already configured for {} Hz / {} channels",
+                session_id, config.sample_rate, config.channels
+            )));
+        }
+        encoder.process_audio(samples)
+    }
+
+    /// Flush and release the session's streaming audio encoder
+    pub fn flush_audio_encoder(&self, session_id: &str) -> CaptureResult<Vec<EncodedAudioSegment>> {
+        let encoder = self.audio_encoders.lock().unwrap()
+            .remove(session_id)
+            .ok_or_else(|| CaptureError::Session(format!("No audio encoder for session: {}", session_id)))?;
+
+        // Waits for a chunk that is still being encoded
+        let mut encoder = encoder.lock().unwrap();
+        encoder.flush()
+    }
+}
+
+/// Build the streaming encoder config, defaulting to Cap's 48kHz stereo AAC
+fn streaming_audio_config(sample_rate: Option<u32>, channels: Option<u16>) -> CaptureResult<AudioEncodingConfig> {
+    let defaults = AudioEncodingConfig::default();
+    let channels = channels.unwrap_or(defaults.channels);
+
+    let channel_layout = match channels {
+        1 => AudioChannelLayout::Mono,
+        2 => AudioChannelLayout::Stereo,
+        6 => AudioChannelLayout::Surround51,
+        _ => return Err(CaptureError::Config(format!("Unsupported channel count: {}", channels))),
+    };
+
+    Ok(AudioEncodingConfig {
+        sample_rate: sample_rate.unwrap_or(defaults.sample_rate),
+        channels,
+        channel_layout,
+        ..defaults
+    })
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AudioCaptureConfig, ScreenCaptureConfig};
+    use crate::encoding::EncodingConfig;
+
+    #[tokio::test]
+    async fn test_unknown_session_is_rejected() {
+        let manager = SessionManager::new();
+
+        assert!(matches!(manager.get("missing"), Err(CaptureError::Session(_))));
+        assert!(matches!(manager.start_session("missing").await, Err(CaptureError::Session(_))));
+        assert!(matches!(manager.destroy_session("missing").await, Err(CaptureError::Session(_))));
+    }
+
+    #[tokio::test]
+    async fn test_destroy_session_drops_audio_encoder() {
+        let manager = SessionManager::new();
+        let session_id = manager.register(CapRecordingPipeline::new(RecordingConfig {
+            audio: AudioCaptureConfig::default(),
+            screen: ScreenCaptureConfig::default(),
+            encoding: EncodingConfig::default(),
+            user_id: "user".to_string(),
+            s3_bucket: None,
+            enable_transcription: false,
+            enable_streaming: false,
+            output: None,
+            sinks: None,
+        }).unwrap());
+
+        assert!(manager.process_audio_chunk(&session_id, &[0.0; 960], None, None).unwrap().is_empty());
+        manager.destroy_session(&session_id).await.unwrap();
+        assert!(matches!(manager.flush_audio_encoder(&session_id), Err(CaptureError::Session(_))));
+    }
+
+    #[test]
+    fn test_flush_without_encoder_is_rejected() {
+        let manager = SessionManager::new();
+        assert!(matches!(manager.flush_audio_encoder("missing"), Err(CaptureError::Session(_))));
+    }
+}
@@ -86,8 +86,8 @@ async function testAudioFileOutput() {
                 video: {
                     codec: 'H264',
                     bitrate: 2000000,
-                    frame_rate: [30, 1],
-                    resolution: [1920, 1080],
+                    frame_rate: { numerator: 30, denominator: 1 },
+                    resolution: { width: 1920, height: 1080 },
                     pixel_format: 'YUV420P',
                     hardware_acceleration: true
                 },
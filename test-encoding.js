@@ -66,8 +66,8 @@ async function testCapEncodingPipeline() {
                 video: {
                     codec: "H264",
                     bitrate: 2000000, // 2Mbps
-                    frame_rate: [30, 1], // 30fps
-                    resolution: [1920, 1080],
+                    frame_rate: { numerator: 30, denominator: 1 }, // 30fps
+                    resolution: { width: 1920, height: 1080 },
                     pixel_format: "YUV420P",
                     hardware_acceleration: true
                 },